@@ -0,0 +1,382 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Serialize};
+
+use crate::keymap::Mode;
+
+// Every command the editor can carry out. The key map can produce these directly, so any of them can be rebound.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveWordLeft,
+    MoveWordRight,
+    // moves to the start of the next word, past the whitespace after the current one. This is what w does outside of
+    // insert mode, so that dw takes the whitespace with it.
+    MoveNextWord,
+    MoveLineStart,
+    MoveLineEnd,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    SelectWordLeft,
+    SelectWordRight,
+    // moves the cursor while selecting a rectangle of text, starting one if there isn't one.
+    SelectBlockLeft,
+    SelectBlockRight,
+    SelectBlockUp,
+    SelectBlockDown,
+    DeleteBackward,
+    DeleteForward,
+    DeleteWordBackward,
+    DeleteWordForward,
+    DeleteLine,
+    InsertNewline,
+    InsertTab,
+    InsertChar(char),
+    Copy,
+    Cut,
+    Paste,
+    PasteAfter,
+    Undo,
+    Redo,
+    PreviousBranch,
+    NextBranch,
+    UndoTree,
+    // opens the command line at the bottom of the screen.
+    CommandLine,
+    // opens the search prompt, which looks towards the end (or start) of the text as the search is typed.
+    SearchForward,
+    SearchBackward,
+    // moves to the next match of the last search, in the direction it was entered in or the other direction.
+    SearchNext,
+    SearchPrevious,
+    Save,
+    Quit,
+    SwitchMode(Mode),
+    // moves past the character under the cursor, then switches to insert mode.
+    Append,
+    // switches to insert mode at the left (or right) edge of the selected rectangle.
+    // Whatever's typed on the first line is copied onto the others when insert mode ends.
+    BlockInsert,
+    BlockAppend,
+    // waits for a motion, then acts on the text between where the cursor was and where the motion takes it.
+    Operator(Operator),
+    // drops the pending operator, the selection, and any extra cursors.
+    Cancel,
+    // adds a cursor on the line above the topmost cursor, or below the bottommost one.
+    AddCursorAbove,
+    AddCursorBelow,
+    // selects the word under the cursor, or adds a cursor at the next place the selected text shows up.
+    AddCursorAtNextMatch,
+    // puts a cursor on every line of the selection.
+    AddCursorsToLines,
+    // splits the focused pane into two, one above the other (or side by side).
+    SplitHorizontal,
+    SplitVertical,
+    ClosePane,
+    // moves the focus to the pane on one side of the focused one.
+    FocusLeft,
+    FocusRight,
+    FocusUp,
+    FocusDown,
+    // makes the focused pane a row taller or shorter, or a column wider or narrower.
+    GrowPane,
+    ShrinkPane,
+    WidenPane,
+    NarrowPane,
+    // opens a tab page showing the current buffer, or closes the current one.
+    NewTab,
+    CloseTab,
+    // shows the next or previous tab page.
+    NextTab,
+    PreviousTab,
+    // moves the current tab page one place to the left or right in the tab bar.
+    MoveTabLeft,
+    MoveTabRight,
+}
+impl Action {
+    // Whether this action only moves the cursor, so an operator can act on the text it moves over.
+    pub fn is_motion(self) -> bool {
+        matches!(
+            self,
+            Action::MoveLeft
+                | Action::MoveRight
+                | Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveWordLeft
+                | Action::MoveWordRight
+                | Action::MoveNextWord
+                | Action::MoveLineStart
+                | Action::MoveLineEnd
+        )
+    }
+    // Whether this action is carried out at every cursor when there's more than one.
+    pub fn applies_to_each_cursor(self) -> bool {
+        self.is_motion()
+            || matches!(
+                self,
+                Action::SelectLeft
+                    | Action::SelectRight
+                    | Action::SelectUp
+                    | Action::SelectDown
+                    | Action::SelectWordLeft
+                    | Action::SelectWordRight
+                    | Action::DeleteBackward
+                    | Action::DeleteForward
+                    | Action::DeleteWordBackward
+                    | Action::DeleteWordForward
+                    | Action::InsertNewline
+                    | Action::InsertTab
+                    | Action::InsertChar(_)
+                    | Action::Paste
+                    | Action::PasteAfter
+                    | Action::Append
+            )
+    }
+}
+// Something that can be done to a stretch of text.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Operator {
+    Delete,
+    Yank,
+    Indent,
+    Outdent,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+}
+impl Operator {
+    // The key that starts the operator, shown while it waits for a motion.
+    pub fn name(self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Yank => 'y',
+            Operator::Indent => '>',
+            Operator::Outdent => '<',
+            Operator::Lowercase => 'u',
+            Operator::Uppercase => 'U',
+            Operator::ToggleCase => '~',
+        }
+    }
+}
+// Something the key map can turn a key into: either another key, or an action.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Output {
+    Key(KeyEvent),
+    Action(Action),
+}
+// The motion a letter stands for outside of insert mode.
+fn motion(c: char) -> Option<Action> {
+    Some(match c {
+        'h' => Action::MoveLeft,
+        'j' => Action::MoveDown,
+        'k' => Action::MoveUp,
+        'l' => Action::MoveRight,
+        'w' => Action::MoveNextWord,
+        'b' => Action::MoveWordLeft,
+        '0' => Action::MoveLineStart,
+        '$' => Action::MoveLineEnd,
+        _ => return None,
+    })
+}
+// The action that a key carries out if the key map doesn't turn it into something else.
+pub fn default_action(k: KeyEvent, mode: Mode) -> Option<Action> {
+    let KeyEvent { code, modifiers } = k;
+    let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+    let shift = modifiers.contains(KeyModifiers::SHIFT);
+    let alt = modifiers.contains(KeyModifiers::ALT);
+    Some(match code {
+        KeyCode::Esc if mode == Mode::Insert => Action::SwitchMode(Mode::Command),
+        KeyCode::Esc if mode.is_visual() => Action::SwitchMode(Mode::Command),
+        KeyCode::Esc => Action::Cancel,
+        // in command mode, letters move the cursor and act on text instead of being typed.
+        KeyCode::Char(c) if mode == Mode::Command && !ctrl && !alt => match c {
+            'x' => Action::DeleteForward,
+            'd' => Action::Operator(Operator::Delete),
+            'y' => Action::Operator(Operator::Yank),
+            '>' => Action::Operator(Operator::Indent),
+            '<' => Action::Operator(Operator::Outdent),
+            'v' => Action::SwitchMode(Mode::Visual),
+            'V' => Action::SwitchMode(Mode::VisualLine),
+            'p' => Action::PasteAfter,
+            'P' => Action::Paste,
+            'u' => Action::Undo,
+            'i' => Action::SwitchMode(Mode::Insert),
+            'a' => Action::Append,
+            ':' => Action::CommandLine,
+            '/' => Action::SearchForward,
+            '?' => Action::SearchBackward,
+            'n' => Action::SearchNext,
+            'N' => Action::SearchPrevious,
+            _ => return motion(c),
+        },
+        // in the visual modes, letters extend the selection or act on it.
+        KeyCode::Char(c) if mode.is_visual() && !ctrl && !alt => match c {
+            'd' | 'x' => Action::Operator(Operator::Delete),
+            'y' => Action::Operator(Operator::Yank),
+            '>' => Action::Operator(Operator::Indent),
+            '<' => Action::Operator(Operator::Outdent),
+            'u' => Action::Operator(Operator::Lowercase),
+            'U' => Action::Operator(Operator::Uppercase),
+            '~' => Action::Operator(Operator::ToggleCase),
+            // pressing the key for the current visual mode leaves it, while the other one switches to it.
+            'v' if mode == Mode::Visual => Action::SwitchMode(Mode::Command),
+            'v' => Action::SwitchMode(Mode::Visual),
+            'V' if mode == Mode::VisualLine => Action::SwitchMode(Mode::Command),
+            'V' => Action::SwitchMode(Mode::VisualLine),
+            'I' if mode == Mode::VisualBlock => Action::BlockInsert,
+            'A' if mode == Mode::VisualBlock => Action::BlockAppend,
+            ':' => Action::CommandLine,
+            _ => return motion(c),
+        },
+        // nothing is typed outside of insert mode, so these keys only move the cursor.
+        KeyCode::Backspace if mode != Mode::Insert => Action::MoveLeft,
+        KeyCode::Enter if mode != Mode::Insert => Action::MoveDown,
+        KeyCode::Tab if mode != Mode::Insert => return None,
+        KeyCode::F(3) if shift => Action::SearchPrevious,
+        KeyCode::F(3) => Action::SearchNext,
+        KeyCode::Home => Action::MoveLineStart,
+        KeyCode::End => Action::MoveLineEnd,
+        KeyCode::Backspace if ctrl => Action::DeleteWordBackward,
+        KeyCode::Backspace => Action::DeleteBackward,
+        KeyCode::Delete if ctrl => Action::DeleteWordForward,
+        KeyCode::Delete => Action::DeleteForward,
+        KeyCode::Enter => Action::InsertNewline,
+        KeyCode::Tab => Action::InsertTab,
+        KeyCode::PageUp if ctrl && shift => Action::MoveTabLeft,
+        KeyCode::PageDown if ctrl && shift => Action::MoveTabRight,
+        KeyCode::PageUp if ctrl => Action::PreviousTab,
+        KeyCode::PageDown if ctrl => Action::NextTab,
+        KeyCode::Up if ctrl && alt => Action::AddCursorAbove,
+        KeyCode::Down if ctrl && alt => Action::AddCursorBelow,
+        KeyCode::Left if alt && !shift => Action::FocusLeft,
+        KeyCode::Right if alt && !shift => Action::FocusRight,
+        KeyCode::Up if alt && !shift => Action::FocusUp,
+        KeyCode::Down if alt && !shift => Action::FocusDown,
+        KeyCode::Left if shift && alt => Action::SelectBlockLeft,
+        KeyCode::Right if shift && alt => Action::SelectBlockRight,
+        KeyCode::Up if shift && alt => Action::SelectBlockUp,
+        KeyCode::Down if shift && alt => Action::SelectBlockDown,
+        KeyCode::Left => match (shift, ctrl) {
+            (true, true) => Action::SelectWordLeft,
+            (true, false) => Action::SelectLeft,
+            (false, true) => Action::MoveWordLeft,
+            (false, false) => Action::MoveLeft,
+        },
+        KeyCode::Right => match (shift, ctrl) {
+            (true, true) => Action::SelectWordRight,
+            (true, false) => Action::SelectRight,
+            (false, true) => Action::MoveWordRight,
+            (false, false) => Action::MoveRight,
+        },
+        KeyCode::Up if shift => Action::SelectUp,
+        KeyCode::Up => Action::MoveUp,
+        KeyCode::Down if shift => Action::SelectDown,
+        KeyCode::Down => Action::MoveDown,
+        KeyCode::Char(c) if ctrl => match c.to_ascii_lowercase() {
+            'c' => Action::Copy,
+            'x' => Action::Cut,
+            // outside of insert mode, this works like it does in vim.
+            'v' if mode == Mode::VisualBlock => Action::SwitchMode(Mode::Command),
+            'v' if mode != Mode::Insert => Action::SwitchMode(Mode::VisualBlock),
+            'v' => Action::Paste,
+            'z' if shift => Action::Redo,
+            'z' => Action::Undo,
+            'y' => Action::Redo,
+            'u' => Action::UndoTree,
+            'd' => Action::DeleteLine,
+            's' => Action::Save,
+            'f' => Action::SearchForward,
+            'q' => Action::Quit,
+            _ => return None,
+        },
+        KeyCode::Char(c) if alt => match c.to_ascii_lowercase() {
+            'z' => Action::PreviousBranch,
+            'y' => Action::NextBranch,
+            'n' => Action::AddCursorAtNextMatch,
+            'i' => Action::AddCursorsToLines,
+            's' => Action::SplitHorizontal,
+            'v' => Action::SplitVertical,
+            'c' => Action::ClosePane,
+            '+' | '=' => Action::GrowPane,
+            '-' => Action::ShrinkPane,
+            '>' | '.' => Action::WidenPane,
+            '<' | ',' => Action::NarrowPane,
+            't' => Action::NewTab,
+            'w' => Action::CloseTab,
+            _ => return None,
+        },
+        KeyCode::Char(c) => Action::InsertChar(c),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn motions() {
+        let cases = [
+            ('h', Some(Action::MoveLeft)),
+            ('j', Some(Action::MoveDown)),
+            ('k', Some(Action::MoveUp)),
+            ('l', Some(Action::MoveRight)),
+            ('w', Some(Action::MoveNextWord)),
+            ('b', Some(Action::MoveWordLeft)),
+            ('0', Some(Action::MoveLineStart)),
+            ('$', Some(Action::MoveLineEnd)),
+            ('q', None),
+        ];
+        for (c, action) in cases {
+            assert_eq!(motion(c), action, "{c}");
+            // every motion can be used after an operator.
+            assert!(action.is_none_or(Action::is_motion), "{c}");
+        }
+    }
+
+    #[test]
+    fn default_actions() {
+        let none = KeyModifiers::NONE;
+        let ctrl = KeyModifiers::CONTROL;
+        let alt = KeyModifiers::ALT;
+        let cases = [
+            (key(KeyCode::Char('w'), none), Mode::Command, Some(Action::MoveNextWord)),
+            (key(KeyCode::Char('w'), none), Mode::Insert, Some(Action::InsertChar('w'))),
+            (key(KeyCode::Char('w'), none), Mode::Visual, Some(Action::MoveNextWord)),
+            (key(KeyCode::Char('d'), none), Mode::Command, Some(Action::Operator(Operator::Delete))),
+            (key(KeyCode::Char('x'), none), Mode::VisualLine, Some(Action::Operator(Operator::Delete))),
+            (key(KeyCode::Char('u'), none), Mode::Command, Some(Action::Undo)),
+            (key(KeyCode::Char('u'), none), Mode::Visual, Some(Action::Operator(Operator::Lowercase))),
+            (key(KeyCode::Char('v'), none), Mode::Visual, Some(Action::SwitchMode(Mode::Command))),
+            (key(KeyCode::Char('V'), none), Mode::Visual, Some(Action::SwitchMode(Mode::VisualLine))),
+            (key(KeyCode::Char('I'), none), Mode::VisualBlock, Some(Action::BlockInsert)),
+            (key(KeyCode::Char('I'), none), Mode::Visual, None),
+            (key(KeyCode::Char('v'), ctrl), Mode::Command, Some(Action::SwitchMode(Mode::VisualBlock))),
+            (key(KeyCode::Char('v'), ctrl), Mode::VisualBlock, Some(Action::SwitchMode(Mode::Command))),
+            (key(KeyCode::Char('v'), ctrl), Mode::Insert, Some(Action::Paste)),
+            (key(KeyCode::Char('Z'), ctrl | KeyModifiers::SHIFT), Mode::Insert, Some(Action::Redo)),
+            (key(KeyCode::Char('s'), alt), Mode::Command, Some(Action::SplitHorizontal)),
+            (key(KeyCode::Esc, none), Mode::Insert, Some(Action::SwitchMode(Mode::Command))),
+            (key(KeyCode::Esc, none), Mode::Command, Some(Action::Cancel)),
+            (key(KeyCode::Enter, none), Mode::Command, Some(Action::MoveDown)),
+            (key(KeyCode::Enter, none), Mode::Insert, Some(Action::InsertNewline)),
+            (key(KeyCode::Tab, none), Mode::Command, None),
+            (key(KeyCode::Left, alt), Mode::Insert, Some(Action::FocusLeft)),
+            (key(KeyCode::Left, alt | KeyModifiers::SHIFT), Mode::Insert, Some(Action::SelectBlockLeft)),
+            (key(KeyCode::Up, ctrl | alt), Mode::Insert, Some(Action::AddCursorAbove)),
+            (key(KeyCode::PageDown, ctrl), Mode::Insert, Some(Action::NextTab)),
+        ];
+        for (k, mode, action) in cases {
+            assert_eq!(default_action(k, mode), action, "{k:?} in {mode:?}");
+        }
+    }
+}
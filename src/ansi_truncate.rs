@@ -7,14 +7,16 @@ use grid_ui::{
 };
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::render;
+
 #[derive(Debug)]
-pub struct AnsiTruncate {
-    pub left: &'static str,
-    pub right: &'static str,
+pub struct AnsiTruncate<'a> {
+    pub left: &'a str,
+    pub right: &'a str,
     pub extra_length: usize,
 }
-impl AnsiTruncate {
-    pub fn new(left: &'static str, right: &'static str) -> AnsiTruncate {
+impl<'a> AnsiTruncate<'a> {
+    pub fn new(left: &'a str, right: &'a str) -> AnsiTruncate<'a> {
         AnsiTruncate {
             left,
             right,
@@ -22,25 +24,43 @@ impl AnsiTruncate {
         }
     }
 }
-impl Display for AnsiTruncate {
+impl Display for AnsiTruncate<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
-impl TrimStrategy for AnsiTruncate {
+impl TrimStrategy for AnsiTruncate<'_> {
     type Input = String;
     fn trim(&mut self, text: String, chunk: &DrawProcess, _: Alignment) -> Vec<TrimmedText> {
-        let blank_space = " ".graphemes(true).cycle();
-        let orig = text
-            .graphemes(true)
-            .chain(blank_space)
-            .take(chunk.width() + self.extra_length)
-            .collect::<String>();
+        // escape codes don't take up any room, so they aren't counted towards the width.
+        let width = chunk.width() + self.extra_length;
+        let mut orig = String::new();
+        let (mut taken, mut escape) = (0, false);
+        for grapheme in text.graphemes(true) {
+            if grapheme == "\u{1b}" {
+                escape = true;
+            }
+            if escape {
+                orig.push_str(grapheme);
+                escape = !grapheme.chars().all(|c| c.is_ascii_alphabetic());
+                continue;
+            }
+            // wide characters take up two cells.
+            let grapheme_width = render::text_width(grapheme);
+            if taken + grapheme_width > width {
+                break;
+            }
+            // tabs should have been spread out into spaces already. One that's left is drawn as a space, so the
+            // terminal doesn't move everything after it over.
+            orig.push_str(if grapheme == "\t" { " " } else { grapheme });
+            taken += grapheme_width;
+        }
+        orig.extend(std::iter::repeat_n(' ', width - taken));
         let res = format!(
             "{}{}{}",
-            self.left.to_string(),
+            self.left,
             orig,
-            self.right.to_string()
+            self.right
         );
         self.extra_length = 0; // resets extra length.
         vec![TrimmedText(res)]
@@ -0,0 +1,344 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{
+    command_line::{Command, Message},
+    config::Config,
+    format::FileFormat,
+    grep::{same_file, Grep},
+    panes::Direction,
+    history,
+    keymap::Mode,
+    rope::Rope,
+    textbox::TextBox,
+    State,
+};
+
+// Something a textbox can't do by itself, because it has to do with the other buffers. It's left in the state for the
+// buffers to carry out.
+pub enum Request {
+    Command(Command),
+    // opens a file, or switches to the buffer it's open in, and goes to a line of it (counting from one).
+    Open(String, Option<usize>),
+    // replaces the matches of the last project-wide search in every file, now that it's been confirmed.
+    Replace(String),
+    // moves the focus to the pane on one side of the focused one.
+    Focus(Direction),
+}
+// Every file that's open, each in a textbox of its own. They're numbered from one, up to the 16-bit limit.
+// One of them is shown at a time.
+pub struct Buffers {
+    buffers: BTreeMap<u16, TextBox>,
+    current: u16,
+}
+impl Buffers {
+    // Opens every file given. The first one is shown.
+    pub fn new(paths: &[String], config: &Config) -> Buffers {
+        let mut buffers = Buffers {
+            buffers: BTreeMap::new(),
+            current: 1,
+        };
+        for path in paths {
+            let _ = buffers.open(path, config);
+        }
+        buffers.current = *buffers.buffers.keys().next().unwrap_or(&1);
+        buffers
+    }
+    // The buffer being shown.
+    pub fn current(&mut self) -> &mut TextBox {
+        self.buffers.get_mut(&self.current).expect("Safe unwrap")
+    }
+    pub fn current_id(&self) -> u16 {
+        self.current
+    }
+    pub fn contains(&self, id: u16) -> bool {
+        self.buffers.contains_key(&id)
+    }
+    pub fn get(&mut self, id: u16) -> &mut TextBox {
+        self.buffers.get_mut(&id).expect("Safe unwrap")
+    }
+    // Opens a file in a new buffer, or finds the buffer it's already open in. Returns the buffer's number.
+    pub fn open(&mut self, path: &str, config: &Config) -> Result<u16, String> {
+        if let Some((id, _)) = self.buffers.iter().find(|(_, x)| same_file(x.path(), path)) {
+            return Ok(*id);
+        }
+        // the lowest number that isn't taken.
+        let id = (1..=u16::MAX)
+            .find(|x| !self.buffers.contains_key(x))
+            .ok_or_else(|| "Too many buffers".to_string())?;
+        self.buffers.insert(id, load(path, config));
+        Ok(id)
+    }
+    // Shows another buffer. Whatever mode the last one was in is left behind.
+    pub fn switch(&mut self, id: u16, state: &mut State) {
+        if id != self.current {
+            self.current().switch_mode(Mode::Command, state);
+            self.current = id;
+        }
+    }
+    // Shows the next buffer (or the previous one), going around the end of the list.
+    pub fn cycle(&mut self, forward: bool, state: &mut State) {
+        let ids = self.buffers.keys().copied().collect::<Vec<_>>();
+        let i = ids.iter().position(|x| *x == self.current).expect("Safe unwrap");
+        let target = if forward { (i + 1) % ids.len() } else { (i + ids.len() - 1) % ids.len() };
+        self.switch(ids[target], state);
+    }
+    // Lists the buffers by number. The one being shown is marked with a %, and ones with unsaved changes with a +.
+    pub fn list(&self) -> String {
+        self.buffers
+            .iter()
+            .map(|(id, x)| {
+                let current = if *id == self.current { "%" } else { " " };
+                let modified = if x.modified() { "+" } else { " " };
+                format!("{}{}{} \"{}\"", id, current, modified, x.path())
+            })
+            .collect::<Vec<_>>()
+            .join("   ")
+    }
+    // Closes the buffer being shown, and shows the one after it. Closing the last one quits.
+    pub fn close(&mut self, state: &mut State) {
+        self.current().save_history(&state.config);
+        if self.buffers.len() == 1 {
+            state.will_quit = true;
+            return;
+        }
+        let closed = self.current;
+        self.cycle(true, state);
+        self.buffers.remove(&closed);
+    }
+    // Writes every buffer's undo history to disk.
+    pub fn save_histories(&mut self, config: &Config) {
+        for buffer in self.buffers.values_mut() {
+            buffer.save_history(config);
+        }
+    }
+    // How long until the first message on a status line goes away.
+    pub fn message_time_left(&self, timeout: Duration) -> Option<Duration> {
+        self.buffers.values().filter_map(|x| x.message_time_left(timeout)).min()
+    }
+    // The path and text of every buffer, for searching them as they are rather than as they were saved.
+    fn texts(&self) -> Vec<(String, String)> {
+        self.buffers.values().map(|x| (x.path().to_string(), x.text())).collect()
+    }
+    // Carries out something a textbox asked for.
+    pub fn handle(&mut self, request: Request, state: &mut State) {
+        match request {
+            Request::Command(command) => self.run_command(command, state),
+            Request::Open(path, line) => match self.open(&path, &state.config) {
+                Ok(id) => {
+                    self.switch(id, state);
+                    if let Some(line) = line {
+                        self.current().goto(line);
+                    }
+                }
+                Err(e) => state.message = Some(Message::Error(e)),
+            },
+            Request::Replace(replacement) => self.grep_replace(&replacement, state),
+            // the panes carry this out themselves.
+            Request::Focus(_) => {}
+        }
+    }
+    pub fn run_command(&mut self, command: Command, state: &mut State) {
+        match command {
+            Command::Quit(force) => {
+                match self.buffers.iter().find(|(_, x)| x.modified()).map(|(id, _)| *id) {
+                    // the first buffer with unsaved changes is shown, so they can be looked at.
+                    Some(id) if !force => {
+                        self.switch(id, state);
+                        state.message = Some(Message::Error(format!(
+                            "No write since last change for \"{}\" (add ! to override)",
+                            self.current().path()
+                        )));
+                    }
+                    _ => {
+                        self.save_histories(&state.config);
+                        state.will_quit = true;
+                    }
+                }
+            }
+            Command::Edit(path) => self.handle(Request::Open(path, None), state),
+            Command::Buffer(id) if self.buffers.contains_key(&id) => self.switch(id, state),
+            Command::Buffer(id) => state.message = Some(Message::Error(format!("Buffer {} does not exist", id))),
+            Command::BufferNext => self.cycle(true, state),
+            Command::BufferPrevious => self.cycle(false, state),
+            Command::Buffers => state.message = Some(Message::Info(self.list())),
+            // closing a buffer with unsaved changes has to be confirmed.
+            Command::BufferDelete(force) if force || !self.current().modified() => self.close(state),
+            Command::BufferDelete(_) => state.confirm_close = true,
+            Command::Grep(pattern, dir) => self.grep(pattern, dir, state),
+            Command::GrepReplace(replacement) => match &mut state.grep {
+                Some(grep) if !grep.results.is_empty() => grep.prepare_replace(replacement),
+                _ => state.message = Some(Message::Error("No grep results to replace".to_string())),
+            },
+            // everything else is carried out by the textbox itself.
+            command => self.current().run_command(command, state),
+        }
+    }
+    // Searches every file under a directory, or the current one, and shows the results. With no pattern, the results
+    // of the last search are shown again.
+    pub fn grep(&mut self, pattern: Option<String>, dir: Option<String>, state: &mut State) {
+        let pattern = match pattern {
+            Some(val) => val,
+            None => {
+                match &mut state.grep {
+                    Some(grep) => grep.open = true,
+                    None => state.message = Some(Message::Error("No previous grep".to_string())),
+                }
+                return;
+            }
+        };
+        let dir = dir.unwrap_or_else(|| ".".to_string());
+        match Grep::search(&pattern, &dir, state.config.ignore_case, &self.texts()) {
+            Ok(grep) if grep.results.is_empty() => {
+                state.message = Some(Message::Error(format!("Pattern not found: {}", pattern)));
+            }
+            Ok(grep) => state.grep = Some(grep),
+            Err(e) => state.message = Some(Message::Error(e)),
+        }
+    }
+    // Carries out a confirmed replacement on every line that the last project-wide search found. Files that are open are
+    // changed in the editor, each as one undo, and the others are changed on disk.
+    pub fn grep_replace(&mut self, replacement: &str, state: &mut State) {
+        let grep = match &state.grep {
+            Some(val) => val,
+            None => return,
+        };
+        let (mut count, mut files) = (0, 0);
+        for path in grep.paths() {
+            let open = self.buffers.values_mut().find(|x| same_file(x.path(), &path));
+            let replaced = match open {
+                Some(buffer) => buffer.replace_matches(&grep.regex, replacement, &grep.lines(&path)),
+                None => match grep.replace_in_file(&path, replacement) {
+                    Ok(val) => val,
+                    Err(e) => {
+                        state.message = Some(Message::Error(format!("Failed to replace in {}: {}", path, e)));
+                        return;
+                    }
+                },
+            };
+            if replaced > 0 {
+                count += replaced;
+                files += 1;
+            }
+        }
+        // the results are searched for again, so they match the files as they are now.
+        let (pattern, dir) = (grep.pattern.clone(), grep.dir.clone());
+        state.grep = None;
+        self.grep(Some(pattern), Some(dir), state);
+        if let Some(grep) = &mut state.grep {
+            grep.open = false;
+        }
+        state.message = Some(Message::Info(format!("Replaced {} matches in {} files", count, files)));
+    }
+}
+// Reads a file into a textbox. A file that doesn't exist yet starts out empty.
+fn load(p: &str, config: &Config) -> TextBox {
+    // reads the file into a rope, one line at a time.
+    let file = std::fs::read_to_string(p).unwrap_or_default();
+    // the line endings and byte order mark are put back when it's saved.
+    let (format, text) = FileFormat::detect(&file);
+    let contents = Rope::from_lines(text.lines());
+    let hash = history::content_hash(file.as_bytes());
+    // the file's text is no longer needed, now that it's in the rope.
+    drop(file);
+
+    // creates a textbox out of the file's output
+    TextBox::new(contents, p.to_string(), hash, format, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::*;
+    use crate::textbox::tests::state;
+
+    // A directory with a few files to open, made fresh for each test.
+    fn files(name: &str) -> (String, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("textedit-buffers-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Safe unwrap");
+        let mut paths = Vec::new();
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(dir.join(file), file).expect("Safe unwrap");
+            paths.push(dir.join(file).to_string_lossy().to_string());
+        }
+        (dir.to_string_lossy().to_string(), paths)
+    }
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn opening_a_file_twice() {
+        let (dir, paths) = files("open");
+        let mut state = state();
+        let mut buffers = Buffers::new(&[paths[0].clone(), paths[1].clone(), paths[0].clone()], &state.config);
+        assert_eq!(buffers.buffers.len(), 2);
+        assert_eq!(buffers.current_id(), 1);
+        // the same file, however its path is written, goes to the buffer it's open in.
+        let other = format!("{}/./a.txt", dir);
+        buffers.run_command(Command::Edit(other), &mut state);
+        assert_eq!((buffers.buffers.len(), buffers.current_id()), (2, 1));
+        buffers.run_command(Command::Edit(paths[2].clone()), &mut state);
+        assert_eq!((buffers.buffers.len(), buffers.current_id()), (3, 3));
+        assert_eq!(buffers.current().text(), "c.txt");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn switching_buffers() {
+        let (dir, paths) = files("switch");
+        let mut state = state();
+        let mut buffers = Buffers::new(&paths, &state.config);
+        let mut shown = Vec::new();
+        for command in [Command::BufferPrevious, Command::BufferPrevious, Command::BufferNext, Command::Buffer(1)] {
+            buffers.run_command(command, &mut state);
+            shown.push(buffers.current_id());
+        }
+        assert_eq!(shown, vec![3, 2, 3, 1]);
+        buffers.run_command(Command::Buffer(9), &mut state);
+        assert!(matches!(state.message, Some(Message::Error(_))));
+        assert_eq!(buffers.current_id(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn closing_modified_buffers() {
+        let (dir, paths) = files("close");
+        let mut state = state();
+        let mut buffers = Buffers::new(&paths[..2], &state.config);
+        buffers.current().insert_text("x");
+        // closing asks first, and anything but a yes keeps the buffer open.
+        buffers.run_command(Command::BufferDelete(false), &mut state);
+        assert!(state.confirm_close);
+        buffers.current().recv_key(key('n'), &mut state);
+        assert!(!state.confirm_close && state.request.is_none());
+        assert_eq!(buffers.buffers.len(), 2);
+        buffers.run_command(Command::BufferDelete(false), &mut state);
+        buffers.current().recv_key(key('y'), &mut state);
+        let request = state.request.take().expect("Safe unwrap");
+        buffers.handle(request, &mut state);
+        assert_eq!((buffers.buffers.len(), buffers.current_id()), (1, 2));
+        // an unmodified buffer closes straight away, and closing the last one quits.
+        buffers.run_command(Command::BufferDelete(false), &mut state);
+        assert!(!state.confirm_close && state.will_quit);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn quitting_with_modified_buffers() {
+        let (dir, paths) = files("quit");
+        let mut state = state();
+        let mut buffers = Buffers::new(&paths, &state.config);
+        buffers.get(2).insert_text("x");
+        // the buffer with unsaved changes is shown instead.
+        buffers.run_command(Command::Quit(false), &mut state);
+        assert!(!state.will_quit);
+        assert!(matches!(state.message, Some(Message::Error(_))));
+        assert_eq!(buffers.current_id(), 2);
+        buffers.run_command(Command::Quit(true), &mut state);
+        assert!(state.will_quit);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
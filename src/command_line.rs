@@ -0,0 +1,560 @@
+use std::{fs, time::Duration};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+// Every command, along with how many of its letters have to be typed to use it.
+const COMMANDS: [(&str, usize); 28] = [
+    ("write", 1),
+    ("quit", 1),
+    ("wq", 2),
+    ("xit", 1),
+    ("set", 2),
+    ("earlier", 2),
+    ("later", 3),
+    ("nohlsearch", 3),
+    ("substitute", 1),
+    ("grep", 2),
+    ("greplace", 5),
+    ("edit", 1),
+    ("buffer", 1),
+    ("buffers", 7),
+    ("ls", 2),
+    ("bnext", 2),
+    ("bprevious", 2),
+    ("bdelete", 2),
+    ("split", 2),
+    ("vsplit", 2),
+    ("close", 3),
+    ("resize", 3),
+    ("vresize", 4),
+    ("tabnext", 4),
+    ("tabnew", 6),
+    ("tabprevious", 4),
+    ("tabclose", 4),
+    ("tabmove", 4),
+];
+
+// Something to tell the user. It's shown on the bottom line until the next key press.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Info(String),
+    Error(String),
+}
+// How far to move through the undo history: a number of undos, or an amount of time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Amount {
+    Steps(usize),
+    Time(Duration),
+}
+// A search and replace, typed as `s/pattern/replacement/flags`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Substitute {
+    // a regular expression.
+    pub pattern: String,
+    // what each match is replaced with. `$1` stands for what the first group matched.
+    pub replacement: String,
+    // whether it was typed as `%s`, which works on the whole text even if some of it is selected.
+    pub whole: bool,
+    // the `c` flag, which asks before each replacement.
+    pub confirm: bool,
+    // the `n` flag, which only counts the matches.
+    pub count_only: bool,
+    // the `i` flag, which ignores case.
+    pub ignore_case: bool,
+}
+// A command typed into the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    // saves the file, or writes it to another path.
+    Write(Option<String>),
+    // quits, even if there are unsaved changes when it's forced.
+    Quit(bool),
+    WriteQuit(Option<String>),
+    // jumps to a line, counting from one.
+    Goto(usize),
+    // changes settings.
+    Set(String),
+    Earlier(Amount),
+    Later(Amount),
+    // stops highlighting the matches of the last search, until the next one.
+    NoHighlight,
+    Substitute(Substitute),
+    // searches every file under a directory, or shows the results of the last search again if no pattern is given.
+    Grep(Option<String>, Option<String>),
+    // replaces every match of the last project-wide search, once it's confirmed.
+    GrepReplace(String),
+    // opens a file in a buffer of its own, or switches to the buffer it's already open in.
+    Edit(String),
+    // switches to a buffer by its number.
+    Buffer(u16),
+    BufferNext,
+    BufferPrevious,
+    // lists every buffer.
+    Buffers,
+    // closes the current buffer, even if there are unsaved changes when it's forced.
+    BufferDelete(bool),
+    // splits the focused pane into two, one above the other (or side by side), and opens a file in the new one.
+    Split(Option<String>),
+    VerticalSplit(Option<String>),
+    // closes the focused pane.
+    Close,
+    // changes the focused pane's height (or width), either by an amount or to it.
+    Resize { vertical: bool, amount: isize, relative: bool },
+    // opens a tab page after the current one, showing the current buffer or a file.
+    TabNew(Option<String>),
+    TabNext,
+    TabPrevious,
+    // closes the current tab page.
+    TabClose,
+    // moves the current tab page to a position, or by a number of places.
+    TabMove { amount: isize, relative: bool },
+}
+impl Command {
+    // Parses a line like `w other.txt`, `q!`, `123` or `set chord_timeout=500`.
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        if let Ok(val) = line.parse::<usize>() {
+            return Ok(Command::Goto(val));
+        }
+        // a % in front means the whole text.
+        let (whole, line) = match line.strip_prefix('%') {
+            Some(val) => (true, val),
+            None => (false, line),
+        };
+        // the name is made of letters, and can be followed by a ! to force it.
+        let (name, rest) = line.split_at(line.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(line.len()));
+        let (force, rest) = match rest.strip_prefix('!') {
+            Some(val) => (true, val),
+            None => (false, rest),
+        };
+        let arg = Some(rest.trim().to_string()).filter(|x| !x.is_empty());
+        match full_name(name) {
+            Some("write") => Ok(Command::Write(arg)),
+            Some("quit") => Ok(Command::Quit(force)),
+            Some("wq" | "xit") => Ok(Command::WriteQuit(arg)),
+            Some("set") => Ok(Command::Set(arg.unwrap_or_default())),
+            Some("earlier") => Ok(Command::Earlier(parse_amount(arg)?)),
+            Some("later") => Ok(Command::Later(parse_amount(arg)?)),
+            Some("nohlsearch") => Ok(Command::NoHighlight),
+            Some("substitute") => parse_substitute(rest, whole),
+            Some("grep") => parse_grep(arg),
+            Some("greplace") => Ok(Command::GrepReplace(rest.trim_start().to_string())),
+            Some("edit") => Ok(Command::Edit(arg.ok_or_else(|| "Missing file name".to_string())?)),
+            Some("buffer") => {
+                let arg = arg.ok_or_else(|| "Missing buffer number".to_string())?;
+                Ok(Command::Buffer(arg.parse().map_err(|_| format!("Invalid argument: {}", arg))?))
+            }
+            Some("buffers" | "ls") => Ok(Command::Buffers),
+            Some("bnext") => Ok(Command::BufferNext),
+            Some("bprevious") => Ok(Command::BufferPrevious),
+            Some("bdelete") => Ok(Command::BufferDelete(force)),
+            Some("split") => Ok(Command::Split(arg)),
+            Some("vsplit") => Ok(Command::VerticalSplit(arg)),
+            Some("close") => Ok(Command::Close),
+            Some("resize") => parse_resize(arg, false),
+            Some("vresize") => parse_resize(arg, true),
+            Some("tabnew") => Ok(Command::TabNew(arg)),
+            Some("tabnext") => Ok(Command::TabNext),
+            Some("tabprevious") => Ok(Command::TabPrevious),
+            Some("tabclose") => Ok(Command::TabClose),
+            Some("tabmove") => parse_tab_move(arg),
+            _ => Err(format!("Not an editor command: {}", line)),
+        }
+    }
+}
+// The full name of a command, from as much of it as was typed.
+fn full_name(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|(full, min)| name.len() >= *min && full.starts_with(name))
+        .map(|(full, _)| *full)
+}
+// Parses the `/pattern/replacement/flags` part of a substitute. Any character can stand in for the slashes, and it can
+// be used in the pattern or replacement by putting a backslash in front of it.
+fn parse_substitute(rest: &str, whole: bool) -> Result<Command, String> {
+    let mut parts = split_delimited(rest.trim_start(), 3).into_iter();
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let flags = parts.next().unwrap_or_default();
+    if pattern.is_empty() {
+        return Err("Missing pattern".to_string());
+    }
+    if let Some(c) = flags.chars().find(|x| !"cni".contains(*x)) {
+        return Err(format!("Invalid flag: {}", c));
+    }
+    Ok(Command::Substitute(Substitute {
+        pattern,
+        replacement,
+        whole,
+        confirm: flags.contains('c'),
+        count_only: flags.contains('n'),
+        ignore_case: flags.contains('i'),
+    }))
+}
+// Parses the pattern of a project-wide search, and the directory to search. A pattern can be put between slashes to
+// give a directory after it: `/pattern/ dir`.
+fn parse_grep(arg: Option<String>) -> Result<Command, String> {
+    let arg = match arg {
+        Some(val) => val,
+        None => return Ok(Command::Grep(None, None)),
+    };
+    if !arg.starts_with('/') {
+        return Ok(Command::Grep(Some(arg), None));
+    }
+    let mut parts = split_delimited(&arg, 2).into_iter();
+    let pattern = parts.next().filter(|x| !x.is_empty()).ok_or_else(|| "Missing pattern".to_string())?;
+    let dir = parts.next().map(|x| x.trim().to_string()).filter(|x| !x.is_empty());
+    Ok(Command::Grep(Some(pattern), dir))
+}
+// Splits text into at most a number of parts, at the character it starts with. That character can be put in a part by
+// putting a backslash in front of it.
+fn split_delimited(text: &str, max: usize) -> Vec<String> {
+    let mut chars = text.chars();
+    let delimiter = match chars.next() {
+        Some(val) => val,
+        None => return Vec::new(),
+    };
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut().expect("Safe unwrap").push(next),
+                Some(next) => parts.last_mut().expect("Safe unwrap").extend(['\\', next]),
+                None => parts.last_mut().expect("Safe unwrap").push('\\'),
+            },
+            c if c == delimiter && parts.len() < max => parts.push(String::new()),
+            c => parts.last_mut().expect("Safe unwrap").push(c),
+        }
+    }
+    parts
+}
+// Parses a size for a pane. A sign in front makes it bigger or smaller by that much, rather than that size.
+fn parse_resize(arg: Option<String>, vertical: bool) -> Result<Command, String> {
+    let arg = arg.ok_or_else(|| "Missing size".to_string())?;
+    let amount = arg.parse::<isize>().map_err(|_| format!("Invalid argument: {}", arg))?;
+    Ok(Command::Resize {
+        vertical,
+        amount,
+        relative: arg.starts_with(['+', '-']),
+    })
+}
+// Parses where to move a tab page to. A sign in front moves it by that many places, and nothing at all moves it to the end.
+fn parse_tab_move(arg: Option<String>) -> Result<Command, String> {
+    let arg = match arg {
+        Some(val) => val,
+        None => {
+            return Ok(Command::TabMove {
+                amount: isize::MAX,
+                relative: false,
+            })
+        }
+    };
+    let amount = arg.parse::<isize>().map_err(|_| format!("Invalid argument: {}", arg))?;
+    Ok(Command::TabMove {
+        amount,
+        relative: arg.starts_with(['+', '-']),
+    })
+}
+// Parses a number of undos (`3`) or an amount of time (`10s`, `5m`, `2h`). Nothing at all means one undo.
+fn parse_amount(arg: Option<String>) -> Result<Amount, String> {
+    let arg = match arg {
+        Some(val) => val,
+        None => return Ok(Amount::Steps(1)),
+    };
+    let (number, unit) = arg.split_at(arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len()));
+    let number = number.parse::<u64>().map_err(|_| format!("Invalid argument: {}", arg))?;
+    let seconds = match unit {
+        "" => return Ok(Amount::Steps(number as usize)),
+        "s" => Some(number),
+        "m" => number.checked_mul(60),
+        "h" => number.checked_mul(60 * 60),
+        "d" => number.checked_mul(60 * 60 * 24),
+        _ => return Err(format!("Invalid argument: {}", arg)),
+    };
+    let seconds = seconds.ok_or_else(|| "Amount too large".to_string())?;
+    Ok(Amount::Time(Duration::from_secs(seconds)))
+}
+// The line at the bottom of the screen that commands and searches are typed into.
+pub struct CommandLine {
+    pub open: bool,
+    // the character shown before the input, which says what it's for: `:` for a command, or `/` and `?` for a search.
+    pub prompt: char,
+    pub input: Vec<char>,
+    // where in the input the cursor is.
+    pub cursor: usize,
+    // every line that has been entered, oldest first, along with the prompt it was entered at.
+    // Each prompt only looks through its own lines.
+    history: Vec<(char, String)>,
+    // the line of the history being shown, if the history is being looked through.
+    browsing: Option<usize>,
+    // what was typed before looking through the history. Only lines that start with it are shown.
+    draft: String,
+}
+impl CommandLine {
+    pub fn new() -> CommandLine {
+        CommandLine {
+            open: false,
+            prompt: ':',
+            input: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            browsing: None,
+            draft: String::new(),
+        }
+    }
+    pub fn open(&mut self, prompt: char) {
+        self.open = true;
+        self.prompt = prompt;
+        self.input.clear();
+        self.cursor = 0;
+        self.browsing = None;
+    }
+    // Handles a key press. Returns the line once it's entered.
+    pub fn recv_key(&mut self, k: KeyEvent, message: &mut Option<Message>) -> Option<String> {
+        let KeyEvent { code, modifiers } = k;
+        match code {
+            KeyCode::Enter => {
+                self.open = false;
+                let line = self.input.iter().collect::<String>();
+                let entry = (self.prompt, line.clone());
+                if !line.trim().is_empty() && self.history.last() != Some(&entry) {
+                    self.history.push(entry);
+                }
+                return Some(line);
+            }
+            KeyCode::Esc => self.open = false,
+            KeyCode::Char('c' | 'C') if modifiers.contains(KeyModifiers::CONTROL) => self.open = false,
+            // backspacing past the start of the line closes it.
+            KeyCode::Backspace if self.input.is_empty() => self.open = false,
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
+            KeyCode::Up => self.browse(true),
+            KeyCode::Down => self.browse(false),
+            KeyCode::Tab if self.prompt == ':' => *message = self.complete(),
+            KeyCode::Char(c) => {
+                self.input.insert(self.cursor, c);
+                self.cursor += 1;
+                self.browsing = None;
+            }
+            _ => {}
+        }
+        None
+    }
+    // Shows the previous (or next) line of the history that starts with what was typed.
+    fn browse(&mut self, back: bool) {
+        if self.browsing.is_none() {
+            self.draft = self.input.iter().collect();
+        }
+        let wanted = |(prompt, line): &(char, String)| *prompt == self.prompt && line.starts_with(&self.draft);
+        let found = if back {
+            let end = self.browsing.unwrap_or(self.history.len());
+            self.history[..end].iter().rposition(wanted)
+        } else if let Some(current) = self.browsing {
+            self.history[current + 1..]
+                .iter()
+                .position(wanted)
+                .map(|x| x + current + 1)
+        } else {
+            return;
+        };
+        match found {
+            Some(val) => {
+                self.browsing = Some(val);
+                self.input = self.history[val].1.chars().collect();
+            }
+            // going forward past the newest line brings back what was typed.
+            None if !back => {
+                self.browsing = None;
+                self.input = self.draft.chars().collect();
+            }
+            None => {}
+        }
+        self.cursor = self.input.len();
+    }
+    // Completes the word before the cursor: a command name if it's the first word, and a path otherwise.
+    // If there's more than one way to complete it, it's completed as far as they agree, and the options are listed.
+    fn complete(&mut self) -> Option<Message> {
+        let before = &self.input[..self.cursor];
+        let start = before.iter().rposition(|x| *x == ' ').map(|x| x + 1).unwrap_or(0);
+        let word = before[start..].iter().collect::<String>();
+        let options = if before[..start].iter().all(|x| *x == ' ') {
+            COMMANDS
+                .iter()
+                .filter(|(full, _)| full.starts_with(&word))
+                .map(|(full, _)| format!("{} ", full))
+                .collect()
+        } else {
+            complete_path(&word)
+        };
+        let first = match options.first() {
+            Some(val) => val,
+            None => return Some(Message::Error(format!("No matches for {}", word))),
+        };
+        // the longest start that every option shares.
+        let shared = options.iter().fold(first.chars().collect::<Vec<_>>(), |shared, x| {
+            shared.into_iter().zip(x.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+        });
+        self.input.splice(start..self.cursor, shared.iter().copied());
+        self.cursor = start + shared.len();
+        if options.len() > 1 {
+            Some(Message::Info(options.iter().map(|x| x.trim_end()).collect::<Vec<_>>().join("  ")))
+        } else {
+            None
+        }
+    }
+}
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// The paths that start with part of a path. Directories end in a slash, so they can be completed into.
+fn complete_path(word: &str) -> Vec<String> {
+    let (dir, name) = match word.rfind('/') {
+        Some(val) => word.split_at(val + 1),
+        None => ("", word),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(val) => val,
+        Err(_) => return Vec::new(),
+    };
+    let mut options: Vec<String> = entries
+        .filter_map(|x| x.ok())
+        .filter_map(|x| {
+            let file_name = x.file_name().to_string_lossy().to_string();
+            // hidden files only show up if a dot was typed.
+            if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let slash = if x.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect();
+    options.sort();
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitute(pattern: &str, replacement: &str, flags: &str, whole: bool) -> Command {
+        Command::Substitute(Substitute {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            whole,
+            confirm: flags.contains('c'),
+            count_only: flags.contains('n'),
+            ignore_case: flags.contains('i'),
+        })
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_delimited("/a/b/c", 3), vec!["a", "b", "c"]);
+        // the last part takes in any delimiters after it.
+        assert_eq!(split_delimited("/a/b/c/d", 3), vec!["a", "b", "c/d"]);
+        assert_eq!(split_delimited("/a", 3), vec!["a"]);
+        assert_eq!(split_delimited("", 3), Vec::<String>::new());
+        // an escaped delimiter is kept without its backslash, while other escapes are left for the regular expression.
+        assert_eq!(split_delimited(r"/a\/b/\d+\\/", 3), vec!["a/b", r"\d+\\", ""]);
+        assert_eq!(split_delimited(r"#a/b\#c#d#", 3), vec!["a/b#c", "d", ""]);
+        assert_eq!(split_delimited(r"/a\", 3), vec![r"a\"]);
+    }
+
+    #[test]
+    fn substitutes() {
+        assert_eq!(Command::parse("s/one/two/"), Ok(substitute("one", "two", "", false)));
+        assert_eq!(Command::parse("%s/one/two/g"), Err("Invalid flag: g".to_string()));
+        assert_eq!(Command::parse("%s/one/two/cin"), Ok(substitute("one", "two", "cin", true)));
+        assert_eq!(Command::parse("s/one"), Ok(substitute("one", "", "", false)));
+        assert_eq!(Command::parse(r"substitute #a\#b#$1/c#i"), Ok(substitute("a#b", "$1/c", "i", false)));
+        assert_eq!(Command::parse(r"s/(\w+)\/x/\//n"), Ok(substitute(r"(\w+)/x", "/", "n", false)));
+        assert_eq!(Command::parse("s//two/"), Err("Missing pattern".to_string()));
+        assert_eq!(Command::parse("s"), Err("Missing pattern".to_string()));
+    }
+
+    #[test]
+    fn greps() {
+        assert_eq!(Command::parse("grep"), Ok(Command::Grep(None, None)));
+        assert_eq!(Command::parse("gr fn main"), Ok(Command::Grep(Some("fn main".to_string()), None)));
+        assert_eq!(
+            Command::parse(r"grep /a\/b/ src"),
+            Ok(Command::Grep(Some("a/b".to_string()), Some("src".to_string())))
+        );
+        assert_eq!(Command::parse("grep //"), Err("Missing pattern".to_string()));
+        assert_eq!(Command::parse("grepl  x "), Ok(Command::GrepReplace("x".to_string())));
+    }
+
+    #[test]
+    fn commands() {
+        let cases = [
+            ("w", Command::Write(None)),
+            ("write out.txt", Command::Write(Some("out.txt".to_string()))),
+            ("q", Command::Quit(false)),
+            ("q!", Command::Quit(true)),
+            ("wq", Command::WriteQuit(None)),
+            ("x new.txt", Command::WriteQuit(Some("new.txt".to_string()))),
+            (" 42 ", Command::Goto(42)),
+            ("se tabstop=8", Command::Set("tabstop=8".to_string())),
+            ("ea", Command::Earlier(Amount::Steps(1))),
+            ("earlier 3", Command::Earlier(Amount::Steps(3))),
+            ("lat 10m", Command::Later(Amount::Time(Duration::from_secs(600)))),
+            ("later 1d", Command::Later(Amount::Time(Duration::from_secs(86400)))),
+            ("noh", Command::NoHighlight),
+            ("e other.txt", Command::Edit("other.txt".to_string())),
+            ("b 2", Command::Buffer(2)),
+            ("buffers", Command::Buffers),
+            ("ls", Command::Buffers),
+            ("bn", Command::BufferNext),
+            ("bp", Command::BufferPrevious),
+            ("bd", Command::BufferDelete(false)),
+            ("bd!", Command::BufferDelete(true)),
+            ("sp", Command::Split(None)),
+            ("vs other.txt", Command::VerticalSplit(Some("other.txt".to_string()))),
+            ("clo", Command::Close),
+            ("res +5", Command::Resize { vertical: false, amount: 5, relative: true }),
+            ("vres 20", Command::Resize { vertical: true, amount: 20, relative: false }),
+            ("tabnew", Command::TabNew(None)),
+            ("tabn", Command::TabNext),
+            ("tabp", Command::TabPrevious),
+            ("tabc", Command::TabClose),
+            ("tabm", Command::TabMove { amount: isize::MAX, relative: false }),
+            ("tabm -1", Command::TabMove { amount: -1, relative: true }),
+        ];
+        for (line, command) in cases {
+            assert_eq!(Command::parse(line), Ok(command), "{}", line);
+        }
+    }
+
+    #[test]
+    fn bad_commands() {
+        // too short to tell which command it is, or not a command at all.
+        assert_eq!(Command::parse("t"), Err("Not an editor command: t".to_string()));
+        assert_eq!(Command::parse("tab"), Err("Not an editor command: tab".to_string()));
+        assert_eq!(Command::parse("frobnicate"), Err("Not an editor command: frobnicate".to_string()));
+        assert_eq!(Command::parse("e"), Err("Missing file name".to_string()));
+        assert_eq!(Command::parse("b two"), Err("Invalid argument: two".to_string()));
+        assert_eq!(Command::parse("earlier 5y"), Err("Invalid argument: 5y".to_string()));
+        assert_eq!(Command::parse("later 9999999999999999d"), Err("Amount too large".to_string()));
+        assert_eq!(Command::parse("res"), Err("Missing size".to_string()));
+    }
+
+    #[test]
+    fn every_command_parses() {
+        // each command can be typed in full, and with as few letters as it allows.
+        for (full, min) in COMMANDS {
+            assert_eq!(full_name(full), Some(full));
+            assert_eq!(full_name(&full[..min]), Some(full), "{}", &full[..min]);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// The file that the configuration is read from.
+pub const CONFIG_PATH: &str = "config";
+
+// Settings that are read from the config file. Anything missing from the file keeps its default value.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // The number of bytes of text that the undo history may hold before the oldest changes are forgotten.
+    pub undo_budget: usize,
+    // How many milliseconds of pause start a new undo while typing.
+    pub undo_group_pause: u64,
+    // Whether the undo history is saved when a file is saved or closed, and restored when it's opened again unchanged.
+    pub persistent_undo: bool,
+    // Where saved histories go. If this is empty, each history is kept in a hidden file next to its file.
+    pub undo_dir: String,
+    // How many milliseconds to wait for the next key of a key sequence.
+    pub chord_timeout: u64,
+    // Whether searches match letters regardless of case.
+    pub ignore_case: bool,
+    // Whether searches only match whole words.
+    pub whole_word: bool,
+    // Whether searches are regular expressions.
+    pub regex_search: bool,
+    // Whether long lines are wrapped onto the rows below, rather than cut off at the edge of the pane.
+    pub wrap: bool,
+    // How many columns apart the tab stops are.
+    pub tabstop: usize,
+    // Whether the tab key and indenting put in spaces rather than tabs.
+    pub expandtab: bool,
+    // How many columns are kept between the cursor and the left or right edge of a pane, when it's scrolled sideways.
+    pub side_margin: usize,
+    // What each pane's status line shows. Fields in braces are filled in: {path}, {modified}, {mode}, {line},
+    // {column}, {lines}, {line_ending}, {encoding}, {syntax} and {message}. Whatever comes after {=} goes on the right.
+    pub status_line: String,
+    // How many milliseconds a message stays on the status line.
+    pub message_timeout: u64,
+    // Whether the text is colored according to the grammar for its file's extension.
+    pub syntax: bool,
+    // Where grammar files are read from, as well as the ones that come with the editor.
+    pub grammar_dir: String,
+    // The file the colors are read from. If it doesn't exist, the default colors are used.
+    pub theme: String,
+    // How many colors the terminal can show: "16", "256", "truecolor", or "auto" to work it out from the terminal.
+    // Colors in the theme that can't be shown are swapped for the closest ones that can.
+    pub colors: String,
+}
+impl Config {
+    // Reads the config file, falling back on the defaults if there isn't one.
+    pub fn load() -> Config {
+        match fs::read_to_string(CONFIG_PATH) {
+            Ok(val) => serde_json::from_str(&val).expect("Invalid config file!"),
+            Err(_) => Config::default(),
+        }
+    }
+    // Changes a setting from the command line. Takes `name=value`, `name` or `noname` to turn a switch on or off,
+    // or `name?` to show a setting. Returns the setting as it now stands.
+    pub fn set(&mut self, arg: &str) -> Result<String, String> {
+        let mut settings = match serde_json::to_value(&*self) {
+            Ok(Value::Object(val)) => val,
+            _ => return Err("Settings can't be changed".to_string()),
+        };
+        let (name, value) = if let Some(name) = arg.strip_suffix('?') {
+            (name, None)
+        } else if let Some((name, text)) = arg.split_once('=') {
+            // the new value has to be the same kind of value as the old one.
+            let value = match settings.get(name) {
+                Some(Value::Bool(_)) => text.parse::<bool>().ok().map(Value::from),
+                Some(Value::Number(_)) => text.parse::<u64>().ok().map(Value::from),
+                Some(Value::String(_)) => Some(Value::from(text)),
+                _ => return Err(format!("Unknown option: {}", name)),
+            };
+            (name, Some(value.ok_or_else(|| format!("Invalid argument: {}", arg))?))
+        } else if matches!(settings.get(arg), Some(Value::Bool(_))) {
+            (arg, Some(Value::Bool(true)))
+        } else if let Some(name) = arg.strip_prefix("no").filter(|x| matches!(settings.get(*x), Some(Value::Bool(_)))) {
+            (name, Some(Value::Bool(false)))
+        } else {
+            (arg, None)
+        };
+        let slot = settings.get_mut(name).ok_or_else(|| format!("Unknown option: {}", name))?;
+        if let Some(value) = value {
+            *slot = value;
+        }
+        let shown = format!("{}={}", name, slot);
+        *self = serde_json::from_value(Value::Object(settings)).map_err(|e| e.to_string())?;
+        Ok(shown)
+    }
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            undo_budget: 64 * 1024 * 1024,
+            undo_group_pause: 1000,
+            persistent_undo: false,
+            undo_dir: String::new(),
+            chord_timeout: 1000,
+            ignore_case: false,
+            whole_word: false,
+            regex_search: false,
+            wrap: false,
+            tabstop: 4,
+            expandtab: false,
+            side_margin: 5,
+            status_line: " {path}{modified}  {message}{=}{mode}  {line}:{column}  {lines} lines  {syntax}  {line_ending}  {encoding} "
+                .to_string(),
+            message_timeout: 3000,
+            syntax: true,
+            grammar_dir: "grammars".to_string(),
+            theme: "theme".to_string(),
+            colors: "auto".to_string(),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+// How a file's lines are ended and its text encoded, so it's written back the way it was read.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FileFormat {
+    // whether lines end in \r\n rather than \n.
+    pub crlf: bool,
+    // whether the file starts with a byte order mark.
+    pub bom: bool,
+}
+impl FileFormat {
+    // Works out a file's format from its text, and returns the text without the byte order mark. The first line ending
+    // decides it for the whole file.
+    pub fn detect(text: &str) -> (FileFormat, &str) {
+        let (bom, text) = match text.strip_prefix('\u{feff}') {
+            Some(val) => (true, val),
+            None => (false, text),
+        };
+        let crlf = text.find('\n').is_some_and(|i| text[..i].ends_with('\r'));
+        (FileFormat { crlf, bom }, text)
+    }
+    // Turns text with \n line endings back into the file's format.
+    pub fn encode(&self, text: &str) -> String {
+        let bom = if self.bom { "\u{feff}" } else { "" };
+        if self.crlf {
+            format!("{}{}", bom, text.replace('\n', "\r\n"))
+        } else {
+            format!("{}{}", bom, text)
+        }
+    }
+    pub fn line_ending(&self) -> &'static str {
+        if self.crlf {
+            "crlf"
+        } else {
+            "lf"
+        }
+    }
+    pub fn encoding(&self) -> &'static str {
+        if self.bom {
+            "utf-8-bom"
+        } else {
+            "utf-8"
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::{fs, path::Path};
+
+use crossterm::event::{KeyCode, KeyEvent};
+use grid_ui::{grid::Alignment, process::DrawProcess};
+use ignore::WalkBuilder;
+use regex::Regex;
+
+use crate::{
+    ansi, ansi_truncate,
+    search::{build_regex, replacements},
+    textbox::{centered_start, styled_line},
+    theme::Theme,
+};
+
+// A line that a project-wide search found a match on.
+pub struct GrepResult {
+    pub path: String,
+    // the line's index in the file, counting from zero.
+    pub line: usize,
+    pub text: String,
+    // where the matches are in the line, as ranges of character indexes.
+    pub matches: Vec<(usize, usize)>,
+}
+// What a key press in the results list asks for.
+pub enum GrepEvent {
+    // opens a file at a line, counting from one.
+    Open(String, usize),
+    // carries out the replacement that was waiting to be confirmed.
+    Replace(String),
+}
+// The results of searching every file under a directory, shown as a list in place of the text.
+// Files that git ignores are skipped, and so are files that aren't text.
+pub struct Grep {
+    pub pattern: String,
+    pub regex: Regex,
+    pub dir: String,
+    pub results: Vec<GrepResult>,
+    // the result the cursor is on.
+    pub selected: usize,
+    // whether the list is being shown.
+    pub open: bool,
+    // a replacement waiting to be confirmed, along with how many matches it would replace in each file.
+    pub pending: Option<(String, Vec<(String, usize)>)>,
+}
+impl Grep {
+    // Searches every file under a directory for a regular expression. Files that are open are searched as they are in
+    // the editor, rather than as they were last saved. They're given as paths along with their text.
+    pub fn search(pattern: &str, dir: &str, ignore_case: bool, open: &[(String, String)]) -> Result<Grep, String> {
+        let regex = build_regex(pattern, ignore_case)?;
+        let mut results = Vec::new();
+        for path in files(dir) {
+            let text = match read(&path, open) {
+                Some(val) => val,
+                None => continue,
+            };
+            for (line, text) in text.lines().enumerate() {
+                let matches = replacements(&regex, text, None);
+                if !matches.is_empty() {
+                    results.push(GrepResult {
+                        path: path.clone(),
+                        line,
+                        text: text.to_string(),
+                        matches: matches.into_iter().map(|(start, end, _)| (start, end)).collect(),
+                    });
+                }
+            }
+        }
+        Ok(Grep {
+            pattern: pattern.to_string(),
+            regex,
+            dir: dir.to_string(),
+            results,
+            selected: 0,
+            open: true,
+            pending: None,
+        })
+    }
+    // The files that have matches, each one once, in the order they were found.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+        for result in &self.results {
+            if paths.last() != Some(&result.path) {
+                paths.push(result.path.clone());
+            }
+        }
+        paths
+    }
+    // The lines of a file that are in the results, along with the text they had when they were searched.
+    pub fn lines(&self, path: &str) -> Vec<(usize, &str)> {
+        self.results
+            .iter()
+            .filter(|x| x.path == path)
+            .map(|x| (x.line, x.text.as_str()))
+            .collect()
+    }
+    // Counts the matches a replacement would replace in each file, and waits for it to be confirmed.
+    pub fn prepare_replace(&mut self, replacement: String) {
+        let counts = self
+            .paths()
+            .into_iter()
+            .map(|path| {
+                let count = self.results.iter().filter(|x| x.path == path).map(|x| x.matches.len()).sum();
+                (path, count)
+            })
+            .collect();
+        self.pending = Some((replacement, counts));
+        self.open = true;
+    }
+    // Replaces the matches in a file on disk. Only the matches in the results are replaced, so lines are searched one at
+    // a time just like they were to find them, and a line that has changed since then is left alone. Returns how many
+    // matches were replaced.
+    pub fn replace_in_file(&self, path: &str, replacement: &str) -> std::io::Result<usize> {
+        let text = fs::read_to_string(path)?;
+        let lines = self.lines(path);
+        let mut count = 0;
+        let mut replaced = String::with_capacity(text.len());
+        for (i, piece) in text.split_inclusive('\n').enumerate() {
+            // the line's ending is put back after it, the same way lines() takes it off.
+            let line = piece.strip_suffix('\n').map_or(piece, |x| x.strip_suffix('\r').unwrap_or(x));
+            if lines.contains(&(i, line)) {
+                count += self.regex.find_iter(line).count();
+                replaced.push_str(&self.regex.replace_all(line, replacement));
+                replaced.push_str(&piece[line.len()..]);
+            } else {
+                replaced.push_str(piece);
+            }
+        }
+        if count > 0 {
+            fs::write(path, replaced.as_bytes())?;
+        }
+        Ok(count)
+    }
+    // Handles a key press while the list is open.
+    pub fn recv_key(&mut self, k: KeyEvent) -> Option<GrepEvent> {
+        // a replacement waiting to be confirmed takes a yes or a no, and nothing else.
+        if let Some((replacement, _)) = &self.pending {
+            match k.code {
+                KeyCode::Char('y') | KeyCode::Enter => {
+                    let replacement = replacement.clone();
+                    self.pending = None;
+                    return Some(GrepEvent::Replace(replacement));
+                }
+                KeyCode::Char('n' | 'q') | KeyCode::Esc => self.pending = None,
+                _ => {}
+            }
+            return None;
+        }
+        match k.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.results.len().saturating_sub(1));
+            }
+            KeyCode::Enter => {
+                let result = self.results.get(self.selected)?;
+                self.open = false;
+                return Some(GrepEvent::Open(result.path.clone(), result.line + 1));
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.open = false,
+            _ => {}
+        }
+        None
+    }
+    // The question asked before a replacement is carried out.
+    pub fn question(&self) -> Option<String> {
+        let (replacement, counts) = self.pending.as_ref()?;
+        let total: usize = counts.iter().map(|(_, count)| count).sum();
+        Some(format!(
+            "Replace {} matches in {} files with \"{}\"? (y/n)",
+            total,
+            counts.len(),
+            replacement
+        ))
+    }
+    pub fn display(&self, d: &mut DrawProcess, headers: &mut DrawProcess, theme: &Theme) {
+        // removes all extra spaces and inserts these formatting codes at the beginning/end:
+        let mut main_strategy = ansi_truncate::AnsiTruncate::new(&theme.text, ansi::RESET);
+        let mut current_strategy = ansi_truncate::AnsiTruncate::new(&theme.current_line, ansi::RESET);
+        let mut help_strategy = ansi_truncate::AnsiTruncate::new(&theme.help, ansi::RESET);
+        let mut header_strategy = ansi_truncate::AnsiTruncate::new(&theme.gutter, ansi::RESET);
+        let height = d.height().saturating_sub(1);
+
+        // while a replacement waits to be confirmed, the list shows how many matches each file has instead.
+        if let Some((replacement, counts)) = &self.pending {
+            let _ = d.add_to_section(
+                format!("replace {} with {} - y: replace, n: cancel", self.pattern, replacement),
+                &mut help_strategy,
+                Alignment::Plus,
+            );
+            let _ = headers.add_to_section(String::new(), &mut header_strategy, Alignment::Plus);
+            for (i, (path, count)) in counts.iter().enumerate().take(height) {
+                let _ = d.add_to_section(format!("{}: {} matches", path, count), &mut main_strategy, Alignment::Plus);
+                let _ = headers.add_to_section(format!("{:-4} ", i + 1), &mut header_strategy, Alignment::Plus);
+            }
+            return;
+        }
+
+        // the first line explains the controls, and says how much was found.
+        let _ = d.add_to_section(
+            format!(
+                "grep {} - {} results in {} files - up/down: move, enter: open, esc: close",
+                self.pattern,
+                self.results.len(),
+                self.paths().len()
+            ),
+            &mut help_strategy,
+            Alignment::Plus,
+        );
+        let _ = headers.add_to_section(String::new(), &mut header_strategy, Alignment::Plus);
+
+        let start = centered_start(self.selected, self.results.len(), height);
+        for (i, result) in self.results.iter().enumerate().skip(start).take(height) {
+            // the matches are highlighted, after the path and line number.
+            let prefix = format!("{}:{}: ", result.path, result.line + 1);
+            let offset = prefix.chars().count();
+            let line = prefix.chars().chain(result.text.chars()).collect::<Vec<_>>();
+            let mut styles = vec![None; line.len()];
+            for (first, last) in &result.matches {
+                for style in &mut styles[offset + first..offset + last] {
+                    *style = Some(theme.search_match.as_str());
+                }
+            }
+            if i == self.selected {
+                let text = styled_line(&line, &styles, &theme.current_line);
+                let _ = d.add_to_section(text, &mut current_strategy, Alignment::Plus);
+            } else {
+                let text = styled_line(&line, &styles, &theme.text);
+                let _ = d.add_to_section(text, &mut main_strategy, Alignment::Plus);
+            }
+            let _ = headers.add_to_section(format!("{:-4} ", i + 1), &mut header_strategy, Alignment::Plus);
+        }
+    }
+}
+// Every file under a directory that a .gitignore doesn't leave out, in order by name. Hidden files are left out too.
+fn files(dir: &str) -> Vec<String> {
+    WalkBuilder::new(dir)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().is_some_and(|x| x.is_file()))
+        .map(|x| {
+            let path = x.path();
+            path.strip_prefix("./").unwrap_or(path).to_string_lossy().to_string()
+        })
+        .collect()
+}
+// Whether two paths lead to the same file.
+pub fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+// Reads a file, or takes its text from the editor if it's open. Files that aren't text are skipped.
+fn read(path: &str, open: &[(String, String)]) -> Option<String> {
+    if let Some((_, text)) = open.iter().find(|(open_path, _)| same_file(path, open_path)) {
+        return Some(text.clone());
+    }
+    fs::read_to_string(Path::new(path)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of files to search, made fresh for each test.
+    fn dir(name: &str, files: &[(&str, &str)]) -> String {
+        let dir = std::env::temp_dir().join(format!("textedit-grep-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Safe unwrap");
+        for (path, text) in files {
+            fs::write(dir.join(path), text).expect("Safe unwrap");
+        }
+        dir.to_string_lossy().to_string()
+    }
+
+    fn matches(grep: &Grep) -> Vec<Vec<(usize, usize)>> {
+        grep.results.iter().map(|x| x.matches.clone()).collect()
+    }
+
+    #[test]
+    fn search_by_line() {
+        let dir = dir("search", &[("a.txt", "one two\r\nthree\r\ntwo two"), ("b.txt", "nothing")]);
+        let grep = Grep::search("two", &dir, false, &[]).expect("Safe unwrap");
+        let path = format!("{}/a.txt", dir);
+        assert_eq!(grep.paths(), vec![path.clone()]);
+        assert_eq!(grep.lines(&path), vec![(0, "one two"), (2, "two two")]);
+        assert_eq!(matches(&grep), vec![vec![(4, 7)], vec![(0, 3), (4, 7)]]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn open_files_are_searched_as_they_are() {
+        let dir = dir("open", &[("a.txt", "one")]);
+        let path = format!("{}/a.txt", dir);
+        let grep = Grep::search("one", &dir, false, &[(path.clone(), "two\none".to_string())]).expect("Safe unwrap");
+        assert_eq!(grep.lines(&path), vec![(1, "one")]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn replace_what_was_found() {
+        // the pattern could match across the first newline if the file were searched as a whole, but lines are searched
+        // one at a time, so only the last line is listed, and only it is replaced.
+        let dir = dir("replace", &[("a.txt", "foo \r\nbar\r\nfoo bar\r\né foo  bar é")]);
+        let mut grep = Grep::search(r"o\s+b", &dir, false, &[]).expect("Safe unwrap");
+        let path = format!("{}/a.txt", dir);
+        assert_eq!(grep.lines(&path), vec![(2, "foo bar"), (3, "é foo  bar é")]);
+        assert_eq!(matches(&grep), vec![vec![(2, 5)], vec![(4, 8)]]);
+        grep.prepare_replace("O-B".to_string());
+        assert_eq!(grep.pending, Some(("O-B".to_string(), vec![(path.clone(), 2)])));
+        assert_eq!(grep.replace_in_file(&path, "O-B").expect("Safe unwrap"), 2);
+        // the line endings are left as they were.
+        assert_eq!(fs::read_to_string(&path).expect("Safe unwrap"), "foo \r\nbar\r\nfoO-Bar\r\né foO-Bar é");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn changed_lines_are_left_alone() {
+        let dir = dir("changed", &[("a.txt", "one\none\n")]);
+        let grep = Grep::search("(o)ne", &dir, false, &[]).expect("Safe unwrap");
+        let path = format!("{}/a.txt", dir);
+        fs::write(&path, "one\none more\n").expect("Safe unwrap");
+        assert_eq!(grep.replace_in_file(&path, "${1}ld").expect("Safe unwrap"), 1);
+        assert_eq!(fs::read_to_string(&path).expect("Safe unwrap"), "old\none more\n");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
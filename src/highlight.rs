@@ -0,0 +1,349 @@
+use std::{fs, path::Path};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{rope::Rope, theme::Theme};
+
+// The grammars that come with the editor. Grammars in the grammar directory are read as well, and take the place of these
+// if they have the same name.
+const BUILT_IN: [&str; 5] = [
+    include_str!("../grammars/rust.json"),
+    include_str!("../grammars/json.json"),
+    include_str!("../grammars/markdown.json"),
+    include_str!("../grammars/toml.json"),
+    include_str!("../grammars/shell.json"),
+];
+
+// A grammar as it's written in its file: the extensions of the files it's for, the regions that can carry on over several
+// lines (like block comments and strings), and the tokens that can't. Patterns are regular expressions, and each one has a
+// scope that decides how it's drawn.
+#[derive(Deserialize)]
+pub struct GrammarFile {
+    pub name: String,
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub regions: Vec<RegionFile>,
+    #[serde(default)]
+    pub tokens: Vec<TokenFile>,
+}
+#[derive(Deserialize)]
+pub struct RegionFile {
+    pub scope: String,
+    pub start: String,
+    pub end: String,
+    // something that can't end the region, like an escaped quote in a string.
+    #[serde(default)]
+    pub escape: Option<String>,
+}
+#[derive(Deserialize)]
+pub struct TokenFile {
+    pub scope: String,
+    // if the pattern has a group named "token", only the group is styled. The rest is just there to tell where it is.
+    pub pattern: String,
+}
+pub struct Grammar {
+    pub name: String,
+    regions: Vec<Region>,
+    tokens: Vec<Token>,
+}
+struct Region {
+    scope: String,
+    start: Regex,
+    end: Regex,
+    escape: Option<Regex>,
+}
+struct Token {
+    scope: String,
+    pattern: Regex,
+}
+// What a line starts in the middle of: the region an earlier line left open, if there is one.
+pub type LineState = Option<usize>;
+
+impl Grammar {
+    // Compiles the patterns of a grammar file.
+    pub fn compile(file: GrammarFile) -> Result<Grammar, regex::Error> {
+        let regions = file
+            .regions
+            .into_iter()
+            .map(|x| {
+                Ok(Region {
+                    scope: x.scope,
+                    start: Regex::new(&x.start)?,
+                    end: Regex::new(&x.end)?,
+                    escape: x.escape.map(|x| Regex::new(&x)).transpose()?,
+                })
+            })
+            .collect::<Result<_, regex::Error>>()?;
+        let tokens = file
+            .tokens
+            .into_iter()
+            .map(|x| Ok(Token { scope: x.scope, pattern: Regex::new(&x.pattern)? }))
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(Grammar { name: file.name, regions, tokens })
+    }
+    // Finds the grammar for a file by its extension. Grammar files in the directory are read as well as the built-in ones,
+    // and a grammar that can't be read is left out.
+    pub fn find(path: &str, dir: &str) -> Option<Grammar> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        let mut texts = BUILT_IN.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        if let Ok(entries) = fs::read_dir(dir) {
+            let mut paths = entries
+                .flatten()
+                .map(|x| x.path())
+                .filter(|x| x.extension().is_some_and(|x| x == "json"))
+                .collect::<Vec<_>>();
+            paths.sort();
+            texts.extend(paths.into_iter().filter_map(|x| fs::read_to_string(x).ok()));
+        }
+        let files = texts
+            .iter()
+            .filter_map(|x| serde_json::from_str::<GrammarFile>(x).ok())
+            .collect::<Vec<_>>();
+        // the directory's grammars come last, and one with the same name as a built-in grammar replaces it, even if it's
+        // for other extensions.
+        let index = files.iter().rposition(|x| x.extensions.iter().any(|x| x == extension))?;
+        let name = &files[index].name;
+        if files[index + 1..].iter().any(|x| &x.name == name) {
+            return None;
+        }
+        let file = files.into_iter().nth(index)?;
+        Grammar::compile(file).ok()
+    }
+    // Splits a line into styled stretches, given what the line before it left open. Returns the stretches, as character
+    // indexes into the line along with their scopes, and what this line leaves open.
+    pub fn tokenize(&self, line: &str, mut state: LineState) -> (Vec<(usize, usize, &str)>, LineState) {
+        let mut spans = Vec::new();
+        let mut pos = 0;
+        let mut region_start = 0;
+        loop {
+            // a region that's open goes on until its end, or to the end of the line if there isn't one.
+            if let Some(i) = state {
+                let region = &self.regions[i];
+                match region.close(line, pos) {
+                    Some(end) => {
+                        spans.push((region_start, end, region.scope.as_str()));
+                        state = None;
+                        pos = end;
+                    }
+                    None => {
+                        spans.push((region_start, line.len(), region.scope.as_str()));
+                        break;
+                    }
+                }
+            }
+            if pos >= line.len() {
+                break;
+            }
+            // whatever starts first comes next. Regions come before tokens, and then the one listed first.
+            let region = self
+                .regions
+                .iter()
+                .enumerate()
+                .filter_map(|(i, x)| x.start.find_at(line, pos).filter(|x| !x.is_empty()).map(|x| (x.start(), x.end(), i)))
+                .min_by_key(|x| x.0);
+            let token = self
+                .tokens
+                .iter()
+                .filter_map(|x| x.pattern.captures_at(line, pos).map(|c| (c, x.scope.as_str())))
+                .min_by_key(|(c, _)| c.get(0).map_or(0, |x| x.start()));
+            let token_start = token.as_ref().and_then(|(x, _)| x.get(0)).map(|x| x.start());
+            match (region, token) {
+                (Some((start, end, i)), _) if token_start.is_none_or(|x| start <= x) => {
+                    region_start = start;
+                    pos = end;
+                    state = Some(i);
+                }
+                (_, Some((captures, scope))) => {
+                    let whole = captures.get(0).expect("Safe unwrap");
+                    let styled = captures.name("token").unwrap_or(whole);
+                    if !styled.is_empty() {
+                        spans.push((styled.start(), styled.end(), scope));
+                    }
+                    // an empty match doesn't take anything, so the next character is skipped.
+                    pos = if whole.end() > pos {
+                        whole.end()
+                    } else {
+                        pos + line[pos..].chars().next().map_or(1, |x| x.len_utf8())
+                    };
+                }
+                _ => break,
+            }
+        }
+        // the patterns work on bytes, and the rest of the editor works on characters.
+        let mut chars = vec![0; line.len() + 1];
+        for (i, (byte, c)) in line.char_indices().enumerate() {
+            chars[byte..byte + c.len_utf8()].fill(i);
+        }
+        chars[line.len()] = line.chars().count();
+        let spans = spans.into_iter().map(|(first, last, scope)| (chars[first], chars[last], scope)).collect();
+        (spans, state)
+    }
+}
+impl Region {
+    // Where the region ends, if it ends on this line.
+    fn close(&self, line: &str, mut pos: usize) -> Option<usize> {
+        loop {
+            let end = self.end.find_at(line, pos)?;
+            let escape = self.escape.as_ref().and_then(|x| x.find_at(line, pos));
+            match escape.filter(|x| x.start() < end.start() && !x.is_empty()) {
+                Some(escape) => pos = escape.end(),
+                None => return Some(end.end()),
+            }
+        }
+    }
+}
+// Highlights a file with a grammar. It remembers what each line starts in the middle of, so that drawing the lines at the
+// bottom of a file doesn't mean going through the whole of it every time.
+pub struct Highlighter {
+    grammar: Option<Grammar>,
+    // what each line starts in, as far down as it's been worked out. The first line doesn't start in anything.
+    states: Vec<LineState>,
+}
+impl Highlighter {
+    pub fn new(grammar: Option<Grammar>) -> Highlighter {
+        Highlighter { grammar, states: vec![None] }
+    }
+    pub fn name(&self) -> Option<&str> {
+        self.grammar.as_ref().map(|x| x.name.as_str())
+    }
+    // Forgets what the lines after a changed line start in, since the change could have opened or closed a region.
+    pub fn invalidate(&mut self, line: usize) {
+        self.states.truncate(line + 1);
+    }
+    // The styled stretches of some lines, as indexes into the contents. Scopes the theme doesn't style are left out.
+    pub fn spans<'a>(&mut self, contents: &Rope, first: usize, last: usize, theme: &'a Theme) -> Vec<(usize, usize, &'a str)> {
+        let grammar = match &self.grammar {
+            Some(val) => val,
+            None => return Vec::new(),
+        };
+        let last = last.min(contents.len_lines());
+        let mut spans = Vec::new();
+        // the lines that haven't been worked out yet are gone through first, to find what the first one starts in.
+        for i in first.min(self.states.len() - 1)..last {
+            let (line_spans, state) = grammar.tokenize(&contents.line(i), self.states[i]);
+            if self.states.len() == i + 1 {
+                self.states.push(state);
+            }
+            if i >= first {
+                let start = contents.line_to_char(i);
+                spans.extend(
+                    line_spans
+                        .into_iter()
+                        .filter_map(|(x, y, scope)| theme.scope(scope).map(|style| (start + x, start + y, style))),
+                );
+            }
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::{ColorDepth, ThemeFile};
+
+    // A small grammar with a block comment, a string with escapes, and a token that only styles part of what it matches.
+    fn grammar() -> Grammar {
+        let file = serde_json::from_str(
+            r#"{
+                "name": "test",
+                "extensions": ["test"],
+                "regions": [
+                    { "scope": "comment", "start": "/\\*", "end": "\\*/" },
+                    { "scope": "string", "start": "\"", "end": "\"", "escape": "\\\\." }
+                ],
+                "tokens": [
+                    { "scope": "keyword", "pattern": "\\bfn\\b" },
+                    { "scope": "function", "pattern": "\\b(?P<token>\\w+)\\(" }
+                ]
+            }"#,
+        )
+        .expect("Safe unwrap");
+        Grammar::compile(file).expect("Safe unwrap")
+    }
+
+    // Tokenizes lines one after another, the way the highlighter does.
+    fn tokenize<'a>(grammar: &'a Grammar, lines: &[&str]) -> Vec<Vec<(usize, usize, &'a str)>> {
+        let mut state = None;
+        lines
+            .iter()
+            .map(|line| {
+                let (spans, next) = grammar.tokenize(line, state);
+                state = next;
+                spans
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        let grammar = grammar();
+        // only the name is styled, not the bracket after it.
+        assert_eq!(tokenize(&grammar, &["fn main() {"]), vec![vec![(0, 2, "keyword"), (3, 7, "function")]]);
+        assert_eq!(grammar.tokenize("nothing here", None), (vec![], None));
+    }
+
+    #[test]
+    fn regions_over_several_lines() {
+        let grammar = grammar();
+        let lines = ["a /* b", "still", "c */ fn", "/**/"];
+        assert_eq!(tokenize(&grammar, &lines), vec![
+            vec![(2, 6, "comment")],
+            vec![(0, 5, "comment")],
+            vec![(0, 4, "comment"), (5, 7, "keyword")],
+            vec![(0, 4, "comment")],
+        ]);
+        assert_eq!(grammar.tokenize("a /* b", None).1, Some(0));
+        assert_eq!(grammar.tokenize("c */ fn", Some(0)).1, None);
+        // a region's start inside another region doesn't count.
+        assert_eq!(grammar.tokenize("/* \" */ x", None), (vec![(0, 7, "comment")], None));
+    }
+
+    #[test]
+    fn escapes() {
+        let grammar = grammar();
+        assert_eq!(tokenize(&grammar, &[r#"x "a\"b" fn"#]), vec![vec![(2, 8, "string"), (9, 11, "keyword")]]);
+        // an escaped backslash doesn't escape the quote after it.
+        assert_eq!(grammar.tokenize(r#""a\\" fn"#, None), (vec![(0, 5, "string"), (6, 8, "keyword")], None));
+        // an escape at the end of a line leaves the string open.
+        assert_eq!(grammar.tokenize(r#""a\"#, None), (vec![(0, 3, "string")], Some(1)));
+        assert_eq!(grammar.tokenize(r#"\" b" fn"#, Some(1)), (vec![(0, 5, "string"), (6, 8, "keyword")], None));
+    }
+
+    #[test]
+    fn non_ascii() {
+        // the spans are in characters, not bytes.
+        let grammar = grammar();
+        assert_eq!(grammar.tokenize("é /* ü */ 日(", None), (vec![(2, 9, "comment"), (10, 11, "function")], None));
+        assert_eq!(grammar.tokenize("\"日本\" fn", None), (vec![(0, 4, "string"), (5, 7, "keyword")], None));
+        assert_eq!(grammar.tokenize("🦀 /* 🦀", None), (vec![(2, 6, "comment")], Some(0)));
+    }
+
+    #[test]
+    fn invalidate_after_opening_a_comment() {
+        let theme = Theme::new(&ThemeFile::default(), ColorDepth::TrueColor);
+        let comment = theme.scope("comment").expect("Safe unwrap");
+        let mut highlighter = Highlighter::new(Some(grammar()));
+        let mut contents = Rope::from("one\ntwo\nthree");
+        assert_eq!(highlighter.spans(&contents, 0, 3, &theme), vec![]);
+        // the line that changed is the first one, so what every line after it starts in is worked out again.
+        contents.insert(0, "/* ");
+        highlighter.invalidate(0);
+        assert_eq!(highlighter.spans(&contents, 2, 3, &theme), vec![(11, 16, comment)]);
+        assert_eq!(highlighter.spans(&contents, 0, 3, &theme), vec![(0, 6, comment), (7, 10, comment), (11, 16, comment)]);
+        // closing it on the second line ends it there.
+        contents.insert(10, " */");
+        highlighter.invalidate(1);
+        assert_eq!(highlighter.spans(&contents, 2, 3, &theme), vec![]);
+    }
+
+    #[test]
+    fn built_in_grammars() {
+        for name in ["a.rs", "a.json", "a.md", "a.toml", "a.sh"] {
+            assert!(Grammar::find(name, "").is_some(), "{}", name);
+        }
+        assert!(Grammar::find("a.unknown", "").is_none());
+        assert!(Grammar::find("Makefile", "").is_none());
+    }
+}
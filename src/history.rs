@@ -0,0 +1,780 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    rope::Rope,
+    textbox::{kind, Cursor},
+};
+
+// The number of bytes each stored edit costs, on top of the text it holds.
+pub const EDIT_OVERHEAD: usize = std::mem::size_of::<Edit>();
+
+// A single change to the contents: some text was removed at an index, and other text was inserted in its place.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Edit {
+    pub index: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+impl Edit {
+    // Whether the edit inserts text without removing any.
+    pub fn is_insertion(&self) -> bool {
+        self.removed.is_empty() && !self.inserted.is_empty()
+    }
+    // Whether the edit removes text without inserting any.
+    pub fn is_deletion(&self) -> bool {
+        self.inserted.is_empty() && !self.removed.is_empty()
+    }
+    // Applies the edit to the contents.
+    pub fn apply(&self, contents: &mut Rope) {
+        contents.remove(self.index, self.index + self.removed.chars().count());
+        contents.insert(self.index, &self.inserted);
+    }
+    // Reverts the edit, assuming it was the last thing applied to the contents.
+    pub fn revert(&self, contents: &mut Rope) {
+        contents.remove(self.index, self.index + self.inserted.chars().count());
+        contents.insert(self.index, &self.removed);
+    }
+    // The number of bytes the edit takes up in the history.
+    pub fn size(&self) -> usize {
+        EDIT_OVERHEAD + self.removed.len() + self.inserted.len()
+    }
+}
+// A series of edits that are undone and redone together, along with the cursor before and after them.
+#[derive(Serialize, Deserialize)]
+pub struct TextBoxUndos {
+    pub edits: Vec<Edit>,
+    pub before: Cursor,
+    pub after: Cursor,
+}
+impl TextBoxUndos {
+    pub fn size(&self) -> usize {
+        self.edits.iter().map(|x| x.size()).sum()
+    }
+}
+// A state in the undo tree. Every node besides the root holds the edits that lead to it from its parent.
+#[derive(Serialize, Deserialize)]
+pub struct UndoNode {
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // the child that redoing moves to. This is the child that was visited most recently.
+    pub redo: Option<usize>,
+    pub undos: TextBoxUndos,
+    // when the node was created.
+    pub time: SystemTime,
+}
+// The undo history is saved as a tree of nodes. Only the tree itself is kept between sessions.
+#[derive(Serialize, Deserialize)]
+pub struct TextBoxHistory {
+    // every state that can be returned to, keyed by ID. IDs are handed out in the order the states were created.
+    nodes: HashMap<usize, UndoNode>,
+    root: usize,
+    // the state the contents are currently in.
+    current: usize,
+    // the state the file was last saved in, if it's still in the tree.
+    saved: Option<usize>,
+    next_id: usize,
+    // edits that have been made since the last action.
+    #[serde(skip)]
+    pending: Vec<Edit>,
+    // the cursor before the first pending edit.
+    #[serde(skip)]
+    pending_cursor: Cursor,
+    // the number of groups that have begun but not ended. Actions are held off until every group has ended.
+    #[serde(skip)]
+    groups: usize,
+    // the node that typing can still be added onto, if there is one.
+    #[serde(skip)]
+    open: Option<usize>,
+    // how long a pause in typing has to be before it starts a new undo.
+    #[serde(skip)]
+    pause: Duration,
+    // the number of bytes the history may hold, and the number it currently holds.
+    #[serde(skip)]
+    budget: usize,
+    #[serde(skip)]
+    size: usize,
+}
+// A history saved to disk, along with a hash of the file's contents at the time it was saved.
+#[derive(Serialize, Deserialize)]
+pub struct SavedHistory<H> {
+    pub path: String,
+    pub hash: u64,
+    pub history: H,
+}
+impl TextBoxHistory {
+    pub fn new(budget: usize, pause: Duration) -> TextBoxHistory {
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            0,
+            UndoNode {
+                parent: None,
+                children: Vec::new(),
+                redo: None,
+                undos: TextBoxUndos {
+                    edits: Vec::new(),
+                    before: Cursor::new(),
+                    after: Cursor::new(),
+                },
+                time: SystemTime::now(),
+            },
+        );
+        TextBoxHistory {
+            nodes,
+            root: 0,
+            current: 0,
+            saved: Some(0),
+            next_id: 1,
+            pending: Vec::new(),
+            pending_cursor: Cursor::new(),
+            groups: 0,
+            open: None,
+            pause,
+            budget,
+            size: 0,
+        }
+    }
+    pub fn node(&self, id: usize) -> Option<&UndoNode> {
+        self.nodes.get(&id)
+    }
+    pub fn root(&self) -> usize {
+        self.root
+    }
+    pub fn current(&self) -> usize {
+        self.current
+    }
+    // Marks the current state as the one the file is saved in.
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current);
+    }
+    // Whether the contents have changed since the file was last saved.
+    pub fn modified(&self) -> bool {
+        self.saved != Some(self.current) || !self.pending.is_empty()
+    }
+    // Picks up the limits from the config, forgetting old changes if the history no longer fits.
+    pub fn configure(&mut self, config: &Config) {
+        self.pause = Duration::from_millis(config.undo_group_pause);
+        self.budget = config.undo_budget;
+        self.prune();
+    }
+    // Reads a history from disk, if there is one for this file and the file hasn't changed since it was written.
+    // The contents are left in the state that was saved, so the cursor from that state is returned along with it.
+    pub fn load(path: &str, hash: u64, config: &Config) -> Option<(TextBoxHistory, Cursor)> {
+        let undo_path = history_path(path, config)?;
+        let saved: SavedHistory<TextBoxHistory> =
+            serde_json::from_str(&fs::read_to_string(undo_path).ok()?).ok()?;
+        if saved.hash != hash || saved.path != absolute(path).to_string_lossy() {
+            return None;
+        }
+        let mut history = saved.history;
+        history.current = history.saved?;
+        history.size = history.nodes.values().map(|x| x.undos.size()).sum();
+        history.configure(config);
+        let cursor = history.nodes.get(&history.current)?.undos.after;
+        Some((history, cursor))
+    }
+    // Writes the history to disk, as long as the state the file was saved in is still part of it.
+    pub fn save(&mut self, path: &str, hash: u64, config: &Config, tbcu: &Cursor) -> std::io::Result<()> {
+        self.action(tbcu);
+        let undo_path = match (history_path(path, config), self.saved) {
+            (Some(val), Some(_)) => val,
+            _ => return Ok(()),
+        };
+        if let Some(parent) = undo_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let saved = SavedHistory {
+            path: absolute(path).to_string_lossy().to_string(),
+            hash,
+            history: &*self,
+        };
+        fs::write(undo_path, serde_json::to_string(&saved)?)
+    }
+    // Records an edit that has just been made. The cursor is the one from before the edit.
+    pub fn record(&mut self, edit: Edit, tbcu: &Cursor) {
+        if self.pending.is_empty() {
+            self.pending_cursor = *tbcu;
+        }
+        self.pending.push(edit);
+    }
+    // Starts a group. Everything edited until the group ends is undone in one step, even if there are actions in between.
+    pub fn begin_group(&mut self) {
+        self.groups += 1;
+    }
+    // Ends a group, turning it into one undo once every group has ended.
+    pub fn end_group(&mut self, tbcu: &Cursor) {
+        self.groups = self.groups.saturating_sub(1);
+        if self.groups == 0 && !self.pending.is_empty() {
+            self.commit(tbcu);
+            self.open = None;
+        }
+    }
+    // Groups every edit made since the last action into one undo, branching off of the current state.
+    // Runs of typing or deleting in one place are added onto the previous undo until they reach a word boundary or a pause.
+    pub fn action(&mut self, tbcu: &Cursor) {
+        if self.pending.is_empty() || self.groups > 0 {
+            return;
+        }
+        if self.pending.len() == 1 && self.coalesces(&self.pending[0]) {
+            let edit = self.pending.pop().expect("Safe unwrap");
+            self.size += edit.size();
+            let node = self.nodes.get_mut(&self.current).expect("Safe unwrap");
+            node.undos.edits.push(edit);
+            node.undos.after = *tbcu;
+            node.time = SystemTime::now();
+            self.prune();
+            return;
+        }
+        let single = self.pending.len() == 1;
+        self.commit(tbcu);
+        // only single insertions or deletions can be added onto.
+        self.open = if single { Some(self.current) } else { None };
+    }
+    // Whether an edit can be added onto the current undo.
+    fn coalesces(&self, edit: &Edit) -> bool {
+        // the saved state can't change, or it would no longer match the file.
+        if self.open != Some(self.current) || self.saved == Some(self.current) {
+            return false;
+        }
+        let node = &self.nodes[&self.current];
+        let last = match node.undos.edits.last() {
+            Some(val) => val,
+            None => return false,
+        };
+        // anything built on top of the current state would be broken by changing it.
+        if !node.children.is_empty() {
+            return false;
+        }
+        if SystemTime::now().duration_since(node.time).map(|x| x > self.pause).unwrap_or(true) {
+            return false;
+        }
+        // the characters on either side of the boundary between the two edits.
+        let (before, after) = if last.is_insertion() && edit.is_insertion() {
+            // typing continues right where it left off.
+            if edit.index != last.index + last.inserted.chars().count() {
+                return false;
+            }
+            (last.inserted.chars().last(), edit.inserted.chars().next())
+        } else if last.is_deletion() && edit.is_deletion() {
+            // backspace removes the character before the last one removed, while delete removes the one after.
+            if edit.index + edit.removed.chars().count() == last.index {
+                (last.removed.chars().next(), edit.removed.chars().last())
+            } else if edit.index == last.index {
+                (last.removed.chars().last(), edit.removed.chars().next())
+            } else {
+                return false;
+            }
+        } else {
+            return false;
+        };
+        match (before, after) {
+            (Some(before), Some(after)) => {
+                // newlines always start a new undo.
+                if before == '\n' || after == '\n' {
+                    false
+                } else {
+                    // whitespace stays with the word before it, but a change of kind otherwise starts a new word.
+                    kind(before) == kind(after) || after.is_whitespace()
+                }
+            }
+            _ => false,
+        }
+    }
+    // Turns the pending edits into a new node.
+    fn commit(&mut self, tbcu: &Cursor) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let undos = TextBoxUndos {
+            edits: std::mem::take(&mut self.pending),
+            before: self.pending_cursor,
+            after: *tbcu,
+        };
+        self.size += undos.size();
+        self.nodes.insert(
+            id,
+            UndoNode {
+                parent: Some(self.current),
+                children: Vec::new(),
+                redo: None,
+                undos,
+                time: SystemTime::now(),
+            },
+        );
+        let parent = self.nodes.get_mut(&self.current).expect("Safe unwrap");
+        parent.children.push(id);
+        parent.redo = Some(id);
+        self.current = id;
+        self.prune();
+    }
+    // Forgets the oldest parts of the tree until the history fits in its budget.
+    fn prune(&mut self) {
+        while self.size > self.budget {
+            if self.root != self.current {
+                let path = self.ancestors(self.current);
+                let root = &self.nodes[&self.root];
+                if let Some(&branch) = root.children.iter().find(|x| !path.contains(x)) {
+                    // branches that don't lead to the current state go first.
+                    self.remove_subtree(branch);
+                    continue;
+                }
+                // otherwise, the root's only child becomes the new root. The current state is kept undoable though.
+                let next = root.children[0];
+                if next != self.current {
+                    self.nodes.remove(&self.root);
+                    let node = self.nodes.get_mut(&next).expect("Safe unwrap");
+                    self.size -= node.undos.size();
+                    node.parent = None;
+                    node.undos.edits.clear();
+                    self.root = next;
+                    continue;
+                }
+            }
+            // all that's left above the current state is one undo, so the states that can be redone from it go next.
+            match self.redo_branch() {
+                Some(id) => self.remove_subtree(id),
+                // the current state's own edits are always kept, even if they don't fit.
+                None => break,
+            }
+        }
+        // if the saved state was forgotten, there's no longer a state that matches the file.
+        if let Some(saved) = self.saved {
+            if !self.nodes.contains_key(&saved) {
+                self.saved = None;
+            }
+        }
+    }
+    // The part of the tree below the current state to forget first: the oldest branch that redoing doesn't lead down, or
+    // if there isn't one, the last state that redoing leads to.
+    fn redo_branch(&self) -> Option<usize> {
+        let mut chain = vec![self.current];
+        while let Some(next) = self.nodes[chain.last().expect("Safe unwrap")].redo {
+            chain.push(next);
+        }
+        let branch = chain
+            .iter()
+            .flat_map(|id| self.nodes[id].children.iter().copied())
+            .filter(|x| !chain.contains(x))
+            .min();
+        branch.or_else(|| chain.get(1..).and_then(|x| x.last().copied()))
+    }
+    // Removes a node and everything below it.
+    fn remove_subtree(&mut self, id: usize) {
+        if let Some(parent) = self.nodes[&id].parent {
+            let parent = self.nodes.get_mut(&parent).expect("Safe unwrap");
+            parent.children.retain(|x| *x != id);
+            if parent.redo == Some(id) {
+                parent.redo = parent.children.last().copied();
+            }
+        }
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes.remove(&id) {
+                self.size -= node.undos.size();
+                stack.extend(node.children);
+            }
+        }
+    }
+    // The IDs of a node and all of the nodes above it.
+    fn ancestors(&self, mut id: usize) -> Vec<usize> {
+        let mut ancestors = vec![id];
+        while let Some(parent) = self.nodes[&id].parent {
+            ancestors.push(parent);
+            id = parent;
+        }
+        ancestors
+    }
+    // Reverts the current node's edits, moving to its parent.
+    fn step_up(&mut self, tbco: &mut Rope, tbcu: &mut Cursor) -> bool {
+        self.open = None;
+        let node = &self.nodes[&self.current];
+        if let Some(parent) = node.parent {
+            // reverts the edits in the opposite order they were made in.
+            for edit in node.undos.edits.iter().rev() {
+                edit.revert(tbco);
+            }
+            *tbcu = node.undos.before;
+            self.current = parent;
+            true
+        } else {
+            false
+        }
+    }
+    // Applies a child's edits, moving to that child.
+    fn step_down(&mut self, child: usize, tbco: &mut Rope, tbcu: &mut Cursor) {
+        self.open = None;
+        let node = &self.nodes[&child];
+        for edit in &node.undos.edits {
+            edit.apply(tbco);
+        }
+        *tbcu = node.undos.after;
+        self.nodes.get_mut(&self.current).expect("Safe unwrap").redo = Some(child);
+        self.current = child;
+    }
+    // Undoes one state. Returns whether there was one to undo.
+    pub fn undo(&mut self, tbco: &mut Rope, tbcu: &mut Cursor) -> bool {
+        self.action(tbcu);
+        self.step_up(tbco, tbcu)
+    }
+    // Redoes one state. Returns whether there was one to redo.
+    pub fn redo(&mut self, tbco: &mut Rope, tbcu: &mut Cursor) -> bool {
+        self.action(tbcu);
+        match self.nodes[&self.current].redo {
+            Some(child) => {
+                self.step_down(child, tbco, tbcu);
+                true
+            }
+            None => false,
+        }
+    }
+    // Moves to any state in the tree, undoing up to the closest common ancestor and redoing down from there.
+    pub fn goto(&mut self, target: usize, tbco: &mut Rope, tbcu: &mut Cursor) {
+        self.action(tbcu);
+        if !self.nodes.contains_key(&target) {
+            return;
+        }
+        let path = self.ancestors(target);
+        while !path.contains(&self.current) {
+            self.step_up(tbco, tbcu);
+        }
+        let common = path.iter().position(|x| *x == self.current).expect("Safe unwrap");
+        for &id in path[..common].iter().rev() {
+            self.step_down(id, tbco, tbcu);
+        }
+    }
+    // Moves to a sibling of the current state - the next branch over if forward is true, or the previous branch otherwise.
+    pub fn branch(&mut self, forward: bool, tbco: &mut Rope, tbcu: &mut Cursor) {
+        self.action(tbcu);
+        if let Some(parent) = self.nodes[&self.current].parent {
+            let siblings = &self.nodes[&parent].children;
+            let pos = siblings.iter().position(|x| *x == self.current).expect("Safe unwrap");
+            let target = if forward {
+                siblings[(pos + 1) % siblings.len()]
+            } else {
+                siblings[(pos + siblings.len() - 1) % siblings.len()]
+            };
+            self.goto(target, tbco, tbcu);
+        }
+    }
+    // Moves to the newest state that already existed a certain amount of time before the current state was made.
+    pub fn earlier(&mut self, amount: Duration, tbco: &mut Rope, tbcu: &mut Cursor) {
+        self.action(tbcu);
+        let time = self.nodes[&self.current].time;
+        let target = time.checked_sub(amount).unwrap_or(UNIX_EPOCH);
+        self.goto(self.newest_before(target), tbco, tbcu);
+    }
+    // Moves to the newest state that existed a certain amount of time after the current state was made.
+    pub fn later(&mut self, amount: Duration, tbco: &mut Rope, tbcu: &mut Cursor) {
+        self.action(tbcu);
+        let time = self.nodes[&self.current].time;
+        let target = time.checked_add(amount).unwrap_or(time);
+        self.goto(self.newest_before(target), tbco, tbcu);
+    }
+    // The newest node made at or before a given time, or the root if there isn't one.
+    fn newest_before(&self, time: SystemTime) -> usize {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.time <= time)
+            .map(|(id, _)| *id)
+            .max()
+            .unwrap_or(self.root)
+    }
+    // Lists the tree's nodes from top to bottom, along with the tree lines to draw to their left.
+    // Chains of single children are drawn in one column; only real branches get indented.
+    pub fn rows(&self) -> Vec<(usize, String)> {
+        let mut rows = Vec::new();
+        // the node, the lines to its left, and the lines to the left of the nodes below it.
+        let mut stack = vec![(self.root, String::new(), String::new())];
+        while let Some((id, first, rest)) = stack.pop() {
+            rows.push((id, first));
+            let children = &self.nodes[&id].children;
+            if children.len() == 1 {
+                stack.push((children[0], rest.clone(), rest));
+            } else {
+                // pushed in reverse, so the oldest branch is drawn first.
+                for (i, &child) in children.iter().enumerate().rev() {
+                    if i == children.len() - 1 {
+                        stack.push((child, format!("{}└─", rest), format!("{}  ", rest)));
+                    } else {
+                        stack.push((child, format!("{}├─", rest), format!("{}│ ", rest)));
+                    }
+                }
+            }
+        }
+        rows
+    }
+}
+// Hashes the contents of a file. This is FNV-1a, which gives the same result on every run and every platform.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+// The full path to a file, if it can be found.
+fn absolute(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+// Where a file's history is kept. This is either a hidden file next to it, or a file in the undo directory named after the file's path.
+fn history_path(path: &str, config: &Config) -> Option<PathBuf> {
+    if !config.persistent_undo {
+        return None;
+    }
+    let path = absolute(path);
+    if config.undo_dir.is_empty() {
+        let name = path.file_name()?.to_string_lossy();
+        Some(path.with_file_name(format!(".{}.undo", name)))
+    } else {
+        let key = content_hash(path.to_string_lossy().as_bytes());
+        Some(Path::new(&config.undo_dir).join(format!("{:016x}.undo", key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Makes an edit to the contents and records it, the way a textbox does.
+    fn edit(history: &mut TextBoxHistory, contents: &mut Rope, index: usize, removed: usize, inserted: &str) {
+        let edit = Edit {
+            index,
+            removed: contents.slice(index, index + removed),
+            inserted: inserted.to_string(),
+        };
+        edit.apply(contents);
+        history.record(edit, &Cursor::new());
+    }
+
+    // An edit that's undone on its own.
+    fn change(history: &mut TextBoxHistory, contents: &mut Rope, index: usize, removed: usize, inserted: &str) {
+        edit(history, contents, index, removed, inserted);
+        history.action(&Cursor::new());
+    }
+
+    fn history() -> TextBoxHistory {
+        TextBoxHistory::new(usize::MAX, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from("one"), Cursor::new());
+        change(&mut history, &mut contents, 3, 0, "\ntwo");
+        change(&mut history, &mut contents, 0, 3, "three");
+        assert_eq!(contents.to_string(), "three\ntwo");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one\ntwo");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one");
+        assert!(!history.modified());
+        // there's nothing left to undo.
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one");
+        history.redo(&mut contents, &mut cursor);
+        history.redo(&mut contents, &mut cursor);
+        history.redo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "three\ntwo");
+        assert!(history.modified());
+    }
+
+    #[test]
+    fn branches() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from("a"), Cursor::new());
+        change(&mut history, &mut contents, 1, 0, "\nb");
+        history.undo(&mut contents, &mut cursor);
+        // editing after an undo starts a new branch, and keeps the old one.
+        change(&mut history, &mut contents, 1, 0, "\nc");
+        assert_eq!(history.node(history.root()).expect("Safe unwrap").children.len(), 2);
+        // redoing goes down the newest branch.
+        history.undo(&mut contents, &mut cursor);
+        history.redo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a\nc");
+        history.branch(false, &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a\nb");
+        // the branch that was visited last is the one redone.
+        history.undo(&mut contents, &mut cursor);
+        history.redo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a\nb");
+        history.branch(true, &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a\nc");
+    }
+
+    #[test]
+    fn groups_are_one_undo() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from(""), Cursor::new());
+        history.begin_group();
+        change(&mut history, &mut contents, 0, 0, "one");
+        change(&mut history, &mut contents, 3, 0, "\ntwo");
+        history.end_group(&cursor);
+        assert_eq!(contents.to_string(), "one\ntwo");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "");
+    }
+
+    #[test]
+    fn typing_coalesces_by_kind() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from(""), Cursor::new());
+        // a word and the spaces after it are one undo, and so are the dots after that.
+        for (i, c) in "ab  ..".chars().enumerate() {
+            change(&mut history, &mut contents, i, 0, &c.to_string());
+        }
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "ab  ");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "");
+    }
+
+    #[test]
+    fn deleting_coalesces() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from(""), Cursor::new());
+        change(&mut history, &mut contents, 0, 0, "abc def");
+        // backspacing from the end of the word, then deleting forwards from the start of it.
+        change(&mut history, &mut contents, 6, 1, "");
+        change(&mut history, &mut contents, 5, 1, "");
+        change(&mut history, &mut contents, 0, 1, "");
+        assert_eq!(contents.to_string(), "bc d");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "abc d");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "abc def");
+    }
+
+    #[test]
+    fn newlines_break_groups() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from(""), Cursor::new());
+        for (i, c) in "ab\ncd".chars().enumerate() {
+            change(&mut history, &mut contents, i, 0, &c.to_string());
+        }
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "ab\n");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "ab");
+    }
+
+    #[test]
+    fn pauses_break_groups() {
+        let mut history = TextBoxHistory::new(usize::MAX, Duration::from_millis(1));
+        let (mut contents, mut cursor) = (Rope::from(""), Cursor::new());
+        change(&mut history, &mut contents, 0, 0, "a");
+        std::thread::sleep(Duration::from_millis(20));
+        change(&mut history, &mut contents, 1, 0, "b");
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a");
+    }
+
+    #[test]
+    fn earlier_and_later() {
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from(""), Cursor::new());
+        change(&mut history, &mut contents, 0, 0, "one\n");
+        change(&mut history, &mut contents, 4, 0, "two\n");
+        history.undo(&mut contents, &mut cursor);
+        change(&mut history, &mut contents, 4, 0, "three\n");
+        // the states were made a minute apart, in the order of their IDs.
+        let start = UNIX_EPOCH + Duration::from_secs(1000);
+        for (id, node) in history.nodes.iter_mut() {
+            node.time = start + Duration::from_secs(60 * *id as u64);
+        }
+        history.earlier(Duration::from_secs(60), &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one\ntwo\n");
+        history.earlier(Duration::from_secs(90), &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "");
+        history.later(Duration::from_secs(120), &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one\ntwo\n");
+        history.later(Duration::from_secs(3600), &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one\nthree\n");
+    }
+
+    #[test]
+    fn prune_forgets_other_branches_first() {
+        let mut history = TextBoxHistory::new(3 * (EDIT_OVERHEAD + 1), Duration::from_secs(60));
+        let (mut contents, mut cursor) = (Rope::from(""), Cursor::new());
+        change(&mut history, &mut contents, 0, 0, "\n");
+        history.undo(&mut contents, &mut cursor);
+        change(&mut history, &mut contents, 0, 0, "a");
+        change(&mut history, &mut contents, 1, 0, "\n");
+        change(&mut history, &mut contents, 2, 0, "b");
+        change(&mut history, &mut contents, 3, 0, "\n");
+        // the first branch went, and then the oldest undo on the path to the current state.
+        assert!(history.size <= history.budget);
+        history.undo(&mut contents, &mut cursor);
+        history.undo(&mut contents, &mut cursor);
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a");
+        assert_eq!(history.current(), history.root());
+        // the state the file was saved in is gone, so there's nothing to match it anymore.
+        assert!(history.modified());
+    }
+
+    #[test]
+    fn prune_forgets_redos() {
+        let mut history = TextBoxHistory::new(usize::MAX, Duration::from_secs(60));
+        let (mut contents, mut cursor) = (Rope::from(""), Cursor::new());
+        change(&mut history, &mut contents, 0, 0, "a\n");
+        change(&mut history, &mut contents, 2, 0, "b\n");
+        change(&mut history, &mut contents, 4, 0, "c\n");
+        history.undo(&mut contents, &mut cursor);
+        change(&mut history, &mut contents, 4, 0, "d\n");
+        history.undo(&mut contents, &mut cursor);
+        history.undo(&mut contents, &mut cursor);
+        // only the first undo fits, so every state below it has to go, even though they could be redone.
+        history.budget = EDIT_OVERHEAD + 2;
+        history.prune();
+        assert!(history.size <= history.budget);
+        let current = history.node(history.current()).expect("Safe unwrap");
+        assert!(current.children.is_empty() && current.redo.is_none());
+        history.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "");
+    }
+
+    #[test]
+    fn prune_keeps_the_redo_path_longest() {
+        let mut history = TextBoxHistory::new(usize::MAX, Duration::from_secs(60));
+        let (mut contents, mut cursor) = (Rope::from(""), Cursor::new());
+        change(&mut history, &mut contents, 0, 0, "a\n");
+        change(&mut history, &mut contents, 2, 0, "b\n");
+        history.undo(&mut contents, &mut cursor);
+        change(&mut history, &mut contents, 2, 0, "c\n");
+        history.undo(&mut contents, &mut cursor);
+        history.undo(&mut contents, &mut cursor);
+        history.redo(&mut contents, &mut cursor);
+        // the "b" branch isn't where redoing leads, so it goes before the "c" one does.
+        history.budget = 2 * (EDIT_OVERHEAD + 2);
+        history.prune();
+        history.redo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a\nc\n");
+        history.branch(true, &mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "a\nc\n");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("textedit-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Safe unwrap");
+        let path = dir.join("file.txt").to_string_lossy().to_string();
+        fs::write(&path, "one\ntwo").expect("Safe unwrap");
+        let config = Config {
+            persistent_undo: true,
+            undo_dir: dir.join("undo").to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let (mut history, mut contents, mut cursor) = (history(), Rope::from("one"), Cursor::new());
+        change(&mut history, &mut contents, 3, 0, "\ntwo");
+        history.mark_saved();
+        history.save(&path, 42, &config, &cursor).expect("Safe unwrap");
+
+        let (mut loaded, _) = TextBoxHistory::load(&path, 42, &config).expect("Safe unwrap");
+        assert!(!loaded.modified());
+        loaded.undo(&mut contents, &mut cursor);
+        assert_eq!(contents.to_string(), "one");
+        // the file changed since the history was saved, so the history no longer fits it.
+        assert!(TextBoxHistory::load(&path, 43, &config).is_none());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::SystemTime;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use grid_ui::{grid::Alignment, process::DrawProcess};
+
+use crate::{
+    ansi, ansi_truncate,
+    history::{TextBoxHistory, UndoNode},
+    rope::Rope,
+    textbox::{centered_start, Cursor},
+    theme::Theme,
+};
+
+// A view of the undo tree that replaces the text while it's open.
+// Moving around the tree moves the contents to that state right away, so the text underneath is always up to date.
+pub struct HistoryBrowser {
+    // a number being typed in, for jumping back or forward in time.
+    pub input: String,
+}
+impl HistoryBrowser {
+    pub fn new() -> HistoryBrowser {
+        HistoryBrowser {
+            input: String::new(),
+        }
+    }
+    // Handles a key press. Returns false if the browser should close.
+    pub fn recv_key(
+        &mut self,
+        k: KeyEvent,
+        history: &mut TextBoxHistory,
+        tbco: &mut Rope,
+        tbcu: &mut Cursor,
+    ) -> bool {
+        let KeyEvent { code, modifiers } = k;
+        match code {
+            // moves to the state drawn above or below the current one.
+            KeyCode::Up | KeyCode::Down => {
+                let rows = history.rows();
+                let pos = rows
+                    .iter()
+                    .position(|(id, _)| *id == history.current())
+                    .expect("Safe unwrap");
+                let target = if code == KeyCode::Up {
+                    pos.checked_sub(1)
+                } else {
+                    Some(pos + 1).filter(|x| *x < rows.len())
+                };
+                if let Some(target) = target {
+                    history.goto(rows[target].0, tbco, tbcu);
+                }
+            }
+            // moves between sibling branches.
+            KeyCode::Left => history.branch(false, tbco, tbcu),
+            KeyCode::Right => history.branch(true, tbco, tbcu),
+            // the digits of a number of seconds or minutes.
+            KeyCode::Char(c) if c.is_ascii_digit() => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            // jumps back (lowercase) or forward (uppercase) by the number of seconds or minutes typed in.
+            KeyCode::Char(c @ ('s' | 'S' | 'm' | 'M')) => {
+                let amount = self.input.parse::<u64>().unwrap_or(1);
+                let seconds = if c.eq_ignore_ascii_case(&'m') { amount.saturating_mul(60) } else { amount };
+                let duration = std::time::Duration::from_secs(seconds);
+                if c.is_ascii_lowercase() {
+                    history.earlier(duration, tbco, tbcu);
+                } else {
+                    history.later(duration, tbco, tbcu);
+                }
+                self.input.clear();
+            }
+            KeyCode::Char('u' | 'U') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Esc | KeyCode::Enter => return false,
+            _ => {}
+        }
+        true
+    }
+    pub fn display(&self, history: &TextBoxHistory, d: &mut DrawProcess, headers: &mut DrawProcess, theme: &Theme) {
+        // removes all extra spaces and inserts these formatting codes at the beginning/end:
+        let mut main_strategy = ansi_truncate::AnsiTruncate::new(&theme.text, ansi::RESET);
+        let mut current_strategy = ansi_truncate::AnsiTruncate::new(&theme.current_line, ansi::RESET);
+        let mut help_strategy = ansi_truncate::AnsiTruncate::new(&theme.help, ansi::RESET);
+        let mut header_strategy = ansi_truncate::AnsiTruncate::new(&theme.gutter, ansi::RESET);
+
+        // the first line explains the controls, and shows the number being typed.
+        let _ = d.add_to_section(
+            format!(
+                "undo tree - up/down: move, left/right: branch, <n>s/<n>m: back, <n>S/<n>M: forward, esc: close {}",
+                self.input
+            ),
+            &mut help_strategy,
+            Alignment::Plus,
+        );
+        let _ = headers.add_to_section(String::new(), &mut header_strategy, Alignment::Plus);
+
+        let rows = history.rows();
+        let height = d.height().saturating_sub(1);
+        let current = rows
+            .iter()
+            .position(|(id, _)| *id == history.current())
+            .expect("Safe unwrap");
+        let start = centered_start(current, rows.len(), height);
+        let now = SystemTime::now();
+        for (id, lines) in rows.iter().skip(start).take(height) {
+            let node = history.node(*id).expect("Safe unwrap");
+            let marker = if *id == history.current() { "●" } else { "○" };
+            let text = format!("{}{} {}", lines, marker, describe(node, *id == history.root(), now));
+            if *id == history.current() {
+                let _ = d.add_to_section(text, &mut current_strategy, Alignment::Plus);
+            } else {
+                let _ = d.add_to_section(text, &mut main_strategy, Alignment::Plus);
+            }
+            let _ = headers.add_to_section(format!("{:-4} ", id), &mut header_strategy, Alignment::Plus);
+        }
+    }
+}
+// Describes a node: how long ago it was made, and how much it changed.
+fn describe(node: &UndoNode, root: bool, now: SystemTime) -> String {
+    let seconds = now.duration_since(node.time).map(|x| x.as_secs()).unwrap_or(0);
+    let age = if seconds < 60 {
+        format!("{}s ago", seconds)
+    } else if seconds < 60 * 60 {
+        format!("{}m ago", seconds / 60)
+    } else {
+        format!("{}h ago", seconds / (60 * 60))
+    };
+    if root {
+        format!("{}  original", age)
+    } else {
+        let inserted: usize = node.undos.edits.iter().map(|x| x.inserted.chars().count()).sum();
+        let removed: usize = node.undos.edits.iter().map(|x| x.removed.chars().count()).sum();
+        format!("{}  +{} -{}", age, inserted, removed)
+    }
+}
@@ -1,95 +1,288 @@
+use std::time::{Duration, Instant};
+
 use crossterm::event::KeyEvent;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::action::Output;
 
 #[derive(Serialize, Deserialize)]
 pub struct KeyLevels {
     pub levels: Vec<KeyLevel>,
+    // keys that might be the start of a sequence, held back until the sequence is finished or abandoned.
+    #[serde(skip)]
+    pub pending: Vec<KeyEvent>,
+    // when the first pending key was pressed.
+    #[serde(skip)]
+    pub pending_since: Option<Instant>,
+    // how long to wait for the rest of a sequence before letting the pending keys through.
+    #[serde(skip)]
+    pub timeout: Duration,
 }
 impl KeyLevels {
-    pub fn map_keys(&self, event: KeyEvent, current_mode: Mode) -> Vec<KeyEvent> {
-        let mut queue = Vec::new();
-        queue.push(event);
+    pub fn map_keys(&mut self, event: KeyEvent, current_mode: Mode) -> Vec<Output> {
+        let mut rest = std::mem::take(&mut self.pending);
+        rest.push(event);
+        let mut keys = Vec::new();
+        // goes through the keys from the front, until the rest of them could still become a sequence.
+        while !rest.is_empty() && !self.is_prefix(&rest, current_mode) {
+            // a finished sequence goes through all at once, and anything else goes through one key at a time.
+            let len = self.longest_match(&rest, current_mode).max(1);
+            keys.extend(rest.drain(..len));
+        }
+        if rest.is_empty() {
+            self.pending_since = None;
+        } else if !keys.is_empty() || self.pending_since.is_none() {
+            self.pending_since = Some(Instant::now());
+        }
+        self.pending = rest;
+        self.map_sequence(keys, current_mode)
+    }
+    // How long until the pending keys time out, if there are any.
+    pub fn time_left(&self) -> Option<Duration> {
+        self.pending_since
+            .map(|x| self.timeout.saturating_sub(x.elapsed()))
+    }
+    // Lets the pending keys through, since the rest of their sequence never came.
+    pub fn flush(&mut self, current_mode: Mode) -> Vec<Output> {
+        self.pending_since = None;
+        let keys = std::mem::take(&mut self.pending);
+        self.map_sequence(keys, current_mode)
+    }
+    // Whether the keys are the start of some longer sequence.
+    pub fn is_prefix(&self, keys: &[KeyEvent], current_mode: Mode) -> bool {
+        self.levels.iter().any(|level| {
+            level.recurse.iter().chain(level.fall.iter()).any(|line| {
+                line.mode_req.matches(current_mode)
+                    && line.pattern.len() > keys.len()
+                    && line.pattern.starts_with(keys)
+            })
+        })
+    }
+    // The length of the longest sequence that the keys start with.
+    pub fn longest_match(&self, keys: &[KeyEvent], current_mode: Mode) -> usize {
+        self.levels
+            .iter()
+            .flat_map(|level| level.recurse.iter().chain(level.fall.iter()))
+            .filter(|line| line.mode_req.matches(current_mode) && keys.starts_with(&line.pattern))
+            .map(|line| line.pattern.len())
+            .max()
+            .unwrap_or(0)
+    }
+    // Replaces the keys according to each level in turn.
+    pub fn map_sequence(&self, keys: Vec<KeyEvent>, current_mode: Mode) -> Vec<Output> {
+        let mut queue = keys.into_iter().map(Output::Key).collect::<Vec<Output>>();
         // iterates through each level
         for level in &self.levels {
             // a loop because of the recursion portion of the level
             loop {
-                // if the flag is still equal to false, the loop will end.
-                let mut flag = false;
                 // moves the queue over to a new vector
-                let new_queue = std::mem::replace(&mut queue, Vec::new());
-                // iterates through the queue
-                for item in new_queue {
-                    let mut replaced = false;
-                    // iterates through the possible replacements
-                    for line in &level.recurse {
-                        // if there is a replacement...
-                        if line.mode_req.matches(current_mode) && line.pattern == item {
-                            // adds the replacement
-                            queue.append(&mut line.result.clone());
-                            flag = true;
-                            replaced = true;
-                            break;
-                        }
-                    }
-                    // If there hasn't been a replacement, add the item, unaltered.
-                    if !replaced {
-                        queue.push(item);
-                    }
-                }
+                let (new_queue, flag) = level.replace(std::mem::take(&mut queue), &level.recurse, current_mode);
+                queue = new_queue;
                 // if it's left unaltered, break.
                 if !flag {
                     break;
                 }
             }
-            // moves the queue over to a new vector
-            let new_queue = std::mem::replace(&mut queue, Vec::new());
-            for item in new_queue {
-                let mut replaced: bool = false;
-                for line in &level.fall {
-                    // if there is a replacement...
-                    if line.mode_req.matches(current_mode) && line.pattern == item {
-                        // adds the replacement
-                        queue.append(&mut line.result.clone());
-                        replaced = true;
-                        break;
-                    }
-                }
-                if !replaced {
-                    // if there's no replacement, pushes the item on, unaltered.
-                    queue.push(item);
-                }
-            }
+            queue = level.replace(std::mem::take(&mut queue), &level.fall, current_mode).0;
         }
-        return queue;
+        queue
     }
 }
-
 #[derive(Serialize, Deserialize)]
 pub struct KeyLevel {
     pub recurse: Vec<KeyMap>,
     pub fall: Vec<KeyMap>,
 }
+impl KeyLevel {
+    // Goes through the queue once, replacing any sequences of keys that match one of the lines. Actions are left alone.
+    // Returns the new queue, and whether anything was replaced.
+    pub fn replace(&self, queue: Vec<Output>, lines: &[KeyMap], current_mode: Mode) -> (Vec<Output>, bool) {
+        let mut result = Vec::new();
+        let mut replaced = false;
+        let mut i = 0;
+        while i < queue.len() {
+            // if there's more than one replacement, the longest sequence wins, and then the one listed first.
+            let line = lines
+                .iter()
+                .rev()
+                .filter(|line| {
+                    !line.pattern.is_empty()
+                        && line.mode_req.matches(current_mode)
+                        && starts_with(&queue[i..], &line.pattern)
+                })
+                .max_by_key(|line| line.pattern.len());
+            if let Some(line) = line {
+                // adds the replacement
+                result.extend(line.result.iter().copied());
+                replaced = true;
+                i += line.pattern.len();
+            } else {
+                // If there hasn't been a replacement, add the item, unaltered.
+                result.push(queue[i]);
+                i += 1;
+            }
+        }
+        (result, replaced)
+    }
+}
 #[derive(Serialize, Deserialize)]
 pub struct KeyMap {
-    pub pattern: KeyEvent,
-    pub result: Vec<KeyEvent>,
+    // the keys that have to be pressed, in order. A single key can be written on its own.
+    #[serde(deserialize_with = "one_or_many")]
+    pub pattern: Vec<KeyEvent>,
+    // the keys and actions the pattern is replaced with.
+    pub result: Vec<Output>,
     pub mode_req: ModeReq,
 }
+// Whether the queue starts with the given keys.
+fn starts_with(queue: &[Output], pattern: &[KeyEvent]) -> bool {
+    queue.len() >= pattern.len() && queue.iter().zip(pattern).all(|(a, b)| *a == Output::Key(*b))
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(KeyEvent),
+    Many(Vec<KeyEvent>),
+}
+// Reads either a single key or a sequence of keys.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<KeyEvent>, D::Error> {
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(val) => vec![val],
+        OneOrMany::Many(val) => val,
+    })
+}
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum ModeReq {
     Any = 0,
     Insert = 1,
     Command = 2,
+    Visual = 3,
+    VisualLine = 4,
+    VisualBlock = 5,
 }
 impl ModeReq {
     pub fn matches(&self, mode: Mode) -> bool {
         (*self as u8) == 0 || (*self as u8) == (mode as u8)
     }
 }
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Mode {
     Insert = 1,
     Command = 2,
+    Visual = 3,
+    VisualLine = 4,
+    VisualBlock = 5,
 }
+impl Mode {
+    // Whether this is one of the modes where motions extend a selection.
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine | Mode::VisualBlock)
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Insert => "INSERT",
+            Mode::Command => "COMMAND",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+            Mode::VisualBlock => "VISUAL BLOCK",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyCode;
+
+    use super::*;
+    use crate::action::Action;
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::from(KeyCode::Char(c))
+    }
+
+    fn keys(s: &str) -> Vec<KeyEvent> {
+        s.chars().map(key).collect()
+    }
+
+    // A key map with "jk" for escape, and "g" and "gg" for two different motions.
+    fn levels() -> KeyLevels {
+        let line = |pattern: &str, result: Output, mode_req| KeyMap {
+            pattern: keys(pattern),
+            result: vec![result],
+            mode_req,
+        };
+        KeyLevels {
+            levels: vec![KeyLevel {
+                recurse: Vec::new(),
+                fall: vec![
+                    line("jk", Output::Key(KeyEvent::from(KeyCode::Esc)), ModeReq::Insert),
+                    line("g", Output::Action(Action::MoveWordRight), ModeReq::Command),
+                    line("gg", Output::Action(Action::MoveUp), ModeReq::Command),
+                ],
+            }],
+            pending: Vec::new(),
+            pending_since: None,
+            timeout: Duration::from_secs(60),
+        }
+    }
 
+    // Feeds keys in one at a time, and collects everything that comes out.
+    fn press(levels: &mut KeyLevels, s: &str, mode: Mode) -> Vec<Output> {
+        keys(s).into_iter().flat_map(|x| levels.map_keys(x, mode)).collect()
+    }
+
+    #[test]
+    fn sequence() {
+        let mut levels = levels();
+        assert_eq!(press(&mut levels, "j", Mode::Insert), vec![]);
+        assert!(levels.time_left().is_some());
+        assert_eq!(press(&mut levels, "k", Mode::Insert), vec![Output::Key(KeyEvent::from(KeyCode::Esc))]);
+        assert!(levels.pending.is_empty() && levels.time_left().is_none());
+    }
 
+    #[test]
+    fn prefix_then_other_key() {
+        // both keys come out, in the order they were pressed.
+        let mut levels = levels();
+        assert_eq!(press(&mut levels, "jx", Mode::Insert), vec![Output::Key(key('j')), Output::Key(key('x'))]);
+        // the second key can start a sequence of its own.
+        assert_eq!(press(&mut levels, "jjk", Mode::Insert), vec![
+            Output::Key(key('j')),
+            Output::Key(KeyEvent::from(KeyCode::Esc))
+        ]);
+        assert!(levels.pending.is_empty());
+    }
+
+    #[test]
+    fn timeout_flushes() {
+        let mut levels = levels();
+        levels.timeout = Duration::ZERO;
+        assert_eq!(press(&mut levels, "j", Mode::Insert), vec![]);
+        assert_eq!(levels.time_left(), Some(Duration::ZERO));
+        assert_eq!(levels.flush(Mode::Insert), vec![Output::Key(key('j'))]);
+        assert!(levels.pending.is_empty() && levels.time_left().is_none());
+        // the sequence starts over after the flush.
+        assert_eq!(press(&mut levels, "k", Mode::Insert), vec![Output::Key(key('k'))]);
+    }
+
+    #[test]
+    fn binding_that_starts_another() {
+        let mut levels = levels();
+        // the longer binding wins when it's finished.
+        assert_eq!(press(&mut levels, "gg", Mode::Command), vec![Output::Action(Action::MoveUp)]);
+        // the shorter one is used when something else comes after it, or when it times out.
+        assert_eq!(press(&mut levels, "gx", Mode::Command), vec![
+            Output::Action(Action::MoveWordRight),
+            Output::Key(key('x'))
+        ]);
+        assert_eq!(press(&mut levels, "g", Mode::Command), vec![]);
+        assert_eq!(levels.flush(Mode::Command), vec![Output::Action(Action::MoveWordRight)]);
+    }
+
+    #[test]
+    fn other_modes_pass_through() {
+        let mut levels = levels();
+        assert_eq!(press(&mut levels, "jk", Mode::Command), vec![Output::Key(key('j')), Output::Key(key('k'))]);
+        assert!(levels.pending.is_empty());
+    }
+}
@@ -1,114 +1,184 @@
+mod action;
+mod command_line;
 #[allow(dead_code)]
 mod ansi;
 mod ansi_truncate;
+mod buffers;
+mod config;
 #[allow(dead_code)]
 mod debug;
+mod format;
+mod grep;
+mod highlight;
+mod history;
+mod history_browser;
 mod keymap;
+mod panes;
+mod render;
+mod rope;
+mod screen;
+mod search;
+mod tabs;
+mod theme;
 mod textbox;
 
-use std::fs;
+use std::{fs, time::Duration};
+use buffers::{Buffers, Request};
+use command_line::{CommandLine, Message};
+use config::Config;
 use crossterm::{
-    event::Event,
+    event::{DisableMouseCapture, EnableMouseCapture, Event, MouseButton, MouseEventKind},
+    execute,
     terminal::{self, disable_raw_mode, enable_raw_mode},
 };
-use grid_ui::grid::{Alignment, DividerStrategy, Frame, SplitStrategy};
+use grep::Grep;
+use grid_ui::grid::Frame;
 use keymap::{KeyLevels, Mode};
-use textbox::TextBox;
+use search::Search;
+use tabs::Tabs;
+use theme::Theme;
 
 fn main() -> std::io::Result<()> {
     let mut args = std::env::args();
     // discards the first arg (path to the program).
     let _ = args.next();
-    // if there is a next argument...
     let map: KeyLevels =
-        serde_json::from_str(&fs::read_to_string("map").unwrap_or("{[]}".to_string()))
+        serde_json::from_str(&fs::read_to_string("map").unwrap_or_else(|_| r#"{"levels": []}"#.to_string()))
             .expect("Invalid json scheme!");
-    if let Some(val) = args.next() {
-        open(&val, map)?;
+    // every other argument is a file to open.
+    let paths = args.collect::<Vec<_>>();
+    if !paths.is_empty() {
+        open(&paths, map)?;
     } else {
         // error message
-        println!("Usage: cargo run <path> or program <path>");
+        println!("Usage: cargo run <path>... or program <path>...");
     }
     Ok(())
 }
-pub const HEADER_SIZE: usize = 5;
 pub struct State {
     pub mode: Mode,
     pub will_quit: bool,
+    pub config: Config,
+    // the escape codes everything is drawn with.
+    pub theme: Theme,
+    pub command_line: CommandLine,
+    // what to show on the bottom line in place of the mode, until the next key press.
+    pub message: Option<Message>,
+    // the last search that was entered, and whether its matches are highlighted.
+    pub search: Option<Search>,
+    pub highlight_search: bool,
+    // the results of the last project-wide search.
+    pub grep: Option<Grep>,
+    // something the buffers have to carry out, since it has to do with more than one of them.
+    pub request: Option<Request>,
+    // whether closing a buffer with unsaved changes is waiting to be confirmed.
+    pub confirm_close: bool,
 }
 impl State {
     pub fn new() -> State {
+        let config = Config::load();
         State {
             mode: Mode::Command,
             will_quit: false,
+            theme: Theme::load(&config).expect("Invalid theme file!"),
+            config,
+            command_line: CommandLine::new(),
+            message: None,
+            search: None,
+            highlight_search: false,
+            grep: None,
+            request: None,
+            confirm_close: false,
         }
     }
 }
-fn open(p: &str, keymap: KeyLevels) -> std::io::Result<()> {
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// Waits for the next event. If keys are waiting on the rest of a sequence, or a message is waiting to go away, this gives
+// up once they time out.
+fn next_event(keymap: &KeyLevels, message_left: Option<Duration>) -> std::io::Result<Option<Event>> {
+    if let Some(time_left) = keymap.time_left().into_iter().chain(message_left).min() {
+        if !crossterm::event::poll(time_left)? {
+            return Ok(None);
+        }
+    }
+    crossterm::event::read().map(Some)
+}
+fn open(paths: &[String], mut keymap: KeyLevels) -> std::io::Result<()> {
     // initializes the state
     let mut state = State::new();
+    keymap.timeout = Duration::from_millis(state.config.chord_timeout);
 
     // gets the terminal's size
     let (x_max, y_max) = terminal::size()?;
     let mut f = Frame::new(0, 0, x_max as usize, y_max as usize);
 
-    // reads the file to a string
-    let mut file = std::fs::read_to_string(p)
-        .unwrap_or_else(|_| String::new()) // reads the file into a string
-        .lines() // splits into lines
-        .map(|x| x.to_string()) // converts each line into a string
-        .collect::<Vec<String>>(); // collects it into a vector
-
-    // adds a newline to the file if it's empty.
-    if file.len() == 0 {
-        file.push(String::new());
-    }
-
-    // creates a textbox out of the file's output
-    let mut text_box = TextBox::new(file, p.to_string());
+    // opens every file, each in a buffer of its own.
+    let mut buffers = Buffers::new(paths, &state.config);
+    // the screen starts out as one tab with one pane, showing the first file.
+    let mut tabs = Tabs::new(&mut buffers);
 
-    // enables raw mode for the terminal
+    // enables raw mode for the terminal, and clicks on the tab bar.
     enable_raw_mode()?;
+    execute!(std::io::stdout(), EnableMouseCapture)?;
 
-    // creates grid that represents the terminal
-    let mut grid = f.next_frame();
-
-    // splits the grid off into a headers section and initializes it into a draw process.
-    let mut line_nums = grid
-        .split(&SplitStrategy::new().max_x(HEADER_SIZE, Alignment::Minus))
-        .expect("Terminal too small!") // if the terminal is too small, it will panic
-        .into_process(DividerStrategy::Beginning); // lines are drawn from the top.
-
-    // the remainder of the grid is the main section. Creates a draw process out of this.
-    let mut d = grid.into_process(DividerStrategy::Beginning);
     // displays it for the first time.
-    text_box.display(&mut d, &mut line_nums);
-    'outer: while let Ok(val) = crossterm::event::read() {
-        // If a key is pressed...
-        if let Event::Key(val) = val {
-            // handle this key.
-            for i in keymap.map_keys(val, state.mode) {
-                text_box.recv_key(i, &mut d, &mut line_nums, &mut state);
+    tabs.display(&mut buffers, &f, &state);
+    'outer: loop {
+        let message_left = buffers.message_time_left(Duration::from_millis(state.config.message_timeout));
+        let val = match next_event(&keymap, message_left) {
+            Ok(val) => val,
+            Err(_) => break,
+        };
+        // If a key is pressed, or the keys waiting on the rest of a sequence time out...
+        if matches!(val, None | Some(Event::Key(_))) {
+            // messages only last until the next key press.
+            if val.is_some() {
+                state.message = None;
+            }
+            let keys = if let Some(Event::Key(val)) = val {
+                keymap.map_keys(val, state.mode)
+            } else {
+                keymap.flush(state.mode)
+            };
+            // handle these keys.
+            for i in keys {
+                buffers.current().recv(i, &mut state);
+                // anything that has to do with the other buffers, the panes, or the tabs, is carried out here.
+                if let Some(request) = state.request.take() {
+                    tabs.handle(request, &mut buffers, &mut state, &f);
+                }
                 if state.will_quit {
+                    buffers.save_histories(&state.config);
                     break 'outer;
                 }
+                tabs.display(&mut buffers, &f, &state);
+            }
+            // a message on a status line may have timed out.
+            if val.is_none() {
+                tabs.display(&mut buffers, &f, &state);
             }
+            // the timeout may have been changed from the command line.
+            keymap.timeout = Duration::from_millis(state.config.chord_timeout);
         // If the screen is resized...
-        } else if let Event::Resize(x, y) = val {
+        } else if let Some(Event::Resize(x, y)) = val {
             // resizes the frame based on the new terminal size
             f.resize(0, 0, x.into(), y.into());
-            // creates a grid based on that frame
-            grid = f.next_frame();
-            // splits the grid off once again
-            line_nums = grid
-                .split(&SplitStrategy::new().max_x(HEADER_SIZE, Alignment::Minus))
-                .expect("Terminal too small!")
-                .into_process(DividerStrategy::Beginning);
-            // reinitializes the draw process.
-            d = grid.into_process(DividerStrategy::Beginning);
+            // lays the panes out again to fit.
+            tabs.display(&mut buffers, &f, &state);
+        // If a tab's label is clicked on...
+        } else if let Some(Event::Mouse(event)) = val {
+            if event.kind == MouseEventKind::Down(MouseButton::Left) && event.row == 0 {
+                tabs.click(event.column.into(), &mut buffers, &mut state);
+                tabs.display(&mut buffers, &f, &state);
+            }
         }
     }
     // disables raw mode for the terminal
+    execute!(std::io::stdout(), DisableMouseCapture)?;
     disable_raw_mode()?;
     Ok(())
 }
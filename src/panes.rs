@@ -0,0 +1,453 @@
+use std::collections::BTreeMap;
+
+use grid_ui::grid::{Frame, Grid};
+
+use crate::{
+    buffers::{Buffers, Request},
+    command_line::{Command, Message},
+    keymap::Mode,
+    screen::{self, Screen, HEADER_SIZE},
+    textbox::View,
+    State,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+// How the screen is shared out between the panes. A split gives part of its space to the first pane (or panes), and
+// the rest to the second, with a line between them.
+#[derive(Clone, Debug)]
+pub enum Layout {
+    Pane(usize),
+    Split {
+        // whether the panes are side by side, rather than one above the other.
+        vertical: bool,
+        // how much of the space the first side gets.
+        ratio: f64,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+impl Layout {
+    // Works out where each pane goes in a grid, and where the lines between them go. The lines are given along with
+    // whether they run from top to bottom.
+    pub fn place(&self, mut grid: Grid, panes: &mut Vec<(usize, Grid)>, dividers: &mut Vec<(Grid, bool)>) {
+        match self {
+            Layout::Pane(id) => panes.push((*id, grid)),
+            Layout::Split {
+                vertical,
+                ratio,
+                first,
+                second,
+            } => {
+                let (first_grid, divider) = split_grid(&mut grid, *vertical, *ratio);
+                dividers.push((divider, *vertical));
+                first.place(first_grid, panes, dividers);
+                second.place(grid, panes, dividers);
+            }
+        }
+    }
+    // Splits a pane in two, with a new pane in the first half. Returns whether the pane was found.
+    fn split(&mut self, pane: usize, new: usize, vertical: bool) -> bool {
+        match self {
+            Layout::Pane(id) if *id == pane => {
+                *self = Layout::Split {
+                    vertical,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Pane(new)),
+                    second: Box::new(Layout::Pane(pane)),
+                };
+                true
+            }
+            Layout::Pane(_) => false,
+            Layout::Split { first, second, .. } => first.split(pane, new, vertical) || second.split(pane, new, vertical),
+        }
+    }
+    // Takes a pane out, giving its space to whatever was on the other side of it.
+    // Returns the first pane on that side, which is where the focus goes.
+    fn remove(&mut self, pane: usize) -> Option<usize> {
+        let (first, second) = match self {
+            Layout::Pane(_) => return None,
+            Layout::Split { first, second, .. } => (first, second),
+        };
+        let other = match (&**first, &**second) {
+            (Layout::Pane(id), _) if *id == pane => second.clone(),
+            (_, Layout::Pane(id)) if *id == pane => first.clone(),
+            _ => return first.remove(pane).or_else(|| second.remove(pane)),
+        };
+        *self = *other;
+        Some(self.first_pane())
+    }
+    // The pane in the top left.
+    fn first_pane(&self) -> usize {
+        match self {
+            Layout::Pane(id) => *id,
+            Layout::Split { first, .. } => first.first_pane(),
+        }
+    }
+    // Makes a pane bigger or smaller by a number of rows (or columns, if vertical), by moving the line of the nearest
+    // split around it that goes the right way. Returns None if the pane isn't in this part of the layout, and whether
+    // it was resized otherwise.
+    fn resize(&mut self, pane: usize, vertical: bool, delta: isize, grid: Grid) -> Option<bool> {
+        let (split_vertical, ratio, first, second) = match self {
+            Layout::Pane(id) => return Some(false).filter(|_| *id == pane),
+            Layout::Split {
+                vertical,
+                ratio,
+                first,
+                second,
+            } => (*vertical, ratio, first, second),
+        };
+        let mut rest = grid.clone();
+        let (first_grid, _) = split_grid(&mut rest, split_vertical, *ratio);
+        let (resized, in_first) = match first.resize(pane, vertical, delta, first_grid) {
+            Some(val) => (val, true),
+            None => (second.resize(pane, vertical, delta, rest)?, false),
+        };
+        if resized || split_vertical != vertical {
+            return Some(resized);
+        }
+        // the line between the sides takes up one row or column.
+        let total = length(&grid, vertical).saturating_sub(1);
+        let current = first_size(total, *ratio, vertical) as isize;
+        let wanted = if in_first { current + delta } else { current - delta };
+        let min = min_size(vertical).min(total / 2) as isize;
+        *ratio = wanted.clamp(min, total as isize - min) as f64 / total.max(1) as f64;
+        Some(true)
+    }
+}
+// How wide (or tall) a grid is.
+fn length(grid: &Grid, vertical: bool) -> usize {
+    if vertical {
+        grid.end_x - grid.start_x
+    } else {
+        grid.end_y - grid.start_y
+    }
+}
+// The least space a pane can get: a line of text and the status line under it, or the line numbers and a column of text.
+fn min_size(vertical: bool) -> usize {
+    if vertical {
+        HEADER_SIZE + 1
+    } else {
+        2
+    }
+}
+// How much of the space the first side of a split gets. Each side gets at least the least a pane can get, if there's room.
+fn first_size(total: usize, ratio: f64, vertical: bool) -> usize {
+    let min = min_size(vertical).min(total / 2);
+    ((total as f64 * ratio).round() as usize).clamp(min, total - min)
+}
+// Splits the first side of a split off of a grid, along with the line after it. What's left of the grid is the second side.
+fn split_grid(grid: &mut Grid, vertical: bool, ratio: f64) -> (Grid, Grid) {
+    let total = length(grid, vertical).saturating_sub(1);
+    let size = first_size(total, ratio, vertical);
+    let mut first = grid.clone();
+    let mut divider = grid.clone();
+    if vertical {
+        first.end_x = grid.start_x + size;
+        divider.start_x = first.end_x;
+        divider.end_x = (divider.start_x + 1).min(grid.end_x);
+        grid.start_x = divider.end_x;
+    } else {
+        first.end_y = grid.start_y + size;
+        divider.start_y = first.end_y;
+        divider.end_y = (divider.start_y + 1).min(grid.end_y);
+        grid.start_y = divider.end_y;
+    }
+    (first, divider)
+}
+// A view into a buffer.
+pub struct Pane {
+    pub buffer: u16,
+    // where the cursors are, while another pane has the focus.
+    view: View,
+}
+// The panes the screen is split into. The focused one gets the keys, and its buffer is the current buffer.
+pub struct Panes {
+    layout: Layout,
+    panes: BTreeMap<usize, Pane>,
+    focused: usize,
+}
+impl Panes {
+    // Starts out as one pane, showing a buffer with its cursors in a view.
+    pub fn new(buffer: u16, view: View) -> Panes {
+        let mut panes = BTreeMap::new();
+        panes.insert(0, Pane { buffer, view });
+        Panes {
+            layout: Layout::Pane(0),
+            panes,
+            focused: 0,
+        }
+    }
+    // Where each pane is on the screen.
+    fn places(&self, frame: &Frame) -> Vec<(usize, Grid)> {
+        let mut places = Vec::new();
+        self.layout.place(screen::text_grid(frame), &mut places, &mut Vec::new());
+        places
+    }
+    // Where the focused pane is on the screen.
+    fn focused_place(&self, frame: &Frame) -> Grid {
+        self.places(frame)
+            .into_iter()
+            .find(|(id, _)| *id == self.focused)
+            .map(|(_, grid)| grid)
+            .expect("Safe unwrap")
+    }
+    // Carries out something a textbox asked for. Anything that isn't about the panes is left to the buffers.
+    pub fn handle(&mut self, request: Request, buffers: &mut Buffers, state: &mut State, frame: &Frame) {
+        match request {
+            Request::Command(Command::Split(path)) => self.split(false, path, buffers, state, frame),
+            Request::Command(Command::VerticalSplit(path)) => self.split(true, path, buffers, state, frame),
+            Request::Command(Command::Close) => self.close(buffers, state),
+            Request::Command(Command::Resize {
+                vertical,
+                amount,
+                relative,
+            }) => {
+                let delta = if relative {
+                    amount
+                } else {
+                    let place = self.focused_place(frame);
+                    // a pane too small to hold its line numbers (or its status line) counts as having no room at all.
+                    let size = if vertical {
+                        length(&place, true).saturating_sub(HEADER_SIZE)
+                    } else {
+                        length(&place, false).saturating_sub(1)
+                    };
+                    amount - size as isize
+                };
+                if self.layout.resize(self.focused, vertical, delta, screen::text_grid(frame)) != Some(true) {
+                    state.message = Some(Message::Error("No split to resize".to_string()));
+                }
+            }
+            Request::Focus(direction) => self.focus(direction, buffers, state, frame),
+            request => buffers.handle(request, state),
+        }
+    }
+    // Splits the focused pane in two, and focuses the new half. It shows the same buffer, or opens a file.
+    pub fn split(&mut self, vertical: bool, path: Option<String>, buffers: &mut Buffers, state: &mut State, frame: &Frame) {
+        let place = self.focused_place(frame);
+        // each half needs the least a pane can get, with a line between them.
+        let room = length(&place, vertical) > 2 * min_size(vertical);
+        if !room {
+            state.message = Some(Message::Error("Not enough room".to_string()));
+            return;
+        }
+        let id = self.panes.keys().last().map(|x| x + 1).unwrap_or(0);
+        let pane = Pane {
+            buffer: buffers.current_id(),
+            view: buffers.current().view(),
+        };
+        self.panes.insert(id, pane);
+        self.layout.split(self.focused, id, vertical);
+        self.set_focus(id, buffers, state);
+        if let Some(path) = path {
+            buffers.handle(Request::Open(path, None), state);
+        }
+    }
+    // Closes the focused pane. The buffer it showed stays open.
+    pub fn close(&mut self, buffers: &mut Buffers, state: &mut State) {
+        if self.panes.len() == 1 {
+            state.message = Some(Message::Error("Cannot close last pane".to_string()));
+            return;
+        }
+        if let Some(next) = self.layout.remove(self.focused) {
+            buffers.current().switch_mode(Mode::Command, state);
+            self.panes.remove(&self.focused);
+            self.focused = next;
+            self.enter(buffers, state);
+        }
+    }
+    // Moves the focus to the pane next to the focused one.
+    pub fn focus(&mut self, direction: Direction, buffers: &mut Buffers, state: &mut State, frame: &Frame) {
+        let place = self.focused_place(frame);
+        // a spot just past the line on that side of the pane.
+        let spot = match direction {
+            Direction::Left => place.start_x.checked_sub(2).map(|x| (x, place.start_y)),
+            Direction::Right => Some((place.end_x + 1, place.start_y)),
+            Direction::Up => place.start_y.checked_sub(2).map(|y| (place.start_x, y)),
+            Direction::Down => Some((place.start_x, place.end_y + 1)),
+        };
+        let (x, y) = match spot {
+            Some(val) => val,
+            None => return,
+        };
+        let found = self
+            .places(frame)
+            .into_iter()
+            .find(|(_, grid)| (grid.start_x..grid.end_x).contains(&x) && (grid.start_y..grid.end_y).contains(&y));
+        if let Some((id, _)) = found {
+            self.set_focus(id, buffers, state);
+        }
+    }
+    // Moves the focus to another pane. The focused pane's cursors are kept, so they're there when it's focused again.
+    fn set_focus(&mut self, id: usize, buffers: &mut Buffers, state: &mut State) {
+        if id == self.focused {
+            return;
+        }
+        self.leave(buffers, state);
+        self.focused = id;
+        self.enter(buffers, state);
+    }
+    // Keeps the focused pane's cursors, for when the focus moves somewhere else.
+    pub fn leave(&mut self, buffers: &mut Buffers, state: &mut State) {
+        buffers.current().switch_mode(Mode::Command, state);
+        if let Some(pane) = self.panes.get_mut(&self.focused) {
+            pane.view = buffers.current().view();
+        }
+    }
+    // Makes the focused pane's buffer the current one, with the cursors where the pane left them.
+    pub fn enter(&self, buffers: &mut Buffers, state: &mut State) {
+        let pane = &self.panes[&self.focused];
+        buffers.switch(pane.buffer, state);
+        buffers.current().set_view(&pane.view);
+    }
+    // The buffer the focused pane shows.
+    pub fn focused_buffer(&self) -> u16 {
+        self.panes[&self.focused].buffer
+    }
+    // Keeps the panes pointing at buffers that are open. If these are the panes being shown, the focused one shows the
+    // current buffer.
+    pub fn sync(&mut self, buffers: &Buffers, shown: bool) {
+        let current = buffers.current_id();
+        for pane in self.panes.values_mut() {
+            if !buffers.contains(pane.buffer) {
+                pane.buffer = current;
+                pane.view = View::default();
+            }
+        }
+        if let Some(pane) = self.panes.get_mut(&self.focused).filter(|_| shown) {
+            pane.buffer = current;
+        }
+    }
+    // Draws every pane into its part of the screen.
+    pub fn display(&mut self, screen: &mut Screen, buffers: &mut Buffers, state: &State) {
+        for area in &mut screen.areas {
+            let pane = &self.panes[&area.pane];
+            let text_box = buffers.get(pane.buffer);
+            if area.pane == self.focused {
+                text_box.display(&mut area.text, &mut area.headers, state, true);
+                text_box.display_status(area, state, true);
+            } else {
+                // the pane's own cursors are put in just while it's drawn.
+                let saved = text_box.view();
+                text_box.set_view(&pane.view);
+                text_box.display(&mut area.text, &mut area.headers, state, false);
+                text_box.display_status(area, state, false);
+                text_box.set_view(&saved);
+            }
+        }
+    }
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, textbox::tests::state};
+
+    fn frame(width: usize, height: usize) -> Frame {
+        Frame::new(0, 0, width, height)
+    }
+
+    // One pane, showing a file that's never written.
+    fn panes() -> (Panes, Buffers, State) {
+        let path = std::env::temp_dir().join(format!("textedit-panes-{}.txt", std::process::id()));
+        let buffers = Buffers::new(&[path.to_string_lossy().to_string()], &Config::default());
+        let panes = Panes::new(buffers.current_id(), View::default());
+        (panes, buffers, state())
+    }
+
+    // Each pane, and the columns and then the rows it covers.
+    fn places(panes: &Panes, frame: &Frame) -> Vec<(usize, [usize; 4])> {
+        let places = panes.places(frame).into_iter();
+        places.map(|(id, grid)| (id, [grid.start_x, grid.end_x, grid.start_y, grid.end_y])).collect()
+    }
+
+    fn run(command: Command, panes: &mut Panes, buffers: &mut Buffers, state: &mut State, frame: &Frame) {
+        panes.handle(Request::Command(command), buffers, state, frame);
+    }
+
+    #[test]
+    fn split() {
+        let (mut panes, mut buffers, mut state) = panes();
+        let frame = frame(80, 24);
+        // the tab bar and the bottom line leave 22 rows, and 21 once the line between the panes is taken out.
+        run(Command::Split(None), &mut panes, &mut buffers, &mut state, &frame);
+        assert_eq!(places(&panes, &frame), vec![(1, [0, 80, 1, 12]), (0, [0, 80, 13, 23])]);
+        run(Command::VerticalSplit(None), &mut panes, &mut buffers, &mut state, &frame);
+        assert_eq!(places(&panes, &frame), vec![(2, [0, 40, 1, 12]), (1, [41, 80, 1, 12]), (0, [0, 80, 13, 23])]);
+        assert_eq!(panes.focused, 2);
+        // every pane shows the buffer that was split.
+        assert!(panes.panes.values().all(|x| x.buffer == buffers.current_id()));
+    }
+
+    #[test]
+    fn split_without_room() {
+        let (mut panes, mut buffers, mut state) = panes();
+        let frame = frame(80, 6);
+        run(Command::Split(None), &mut panes, &mut buffers, &mut state, &frame);
+        assert!(matches!(state.message, Some(Message::Error(_))));
+        assert_eq!(places(&panes, &frame), vec![(0, [0, 80, 1, 5])]);
+    }
+
+    #[test]
+    fn close() {
+        let (mut panes, mut buffers, mut state) = panes();
+        let frame = frame(80, 24);
+        run(Command::Split(None), &mut panes, &mut buffers, &mut state, &frame);
+        run(Command::VerticalSplit(None), &mut panes, &mut buffers, &mut state, &frame);
+        // the pane next to it takes its space, and the focus.
+        run(Command::Close, &mut panes, &mut buffers, &mut state, &frame);
+        assert_eq!(places(&panes, &frame), vec![(1, [0, 80, 1, 12]), (0, [0, 80, 13, 23])]);
+        assert_eq!(panes.focused, 1);
+        run(Command::Close, &mut panes, &mut buffers, &mut state, &frame);
+        assert_eq!(places(&panes, &frame), vec![(0, [0, 80, 1, 23])]);
+        assert_eq!(panes.focused, 0);
+        assert!(state.message.is_none());
+        run(Command::Close, &mut panes, &mut buffers, &mut state, &frame);
+        assert!(matches!(state.message, Some(Message::Error(_))));
+        assert_eq!(panes.panes.len(), 1);
+    }
+
+    #[test]
+    fn focus() {
+        let (mut panes, mut buffers, mut state) = panes();
+        let frame = frame(80, 24);
+        run(Command::Split(None), &mut panes, &mut buffers, &mut state, &frame);
+        run(Command::VerticalSplit(None), &mut panes, &mut buffers, &mut state, &frame);
+        let mut focused = Vec::new();
+        for direction in [Direction::Left, Direction::Right, Direction::Down, Direction::Right, Direction::Up] {
+            panes.focus(direction, &mut buffers, &mut state, &frame);
+            focused.push(panes.focused);
+        }
+        // there's nothing left of the first pane or right of the second, and going up from the bottom lands on the
+        // pane above its left edge.
+        assert_eq!(focused, vec![2, 1, 0, 0, 2]);
+    }
+
+    #[test]
+    fn resize() {
+        let (mut panes, mut buffers, mut state) = panes();
+        let frame = frame(80, 24);
+        run(Command::Split(None), &mut panes, &mut buffers, &mut state, &frame);
+        let mut heights = Vec::new();
+        for (amount, relative) in [(5, false), (1000, false), (-1000, true), (3, true), (0, false)] {
+            let resize = Command::Resize { vertical: false, amount, relative };
+            run(resize, &mut panes, &mut buffers, &mut state, &frame);
+            let (_, [_, _, start, end]) = places(&panes, &frame)[0];
+            heights.push(end - start);
+        }
+        // the other pane always keeps a line of text and its status line, however far past the frame the size goes.
+        assert_eq!(heights, vec![6, 19, 2, 5, 2]);
+        assert!(state.message.is_none());
+        run(Command::Resize { vertical: true, amount: 10, relative: false }, &mut panes, &mut buffers, &mut state, &frame);
+        assert!(matches!(state.message, Some(Message::Error(_))));
+    }
+}
@@ -0,0 +1,197 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Works out how a line's characters are laid out in the cells of the screen. Characters that are drawn together as one
+// (graphemes, like a letter with an accent on it, or an emoji made of several) share their cells. A tab takes up the
+// cells up to the next tab stop, wide characters take up two, and everything else takes up one.
+
+// How many cells a grapheme takes up, when it starts at a column.
+pub fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = tab_width.max(1);
+        tab_width - column % tab_width
+    } else {
+        // even characters that aren't drawn get a cell, so the cursor can be put on them.
+        grapheme.width().max(1)
+    }
+}
+// The graphemes of a line, each with the index of its first character.
+pub fn graphemes(line: &[char]) -> Vec<(usize, String)> {
+    let text = line.iter().collect::<String>();
+    let mut start = 0;
+    text.graphemes(true)
+        .map(|x| {
+            let grapheme = (start, x.to_string());
+            start += x.chars().count();
+            grapheme
+        })
+        .collect()
+}
+// The index of the first character of each grapheme of a line, and the end of the line.
+pub fn boundaries(line: &[char]) -> Vec<usize> {
+    graphemes(line).into_iter().map(|(x, _)| x).chain([line.len()]).collect()
+}
+// The column each character of a line starts at, and the column just past the end of the line. Characters in the middle
+// of a grapheme are at its column. When lines are wrapped, a wide character that doesn't fit at the end of a row goes on
+// the next one.
+pub fn columns(line: &[char], tab_width: usize, wrap: Option<usize>) -> Vec<usize> {
+    let mut columns = Vec::with_capacity(line.len() + 1);
+    let mut column = 0;
+    for (start, grapheme) in graphemes(line) {
+        let width = grapheme_width(&grapheme, column, tab_width);
+        if let Some(wrap) = wrap.filter(|x| width > 1 && *x >= width && grapheme != "\t") {
+            if column % wrap + width > wrap {
+                column += wrap - column % wrap;
+            }
+        }
+        columns.resize(start + grapheme.chars().count(), column);
+        column += width;
+    }
+    columns.push(column);
+    columns
+}
+// The column a position in a line is drawn at. Positions past the end of the line take up a cell each.
+pub fn column(line: &[char], x: usize, tab_width: usize, wrap: Option<usize>) -> usize {
+    let columns = columns(line, tab_width, wrap);
+    columns[x.min(line.len())] + x.saturating_sub(line.len())
+}
+// How many cells a whole line takes up.
+pub fn width(line: &[char], tab_width: usize, wrap: Option<usize>) -> usize {
+    columns(line, tab_width, wrap)[line.len()]
+}
+// The start of the grapheme that covers a column. Columns past the end of the line are positions past its end.
+pub fn index_at(line: &[char], target: usize, tab_width: usize, wrap: Option<usize>) -> usize {
+    let columns = columns(line, tab_width, wrap);
+    if target >= columns[line.len()] {
+        return line.len() + (target - columns[line.len()]);
+    }
+    boundaries(line).into_iter().take_while(|x| columns[*x] <= target).last().unwrap_or(0)
+}
+// Lays a line out into cells, each with the style of the grapheme it belongs to. A wide grapheme is put in its first
+// cell, and the cells after it are left empty. Tabs become spaces. Styles past the end of the line are kept, for the
+// cells after it.
+pub fn expand<'a>(
+    line: &[char],
+    styles: &[Option<&'a str>],
+    tab_width: usize,
+    wrap: Option<usize>,
+) -> (Vec<String>, Vec<Option<&'a str>>) {
+    let columns = columns(line, tab_width, wrap);
+    let (mut cells, mut cell_styles) = (Vec::new(), Vec::new());
+    for (start, grapheme) in graphemes(line) {
+        let style = styles.get(start).copied().flatten();
+        // a wide character that was moved onto the next row leaves a gap behind it.
+        cells.resize(columns[start], " ".to_string());
+        cell_styles.resize(columns[start], None);
+        // the gap a wide character leaves behind belongs to neither side of it, so it isn't counted here.
+        let width = grapheme_width(&grapheme, columns[start], tab_width);
+        if grapheme == "\t" {
+            cells.extend(std::iter::repeat_n(" ".to_string(), width));
+        } else {
+            cells.push(grapheme);
+            cells.extend(std::iter::repeat_n(String::new(), width - 1));
+        }
+        cell_styles.extend(std::iter::repeat_n(style, width));
+    }
+    cell_styles.extend(styles.iter().skip(line.len()));
+    (cells, cell_styles)
+}
+// The cells between two columns. A wide character that's cut in half by either edge is drawn as a space instead.
+pub fn cut(cells: &[String], first: usize, last: usize) -> Vec<String> {
+    let mut piece = cells[first.min(cells.len())..last.min(cells.len())].to_vec();
+    if piece.first().is_some_and(|x| x.is_empty()) {
+        piece[0] = " ".to_string();
+    }
+    if cells.get(last).is_some_and(|x| x.is_empty()) {
+        if let Some(cell) = piece.last_mut() {
+            *cell = " ".to_string();
+        }
+    }
+    piece
+}
+// How many cells some text takes up on the screen.
+pub fn text_width(text: &str) -> usize {
+    text.graphemes(true).map(|x| grapheme_width(x, 0, 1)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn tabs_go_to_the_next_stop() {
+        // a tab takes up what's left of its stop, wherever it starts.
+        assert_eq!(columns(&chars("\tx"), 4, None), vec![0, 4, 5]);
+        assert_eq!(columns(&chars("ab\tx"), 4, None), vec![0, 1, 2, 4, 5]);
+        assert_eq!(columns(&chars("abc\t\tx"), 4, None), vec![0, 1, 2, 3, 4, 8, 9]);
+        assert_eq!(columns(&chars("abcd\tx"), 4, None), vec![0, 1, 2, 3, 4, 8, 9]);
+        // after a wide character too.
+        assert_eq!(columns(&chars("日\tx"), 4, None), vec![0, 2, 4, 5]);
+        let (cells, _) = expand(&chars("a\tb"), &[], 4, None);
+        assert_eq!(cells, vec!["a", " ", " ", " ", "b"]);
+    }
+
+    #[test]
+    fn wide_characters() {
+        let line = chars("a日本b");
+        assert_eq!(columns(&line, 4, None), vec![0, 1, 3, 5, 6]);
+        assert_eq!(width(&line, 4, None), 6);
+        // either cell of a wide character is the character.
+        assert_eq!(index_at(&line, 1, 4, None), 1);
+        assert_eq!(index_at(&line, 2, 4, None), 1);
+        assert_eq!(index_at(&line, 4, 4, None), 2);
+        assert_eq!(index_at(&line, 8, 4, None), 6);
+        let (cells, _) = expand(&line, &[], 4, None);
+        assert_eq!(cells, vec!["a", "日", "", "本", "", "b"]);
+        // one that's cut in half at either edge becomes a space.
+        assert_eq!(cut(&cells, 2, 4), vec![" ", " "]);
+        assert_eq!(cut(&cells, 1, 5), vec!["日", "", "本", ""]);
+        // one that doesn't fit at the end of a row goes onto the next.
+        assert_eq!(columns(&chars("ab日"), 4, Some(3)), vec![0, 1, 3, 5]);
+        let (cells, styles) = expand(&chars("ab日"), &[Some("x"); 3], 4, Some(3));
+        assert_eq!(cells, vec!["a", "b", " ", "日", ""]);
+        assert_eq!(styles, vec![Some("x"), Some("x"), None, Some("x"), Some("x")]);
+    }
+
+    #[test]
+    fn combining_marks() {
+        // the accent shares the cell of the letter it's on.
+        let line = chars("e\u{301}x");
+        assert_eq!(graphemes(&line), vec![(0, "e\u{301}".to_string()), (2, "x".to_string())]);
+        assert_eq!(boundaries(&line), vec![0, 2, 3]);
+        assert_eq!(columns(&line, 4, None), vec![0, 0, 1, 2]);
+        assert_eq!(index_at(&line, 1, 4, None), 2);
+        let (cells, styles) = expand(&line, &[Some("a"), Some("b"), Some("c")], 4, None);
+        assert_eq!(cells, vec!["e\u{301}", "x"]);
+        assert_eq!(styles, vec![Some("a"), Some("c")]);
+        assert_eq!(text_width("e\u{301}x"), 2);
+    }
+
+    #[test]
+    fn emoji_sequences() {
+        // a family joined with zero width joiners is one grapheme, two cells wide.
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let line = chars(&format!("a{}b", family));
+        assert_eq!(boundaries(&line), vec![0, 1, 6, 7]);
+        assert_eq!(columns(&line, 4, None), vec![0, 1, 1, 1, 1, 1, 3, 4]);
+        assert_eq!(index_at(&line, 2, 4, None), 1);
+        let (cells, _) = expand(&line, &[], 4, None);
+        assert_eq!(cells, vec!["a".to_string(), family.to_string(), String::new(), "b".to_string()]);
+        assert_eq!(text_width(family), 2);
+    }
+
+    #[test]
+    fn past_the_end() {
+        let line = chars("ab");
+        assert_eq!(column(&line, 5, 4, None), 5);
+        assert_eq!(index_at(&line, 5, 4, None), 5);
+        // styles for the cells after the line are kept.
+        let (cells, styles) = expand(&line, &[None, None, Some("cursor")], 4, None);
+        assert_eq!(cells.len(), 2);
+        assert_eq!(styles, vec![None, None, Some("cursor")]);
+    }
+}
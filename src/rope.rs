@@ -0,0 +1,564 @@
+use std::{fmt::Display, rc::Rc};
+
+// The largest number of characters a single leaf will hold.
+pub const MAX_LEAF: usize = 1024;
+
+// A persistent, balanced tree of text chunks.
+// Edits only copy the path from the root to the changed leaf, so cloning a rope
+// (for example, to keep a snapshot of it) is cheap.
+#[derive(Clone)]
+pub struct Rope {
+    root: Rc<Node>,
+}
+impl Rope {
+    // Builds a rope out of a series of lines, joining them with newline characters.
+    pub fn from_lines<'a>(lines: impl Iterator<Item = &'a str>) -> Rope {
+        let mut builder = Builder::new();
+        for (i, line) in lines.enumerate() {
+            if i != 0 {
+                builder.push_str("\n");
+            }
+            builder.push_str(line);
+        }
+        Rope {
+            root: builder.finish(),
+        }
+    }
+    // The number of characters in the rope.
+    pub fn len_chars(&self) -> usize {
+        self.root.chars()
+    }
+    // The number of lines in the rope. An empty rope still has one (empty) line.
+    pub fn len_lines(&self) -> usize {
+        self.root.lines() + 1
+    }
+    // Inserts text before the character at the given index.
+    pub fn insert(&mut self, at: usize, text: &str) {
+        if !text.is_empty() {
+            self.root = insert(&self.root, at.min(self.len_chars()), text);
+        }
+    }
+    // Removes the characters between the two indexes.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len_chars());
+        if start < end {
+            self.root = remove(&self.root, start, end);
+        }
+    }
+    // The index of the first character of a line.
+    pub fn line_to_char(&self, line: usize) -> usize {
+        if line == 0 {
+            0
+        } else if line >= self.len_lines() {
+            self.len_chars()
+        } else {
+            newline(&self.root, line - 1) + 1
+        }
+    }
+    // The line that the character at the given index is on.
+    pub fn char_to_line(&self, at: usize) -> usize {
+        newlines_before(&self.root, at.min(self.len_chars()))
+    }
+    // The number of characters in a line, not counting its newline.
+    pub fn line_len(&self, line: usize) -> usize {
+        self.line_end(line) - self.line_to_char(line)
+    }
+    // The contents of a line, without its newline.
+    pub fn line(&self, line: usize) -> String {
+        self.slice(self.line_to_char(line), self.line_end(line))
+    }
+    // The characters of a line, without its newline.
+    pub fn line_chars(&self, line: usize) -> Vec<char> {
+        self.line(line).chars().collect()
+    }
+    // The text between the two indexes.
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        let mut s = String::new();
+        let end = end.min(self.len_chars());
+        if start < end {
+            collect(&self.root, start, end, &mut s);
+        }
+        s
+    }
+    // The index just past the last character of a line (not counting its newline).
+    fn line_end(&self, line: usize) -> usize {
+        if line + 1 >= self.len_lines() {
+            self.len_chars()
+        } else {
+            newline(&self.root, line)
+        }
+    }
+}
+impl From<&str> for Rope {
+    fn from(text: &str) -> Rope {
+        let mut builder = Builder::new();
+        builder.push_str(text);
+        Rope {
+            root: builder.finish(),
+        }
+    }
+}
+impl Display for Rope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // walks the leaves from left to right.
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            match &**node {
+                Node::Leaf(leaf) => f.write_str(&leaf.text)?,
+                Node::Branch(branch) => {
+                    stack.push(&branch.right);
+                    stack.push(&branch.left);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Node {
+    Leaf(Leaf),
+    Branch(Branch),
+}
+struct Leaf {
+    text: String,
+    chars: usize,
+    lines: usize,
+}
+struct Branch {
+    left: Rc<Node>,
+    right: Rc<Node>,
+    chars: usize,
+    lines: usize,
+    height: usize,
+}
+impl Node {
+    fn leaf(text: String) -> Rc<Node> {
+        let chars = text.chars().count();
+        let lines = text.matches('\n').count();
+        Rc::new(Node::Leaf(Leaf { text, chars, lines }))
+    }
+    fn branch(left: Rc<Node>, right: Rc<Node>) -> Rc<Node> {
+        Rc::new(Node::Branch(Branch {
+            chars: left.chars() + right.chars(),
+            lines: left.lines() + right.lines(),
+            height: left.height().max(right.height()) + 1,
+            left,
+            right,
+        }))
+    }
+    fn chars(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.chars,
+            Node::Branch(branch) => branch.chars,
+        }
+    }
+    fn lines(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.lines,
+            Node::Branch(branch) => branch.lines,
+        }
+    }
+    fn height(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 0,
+            Node::Branch(branch) => branch.height,
+        }
+    }
+    fn children(&self) -> (Rc<Node>, Rc<Node>) {
+        match self {
+            Node::Leaf(_) => panic!("Leaves have no children!"),
+            Node::Branch(branch) => (branch.left.clone(), branch.right.clone()),
+        }
+    }
+}
+
+// Collects text into leaves, then builds a balanced tree out of them.
+struct Builder {
+    leaves: Vec<Rc<Node>>,
+    current: String,
+    current_chars: usize,
+}
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            leaves: Vec::new(),
+            current: String::new(),
+            current_chars: 0,
+        }
+    }
+    fn push_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.current.push(c);
+            self.current_chars += 1;
+            if self.current_chars == MAX_LEAF {
+                self.leaves.push(Node::leaf(std::mem::take(&mut self.current)));
+                self.current_chars = 0;
+            }
+        }
+    }
+    fn finish(mut self) -> Rc<Node> {
+        if self.current_chars > 0 || self.leaves.is_empty() {
+            self.leaves.push(Node::leaf(self.current));
+        }
+        build(&self.leaves)
+    }
+}
+// Builds a perfectly balanced tree out of a list of leaves.
+fn build(leaves: &[Rc<Node>]) -> Rc<Node> {
+    if leaves.len() == 1 {
+        leaves[0].clone()
+    } else {
+        let (left, right) = leaves.split_at(leaves.len() / 2);
+        Node::branch(build(left), build(right))
+    }
+}
+// Converts a character index within a leaf into a byte index.
+fn byte_index(text: &str, at: usize) -> usize {
+    text.char_indices().nth(at).map(|(i, _)| i).unwrap_or(text.len())
+}
+// Joins two trees together, keeping the result balanced.
+fn join(left: Rc<Node>, right: Rc<Node>) -> Rc<Node> {
+    if left.chars() == 0 {
+        return right;
+    }
+    if right.chars() == 0 {
+        return left;
+    }
+    // two small leaves get merged into one.
+    if let (Node::Leaf(l), Node::Leaf(r)) = (&*left, &*right) {
+        if l.chars + r.chars <= MAX_LEAF {
+            return Node::leaf(format!("{}{}", l.text, r.text));
+        }
+    }
+    let (left_height, right_height) = (left.height(), right.height());
+    if left_height > right_height + 1 {
+        // the right tree gets joined somewhere down the left tree's right spine.
+        let (a, b) = left.children();
+        balance(a, join(b, right))
+    } else if right_height > left_height + 1 {
+        // the left tree gets joined somewhere down the right tree's left spine.
+        let (a, b) = right.children();
+        balance(join(left, a), b)
+    } else {
+        Node::branch(left, right)
+    }
+}
+// Creates a branch out of two trees whose heights differ by at most two, rotating if needed.
+fn balance(left: Rc<Node>, right: Rc<Node>) -> Rc<Node> {
+    if right.height() > left.height() + 1 {
+        let (a, b) = right.children();
+        if a.height() > b.height() {
+            let (a1, a2) = a.children();
+            Node::branch(Node::branch(left, a1), Node::branch(a2, b))
+        } else {
+            Node::branch(Node::branch(left, a), b)
+        }
+    } else if left.height() > right.height() + 1 {
+        let (a, b) = left.children();
+        if b.height() > a.height() {
+            let (b1, b2) = b.children();
+            Node::branch(Node::branch(a, b1), Node::branch(b2, right))
+        } else {
+            Node::branch(a, Node::branch(b, right))
+        }
+    } else {
+        Node::branch(left, right)
+    }
+}
+fn insert(node: &Rc<Node>, at: usize, text: &str) -> Rc<Node> {
+    match &**node {
+        Node::Leaf(leaf) => {
+            let byte = byte_index(&leaf.text, at);
+            let mut builder = Builder::new();
+            builder.push_str(&leaf.text[..byte]);
+            builder.push_str(text);
+            builder.push_str(&leaf.text[byte..]);
+            builder.finish()
+        }
+        Node::Branch(branch) => {
+            let left_chars = branch.left.chars();
+            if at <= left_chars {
+                join(insert(&branch.left, at, text), branch.right.clone())
+            } else {
+                join(branch.left.clone(), insert(&branch.right, at - left_chars, text))
+            }
+        }
+    }
+}
+fn remove(node: &Rc<Node>, start: usize, end: usize) -> Rc<Node> {
+    if start == 0 && end >= node.chars() {
+        return Node::leaf(String::new());
+    }
+    match &**node {
+        Node::Leaf(leaf) => {
+            let start_byte = byte_index(&leaf.text, start);
+            let end_byte = byte_index(&leaf.text, end);
+            Node::leaf(format!("{}{}", &leaf.text[..start_byte], &leaf.text[end_byte..]))
+        }
+        Node::Branch(branch) => {
+            let left_chars = branch.left.chars();
+            let left = if start < left_chars {
+                remove(&branch.left, start, end.min(left_chars))
+            } else {
+                branch.left.clone()
+            };
+            let right = if end > left_chars {
+                remove(&branch.right, start.saturating_sub(left_chars), end - left_chars)
+            } else {
+                branch.right.clone()
+            };
+            join(left, right)
+        }
+    }
+}
+// The index of the nth newline character (starting from zero).
+fn newline(node: &Rc<Node>, n: usize) -> usize {
+    match &**node {
+        Node::Leaf(leaf) => leaf
+            .text
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '\n')
+            .nth(n)
+            .map(|(i, _)| i)
+            .unwrap_or(leaf.chars),
+        Node::Branch(branch) => {
+            let left_lines = branch.left.lines();
+            if n < left_lines {
+                newline(&branch.left, n)
+            } else {
+                branch.left.chars() + newline(&branch.right, n - left_lines)
+            }
+        }
+    }
+}
+// The number of newline characters before the given index.
+fn newlines_before(node: &Rc<Node>, at: usize) -> usize {
+    match &**node {
+        Node::Leaf(leaf) => leaf.text.chars().take(at).filter(|c| *c == '\n').count(),
+        Node::Branch(branch) => {
+            let left_chars = branch.left.chars();
+            if at <= left_chars {
+                newlines_before(&branch.left, at)
+            } else {
+                branch.left.lines() + newlines_before(&branch.right, at - left_chars)
+            }
+        }
+    }
+}
+// Pushes the text between the two indexes onto a string.
+fn collect(node: &Rc<Node>, start: usize, end: usize, s: &mut String) {
+    match &**node {
+        Node::Leaf(leaf) => {
+            let start_byte = byte_index(&leaf.text, start);
+            let end_byte = byte_index(&leaf.text, end);
+            s.push_str(&leaf.text[start_byte..end_byte]);
+        }
+        Node::Branch(branch) => {
+            let left_chars = branch.left.chars();
+            if start < left_chars {
+                collect(&branch.left, start, end.min(left_chars), s);
+            }
+            if end > left_chars {
+                collect(&branch.right, start.saturating_sub(left_chars), end - left_chars, s);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small random number generator, so the tests go the same way every time.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, below: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % below.max(1)
+        }
+    }
+
+    // The same edits, on a plain string.
+    fn model_insert(model: &mut String, at: usize, text: &str) {
+        let byte = byte_index(model, at);
+        model.insert_str(byte, text);
+    }
+
+    fn model_remove(model: &mut String, start: usize, end: usize) {
+        let (start, end) = (byte_index(model, start), byte_index(model, end));
+        model.replace_range(start..end, "");
+    }
+
+    // Checks everything a rope can be asked against the string it should hold.
+    fn check(rope: &Rope, model: &str) {
+        assert_eq!(rope.to_string(), model);
+        assert_eq!(rope.len_chars(), model.chars().count());
+        let lines = model.split('\n').collect::<Vec<_>>();
+        assert_eq!(rope.len_lines(), lines.len());
+        let mut start = 0;
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(rope.line(i), *line);
+            assert_eq!(rope.line_to_char(i), start);
+            assert_eq!(rope.line_len(i), line.chars().count());
+            start += line.chars().count() + 1;
+        }
+        // every newline, the character after it, and a sample of the rest. Checking them all would take a while.
+        let mut line = 0;
+        let mut after_newline = true;
+        for (i, c) in model.chars().enumerate() {
+            if c == '\n' || after_newline || i % 37 == 0 {
+                assert_eq!(rope.char_to_line(i), line);
+            }
+            after_newline = c == '\n';
+            if after_newline {
+                line += 1;
+            }
+        }
+        assert_eq!(rope.char_to_line(rope.len_chars()), lines.len() - 1);
+        check_tree(&rope.root);
+    }
+
+    // Checks that the counts kept in the branches add up, that no leaf is too big, and that the tree stays balanced.
+    fn check_tree(node: &Rc<Node>) {
+        match &**node {
+            Node::Leaf(leaf) => {
+                assert!(leaf.chars <= MAX_LEAF);
+                assert_eq!(leaf.chars, leaf.text.chars().count());
+                assert_eq!(leaf.lines, leaf.text.matches('\n').count());
+            }
+            Node::Branch(branch) => {
+                assert_eq!(branch.chars, branch.left.chars() + branch.right.chars());
+                assert_eq!(branch.lines, branch.left.lines() + branch.right.lines());
+                assert_eq!(branch.height, branch.left.height().max(branch.right.height()) + 1);
+                assert!(branch.left.height().abs_diff(branch.right.height()) <= 2);
+                check_tree(&branch.left);
+                check_tree(&branch.right);
+            }
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let rope = Rope::from("");
+        check(&rope, "");
+        assert_eq!(rope.line(3), "");
+        assert_eq!(rope.line_to_char(3), 0);
+    }
+
+    #[test]
+    fn from_lines() {
+        let rope = Rope::from_lines(["one", "", "three"].into_iter());
+        check(&rope, "one\n\nthree");
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut rope = Rope::from("hello world");
+        let mut model = "hello world".to_string();
+        rope.insert(5, ",\nthere");
+        model_insert(&mut model, 5, ",\nthere");
+        check(&rope, &model);
+        rope.remove(0, 7);
+        model_remove(&mut model, 0, 7);
+        check(&rope, &model);
+        // indexes past the end are clamped.
+        rope.insert(1000, "\n!");
+        model.push_str("\n!");
+        check(&rope, &model);
+        rope.remove(3, 1000);
+        model.truncate(byte_index(&model, 3));
+        check(&rope, &model);
+    }
+
+    #[test]
+    fn multi_byte() {
+        let text = "héllo\nwörld 日本語\n🦀 crab";
+        let mut rope = Rope::from(text);
+        let mut model = text.to_string();
+        check(&rope, &model);
+        rope.insert(2, "ü\n");
+        model_insert(&mut model, 2, "ü\n");
+        check(&rope, &model);
+        rope.remove(10, 16);
+        model_remove(&mut model, 10, 16);
+        check(&rope, &model);
+    }
+
+    #[test]
+    fn newlines_at_leaf_boundaries() {
+        // a newline as the last character of one leaf, and as the first of the next.
+        let text = format!("{}\n\n{}", "a".repeat(MAX_LEAF - 1), "b".repeat(MAX_LEAF));
+        let mut rope = Rope::from(text.as_str());
+        let mut model = text.clone();
+        check(&rope, &model);
+        assert_eq!(rope.line_to_char(1), MAX_LEAF);
+        assert_eq!(rope.char_to_line(MAX_LEAF - 1), 0);
+        assert_eq!(rope.char_to_line(MAX_LEAF), 1);
+        rope.insert(MAX_LEAF, "\n");
+        model_insert(&mut model, MAX_LEAF, "\n");
+        check(&rope, &model);
+        rope.remove(MAX_LEAF - 1, MAX_LEAF + 1);
+        model_remove(&mut model, MAX_LEAF - 1, MAX_LEAF + 1);
+        check(&rope, &model);
+    }
+
+    #[test]
+    fn edits_longer_than_a_leaf() {
+        let big = "x\ny".repeat(MAX_LEAF);
+        let mut rope = Rope::from("start end");
+        let mut model = "start end".to_string();
+        rope.insert(6, &big);
+        model_insert(&mut model, 6, &big);
+        check(&rope, &model);
+        rope.insert(0, &big);
+        model_insert(&mut model, 0, &big);
+        check(&rope, &model);
+        // a removal that takes in whole leaves, and parts of the ones on either side.
+        rope.remove(10, 4 * MAX_LEAF);
+        model_remove(&mut model, 10, 4 * MAX_LEAF);
+        check(&rope, &model);
+    }
+
+    #[test]
+    fn random_edits() {
+        let mut random = Random(7);
+        let mut rope = Rope::from("");
+        let mut model = String::new();
+        let pieces = ["a", "\n", "é", "日本", "🦀\n", "line\n", "\n\n"];
+        for i in 0..1000 {
+            let len = model.chars().count();
+            if random.next(3) > 0 || len == 0 {
+                let mut text = String::new();
+                // now and then, something bigger than a leaf.
+                let count = if i % 250 == 0 { MAX_LEAF + random.next(MAX_LEAF) } else { random.next(20) + 1 };
+                for _ in 0..count {
+                    text.push_str(pieces[random.next(pieces.len())]);
+                }
+                let at = random.next(len + 1);
+                rope.insert(at, &text);
+                model_insert(&mut model, at, &text);
+            } else {
+                let start = random.next(len);
+                let end = (start + random.next(if i % 50 == 0 { len } else { 30 }) + 1).min(len);
+                rope.remove(start, end);
+                model_remove(&mut model, start, end);
+            }
+            if i % 200 == 0 {
+                check(&rope, &model);
+            }
+        }
+        check(&rope, &model);
+    }
+
+    #[test]
+    fn snapshots_are_unchanged() {
+        let mut rope = Rope::from("one\ntwo");
+        let snapshot = rope.clone();
+        rope.insert(3, " and a half");
+        rope.remove(0, 2);
+        check(&snapshot, "one\ntwo");
+        check(&rope, "e and a half\ntwo");
+    }
+}
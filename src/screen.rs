@@ -0,0 +1,214 @@
+use std::io::stdout;
+
+use grid_ui::{
+    crossterm::CrosstermHandler,
+    grid::{Alignment, DividerStrategy, Frame, Grid, SplitStrategy},
+    process::DrawProcess,
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    ansi, ansi_truncate,
+    command_line::{CommandLine, Message},
+    keymap::Mode,
+    panes::Layout,
+    render,
+    theme::Theme,
+};
+
+// The width of the line numbers to the left of the text.
+pub const HEADER_SIZE: usize = 5;
+
+// The part of the terminal between the tab bar and the bottom line, which the panes share.
+pub fn text_grid(frame: &Frame) -> Grid {
+    let mut grid = frame.next_frame();
+    grid.start_y = (grid.start_y + 1).min(grid.end_y);
+    grid.end_y = grid.end_y.saturating_sub(1).max(grid.start_y);
+    grid
+}
+// The sections of a pane that get drawn to.
+pub struct Area {
+    pub pane: usize,
+    // the text, or whatever is being shown in its place.
+    pub text: DrawProcess,
+    // the line numbers to the left of the text.
+    pub headers: DrawProcess,
+    // the status line under the text.
+    pub status: DrawProcess,
+}
+impl Area {
+    // Adds a pane's status line. The left side is cut short if there isn't room for both sides.
+    pub fn show_status(&mut self, left: &str, right: &str, focused: bool, theme: &Theme) {
+        let style = if focused { &theme.status } else { &theme.status_inactive };
+        let mut status_strategy = ansi_truncate::AnsiTruncate::new(style, ansi::RESET);
+        let width = self.status.width();
+        let right_width = render::text_width(right).min(width);
+        let mut taken = 0;
+        let left = left
+            .graphemes(true)
+            .take_while(|x| {
+                taken += render::text_width(x);
+                taken <= width - right_width
+            })
+            .collect::<String>();
+        let gap = width - right_width - render::text_width(&left);
+        let line = format!("{}{}{}", left, " ".repeat(gap), right);
+        let _ = self.status.add_to_section(line, &mut status_strategy, Alignment::Plus);
+    }
+}
+// The sections of the terminal that get drawn to.
+pub struct Screen {
+    // the tab bar, at the top of the terminal.
+    pub top: DrawProcess,
+    pub areas: Vec<Area>,
+    // the lines between the panes.
+    dividers: Vec<DrawProcess>,
+    // the line at the bottom of the terminal.
+    pub bottom: DrawProcess,
+}
+impl Screen {
+    // Splits a frame up into sections, with the panes laid out above the bottom line.
+    pub fn new(frame: &Frame, layout: &Layout, theme: &Theme) -> Screen {
+        // creates grid that represents the terminal
+        let mut grid = frame.next_frame();
+
+        // splits the last line off of the grid.
+        let bottom = grid
+            .split(&SplitStrategy::new().max_y(1, Alignment::Plus))
+            .expect("Terminal too small!") // if the terminal is too small, it will panic
+            .into_process(DividerStrategy::Beginning);
+        // and the first line, for the tab bar.
+        let top = grid
+            .split(&SplitStrategy::new().max_y(1, Alignment::Minus))
+            .expect("Terminal too small!")
+            .into_process(DividerStrategy::Beginning);
+
+        let (mut places, mut lines) = (Vec::new(), Vec::new());
+        layout.place(text_grid(frame), &mut places, &mut lines);
+        // a pane that the terminal is too small to fit is left out, rather than drawn in part.
+        let areas = places
+            .into_iter()
+            .filter_map(|(pane, mut grid)| {
+                // splits the last line off for the status line.
+                let status = grid
+                    .split(&SplitStrategy::new().max_y(1, Alignment::Plus))?
+                    .into_process(DividerStrategy::Beginning);
+                // splits the grid off into a headers section and initializes it into a draw process.
+                let headers = grid
+                    .split(&SplitStrategy::new().max_x(HEADER_SIZE, Alignment::Minus))?
+                    .into_process(DividerStrategy::Beginning); // lines are drawn from the top.
+
+                // the remainder of the grid is the main section. Creates a draw process out of this.
+                let text = grid.into_process(DividerStrategy::Beginning);
+                Some(Area {
+                    pane,
+                    text,
+                    headers,
+                    status,
+                })
+            })
+            .collect();
+        // the lines between the panes only have to be filled in once.
+        let mut divider_strategy = ansi_truncate::AnsiTruncate::new(&theme.divider, ansi::RESET);
+        let dividers = lines
+            .into_iter()
+            .map(|(grid, vertical)| {
+                let mut d = grid.into_process(DividerStrategy::Beginning);
+                if vertical {
+                    for _ in 0..d.height() {
+                        let _ = d.add_to_section("│".to_string(), &mut divider_strategy, Alignment::Plus);
+                    }
+                } else {
+                    let line = "─".repeat(d.width());
+                    let _ = d.add_to_section(line, &mut divider_strategy, Alignment::Plus);
+                }
+                d
+            })
+            .collect();
+        Screen {
+            top,
+            areas,
+            dividers,
+            bottom,
+        }
+    }
+    // Adds the tab bar: a label for each tab, with the current one highlighted.
+    pub fn show_tabs(&mut self, labels: &[(String, bool)], theme: &Theme) {
+        let mut bar_strategy = ansi_truncate::AnsiTruncate::new(&theme.tab_bar, ansi::RESET);
+        // the bar's background is brought back after the current tab.
+        let mut bar = theme.tab_bar.clone();
+        for (label, current) in labels {
+            if *current {
+                bar.push_str(&format!("{}{}{}{}", theme.tab_current, label, ansi::RESET, theme.tab_bar));
+            } else {
+                bar.push_str(label);
+            }
+        }
+        let _ = self.top.add_to_section(bar, &mut bar_strategy, Alignment::Plus);
+    }
+    // Adds the current mode to the bottom line, along with anything else worth knowing about it.
+    pub fn show_mode(&mut self, mode: Mode, extra: &str, theme: &Theme) {
+        let mut mode_strategy = ansi_truncate::AnsiTruncate::new(&theme.mode, ansi::RESET);
+        let _ = self
+            .bottom
+            .add_to_section(format!("-- {} -- {}", mode.name(), extra), &mut mode_strategy, Alignment::Plus);
+    }
+    // Adds the command being typed to the bottom line, with the cursor highlighted and a hint about it after it.
+    pub fn show_command_line(&mut self, command_line: &CommandLine, hint: &str, theme: &Theme) {
+        let mut command_strategy = ansi_truncate::AnsiTruncate::new(&theme.text, ansi::RESET);
+        let input = &command_line.input;
+        let cursor = command_line.cursor;
+        let under = input.get(cursor).copied().unwrap_or(' ');
+        let line = format!(
+            "{}{}{}{}{}{}{}",
+            command_line.prompt,
+            input[..cursor].iter().collect::<String>(),
+            theme.cursor,
+            under,
+            theme.text,
+            input[(cursor + 1).min(input.len())..].iter().collect::<String>(),
+            hint
+        );
+        let _ = self.bottom.add_to_section(line, &mut command_strategy, Alignment::Plus);
+    }
+    // Adds a message to the bottom line. Errors are shown in the error color.
+    pub fn show_message(&mut self, message: &Message, theme: &Theme) {
+        let (text, mut strategy) = match message {
+            Message::Info(val) => (val, ansi_truncate::AnsiTruncate::new(&theme.text, ansi::RESET)),
+            Message::Error(val) => (val, ansi_truncate::AnsiTruncate::new(&theme.error, ansi::RESET)),
+        };
+        let _ = self.bottom.add_to_section(text.clone(), &mut strategy, Alignment::Plus);
+    }
+    // Prints every section out to the terminal.
+    pub fn print(&mut self) {
+        let areas = self.areas.iter_mut().flat_map(|x| [&mut x.headers, &mut x.text, &mut x.status]);
+        for d in [&mut self.top].into_iter().chain(areas).chain(self.dividers.iter_mut()).chain([&mut self.bottom]) {
+            d.print(&mut CrosstermHandler, &mut stdout())
+                .expect("Error queueing display instructions");
+        }
+        // flushes the queued instructions out onto the screen.
+        CrosstermHandler::finish(&mut stdout()).expect("Error flushing display queue");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::{ColorDepth, ThemeFile};
+
+    #[test]
+    fn panes_that_dont_fit_are_left_out() {
+        let theme = Theme::new(&ThemeFile::default(), ColorDepth::TrueColor);
+        let layout = Layout::Split {
+            vertical: false,
+            ratio: 0.5,
+            first: Box::new(Layout::Pane(1)),
+            second: Box::new(Layout::Pane(0)),
+        };
+        let screen = Screen::new(&Frame::new(0, 0, 80, 24), &layout, &theme);
+        assert_eq!(screen.areas.iter().map(|x| x.pane).collect::<Vec<_>>(), vec![1, 0]);
+        // two rows can't hold both panes' status lines as well as a line between them.
+        let screen = Screen::new(&Frame::new(0, 0, 80, 4), &layout, &theme);
+        assert!(screen.areas.is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::{config::Config, textbox::kind};
+
+// A search that has been entered, kept around for finding the next and previous matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Search {
+    pub pattern: String,
+    // whether it was entered at the `?` prompt, which searches towards the start of the text.
+    pub backward: bool,
+}
+// Finds every place a pattern shows up in some text, as ranges of character indexes. Matches don't overlap.
+// The config decides whether the pattern is a regular expression, whether case matters, and whether matches have to be
+// whole words. A regular expression that doesn't parse matches nothing.
+pub fn find_all(text: &[char], pattern: &[char], config: &Config) -> Vec<(usize, usize)> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    if config.regex_search {
+        let regex = match build_regex(&pattern.iter().collect::<String>(), config.ignore_case) {
+            Ok(val) => val,
+            Err(_) => return Vec::new(),
+        };
+        let matches = replacements(&regex, &text.iter().collect::<String>(), None);
+        return matches
+            .into_iter()
+            .map(|(start, end, _)| (start, end))
+            .filter(|(start, end)| !config.whole_word || (is_boundary(text, *start) && is_boundary(text, *end)))
+            .collect();
+    }
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i + pattern.len() <= text.len() {
+        if matches_at(text, i, pattern, config) {
+            matches.push((i, i + pattern.len()));
+            i += pattern.len();
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+// Whether the pattern shows up at an index of the text.
+fn matches_at(text: &[char], i: usize, pattern: &[char], config: &Config) -> bool {
+    let same = |a: char, b: char| {
+        if config.ignore_case {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    };
+    if !pattern.iter().zip(&text[i..]).all(|(a, b)| same(*a, *b)) {
+        return false;
+    }
+    !config.whole_word || (is_boundary(text, i) && is_boundary(text, i + pattern.len()))
+}
+// Whether a word can start or end at an index: words end wherever the kind of character changes.
+fn is_boundary(text: &[char], i: usize) -> bool {
+    i == 0 || i == text.len() || kind(text[i - 1]) != kind(text[i])
+}
+// Builds a regular expression, where ^ and $ match at the start and end of every line.
+pub fn build_regex(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .build()
+        .map_err(|e| e.to_string().lines().last().unwrap_or("Invalid pattern").trim().to_string())
+}
+// Finds every match of a regular expression as ranges of character indexes, along with what each match would be
+// replaced with if a replacement is given. `$1` or `${name}` in the replacement stand for what a group matched.
+pub fn replacements(regex: &Regex, text: &str, replacement: Option<&str>) -> Vec<(usize, usize, String)> {
+    // the matches come in order, so the characters only need to be counted up to each one.
+    let (mut bytes, mut chars) = (0, 0);
+    let mut char_index = |byte: usize| {
+        chars += text[bytes..byte].chars().count();
+        bytes = byte;
+        chars
+    };
+    regex
+        .captures_iter(text)
+        .map(|captures| {
+            let whole = captures.get(0).expect("Safe unwrap");
+            let mut replaced = String::new();
+            if let Some(replacement) = replacement {
+                captures.expand(replacement, &mut replaced);
+            }
+            (char_index(whole.start()), char_index(whole.end()), replaced)
+        })
+        .collect()
+}
@@ -0,0 +1,177 @@
+use std::path::Path;
+
+use grid_ui::grid::Frame;
+
+use crate::{
+    buffers::{Buffers, Request},
+    command_line::{Command, Message},
+    panes::Panes,
+    render,
+    screen::Screen,
+    State,
+};
+
+// Tab pages, each with panes laid out in its own way. One of them is shown at a time, under a bar with a label for each.
+pub struct Tabs {
+    tabs: Vec<Panes>,
+    current: usize,
+}
+impl Tabs {
+    // Starts out with one tab, showing the current buffer.
+    pub fn new(buffers: &mut Buffers) -> Tabs {
+        Tabs {
+            tabs: vec![Panes::new(buffers.current_id(), buffers.current().view())],
+            current: 0,
+        }
+    }
+    // Carries out something a textbox asked for. Anything that isn't about the tabs is left to the current tab's panes.
+    pub fn handle(&mut self, request: Request, buffers: &mut Buffers, state: &mut State, frame: &Frame) {
+        match request {
+            Request::Command(Command::TabNew(path)) => {
+                self.tabs[self.current].leave(buffers, state);
+                self.tabs.insert(self.current + 1, Panes::new(buffers.current_id(), buffers.current().view()));
+                self.current += 1;
+                if let Some(path) = path {
+                    buffers.handle(Request::Open(path, None), state);
+                }
+            }
+            Request::Command(Command::TabNext) => self.switch((self.current + 1) % self.tabs.len(), buffers, state),
+            Request::Command(Command::TabPrevious) => {
+                self.switch((self.current + self.tabs.len() - 1) % self.tabs.len(), buffers, state);
+            }
+            Request::Command(Command::TabClose) => self.close(buffers, state),
+            Request::Command(Command::TabMove { amount, relative }) => {
+                let target = if relative { (self.current as isize).saturating_add(amount) } else { amount };
+                let target = target.clamp(0, self.tabs.len() as isize - 1) as usize;
+                let tab = self.tabs.remove(self.current);
+                self.tabs.insert(target, tab);
+                self.current = target;
+            }
+            request => self.tabs[self.current].handle(request, buffers, state, frame),
+        }
+        // closing a buffer can leave panes in any of the tabs without one.
+        for (i, tab) in self.tabs.iter_mut().enumerate() {
+            tab.sync(buffers, i == self.current);
+        }
+    }
+    // Shows another tab.
+    pub fn switch(&mut self, tab: usize, buffers: &mut Buffers, state: &mut State) {
+        if tab == self.current || tab >= self.tabs.len() {
+            return;
+        }
+        self.tabs[self.current].leave(buffers, state);
+        self.current = tab;
+        self.tabs[self.current].enter(buffers, state);
+    }
+    // Closes the current tab, and shows the one after it. The buffers its panes showed stay open.
+    pub fn close(&mut self, buffers: &mut Buffers, state: &mut State) {
+        if self.tabs.len() == 1 {
+            state.message = Some(Message::Error("Cannot close last tab page".to_string()));
+            return;
+        }
+        self.tabs[self.current].leave(buffers, state);
+        self.tabs.remove(self.current);
+        self.current = self.current.min(self.tabs.len() - 1);
+        self.tabs[self.current].enter(buffers, state);
+    }
+    // The label for each tab in the bar: its number, the name of the file its focused pane shows, and a + if that file
+    // has unsaved changes. The current tab is marked.
+    pub fn labels(&self, buffers: &mut Buffers) -> Vec<(String, bool)> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(i, tab)| {
+                let text_box = buffers.get(tab.focused_buffer());
+                let path = text_box.path();
+                let name = Path::new(path).file_name().map(|x| x.to_string_lossy().to_string());
+                let modified = if text_box.modified() { " +" } else { "" };
+                (format!(" {} {}{} ", i + 1, name.unwrap_or_else(|| path.to_string()), modified), i == self.current)
+            })
+            .collect()
+    }
+    // Shows the tab whose label was clicked on in the bar.
+    pub fn click(&mut self, column: usize, buffers: &mut Buffers, state: &mut State) {
+        let mut end = 0;
+        for (i, (label, _)) in self.labels(buffers).into_iter().enumerate() {
+            end += render::text_width(&label);
+            if column < end {
+                self.switch(i, buffers, state);
+                return;
+            }
+        }
+    }
+    // Draws the tab bar, the current tab's panes, and the bottom line.
+    pub fn display(&mut self, buffers: &mut Buffers, frame: &Frame, state: &State) {
+        let tab = &mut self.tabs[self.current];
+        let mut screen = Screen::new(frame, tab.layout(), &state.theme);
+        tab.display(&mut screen, buffers, state);
+        let labels = self.labels(buffers);
+        screen.show_tabs(&labels, &state.theme);
+        buffers.current().display_bottom(&mut screen, state);
+        screen.print();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textbox::tests::state;
+
+    fn run(command: Command, tabs: &mut Tabs, buffers: &mut Buffers, state: &mut State) {
+        tabs.handle(Request::Command(command), buffers, state, &Frame::new(0, 0, 80, 24));
+    }
+
+    // The names in the tab bar, with the current one in brackets.
+    fn bar(tabs: &Tabs, buffers: &mut Buffers) -> String {
+        let labels = tabs.labels(buffers).into_iter();
+        labels.map(|(x, current)| if current { format!("[{}]", x.trim()) } else { x.trim().to_string() }).collect()
+    }
+
+    // Buffers for files that are never written.
+    fn buffers(state: &State) -> Buffers {
+        let dir = std::env::temp_dir().join(format!("textedit-tabs-{}", std::process::id()));
+        let paths = ["a.txt", "b.txt"].map(|x| dir.join(x).to_string_lossy().to_string());
+        Buffers::new(&paths, &state.config)
+    }
+
+    #[test]
+    fn switching_tabs() {
+        let mut state = state();
+        let mut buffers = buffers(&state);
+        let mut tabs = Tabs::new(&mut buffers);
+        let b = buffers.get(2).path().to_string();
+        run(Command::TabNew(Some(b)), &mut tabs, &mut buffers, &mut state);
+        assert_eq!(bar(&tabs, &mut buffers), "1 a.txt[2 b.txt]");
+        assert_eq!(buffers.current_id(), 2);
+        // each tab shows its own buffer again when it's switched to.
+        run(Command::TabNext, &mut tabs, &mut buffers, &mut state);
+        assert_eq!(bar(&tabs, &mut buffers), "[1 a.txt]2 b.txt");
+        assert_eq!(buffers.current_id(), 1);
+        run(Command::TabPrevious, &mut tabs, &mut buffers, &mut state);
+        assert_eq!(buffers.current_id(), 2);
+        run(Command::TabMove { amount: 0, relative: false }, &mut tabs, &mut buffers, &mut state);
+        assert_eq!(bar(&tabs, &mut buffers), "[1 b.txt]2 a.txt");
+        tabs.click(10, &mut buffers, &mut state);
+        assert_eq!(bar(&tabs, &mut buffers), "1 b.txt[2 a.txt]");
+    }
+
+    #[test]
+    fn closing_tabs() {
+        let mut state = state();
+        let mut buffers = buffers(&state);
+        let mut tabs = Tabs::new(&mut buffers);
+        run(Command::TabClose, &mut tabs, &mut buffers, &mut state);
+        assert!(matches!(state.message, Some(Message::Error(_))));
+        assert_eq!(tabs.tabs.len(), 1);
+        state.message = None;
+        run(Command::TabNew(None), &mut tabs, &mut buffers, &mut state);
+        run(Command::BufferNext, &mut tabs, &mut buffers, &mut state);
+        assert_eq!(bar(&tabs, &mut buffers), "1 a.txt[2 b.txt]");
+        // the tab before takes over, and the buffer it was showing is shown again. The closed tab's buffer stays open.
+        run(Command::TabClose, &mut tabs, &mut buffers, &mut state);
+        assert_eq!(bar(&tabs, &mut buffers), "[1 a.txt]");
+        assert_eq!(buffers.current_id(), 1);
+        assert!(buffers.contains(2));
+        assert!(state.message.is_none());
+    }
+}
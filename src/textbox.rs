@@ -1,15 +1,35 @@
-use std::io::stdout;
-
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use grid_ui::{
-    crossterm::CrosstermHandler,
-    grid::{Alignment, DividerStrategy},
-    process::DrawProcess,
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, Instant},
 };
 
-use crate::{ansi, ansi_truncate, State, debug};
+use crossterm::event::{KeyCode, KeyEvent};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+use grid_ui::{grid::Alignment, process::DrawProcess};
 
-pub const UNDOS: usize = 10;
+use crate::{
+    action::{default_action, Action, Operator, Output},
+    ansi, ansi_truncate,
+    command_line::{Amount, Command, Message, Substitute},
+    config::Config,
+    buffers::Request,
+    format::FileFormat,
+    grep::GrepEvent,
+    highlight::{Grammar, Highlighter},
+    history::{content_hash, Edit, TextBoxHistory},
+    history_browser::HistoryBrowser,
+    keymap::Mode,
+    panes::Direction,
+    render,
+    rope::Rope,
+    screen::{Area, Screen},
+    search::{build_regex, find_all, replacements, Search},
+    theme::Theme,
+    State,
+};
 
 pub fn kind(c: char) -> i8 {
     if c.is_ascii_alphabetic() {           // alpha
@@ -24,7 +44,81 @@ pub fn kind(c: char) -> i8 {
         0b_0000_1000
     }
 }
-#[derive(Clone, Copy, Debug)]
+// Changes the case of some text for one of the case operators.
+pub fn change_case(operator: Operator, text: &str) -> String {
+    match operator {
+        Operator::Lowercase => text.to_lowercase(),
+        Operator::Uppercase => text.to_uppercase(),
+        _ => text
+            .chars()
+            .flat_map(|c| {
+                if c.is_uppercase() {
+                    c.to_lowercase().collect::<Vec<_>>()
+                } else {
+                    c.to_uppercase().collect()
+                }
+            })
+            .collect(),
+    }
+}
+// Draws a line of text with some of its characters (or cells) styled. A style just past the end of the line draws a
+// space there, so that a cursor at the end of a line can be seen. The background is brought back after each styled
+// stretch.
+pub fn styled_line<T: Display>(line: &[T], styles: &[Option<&str>], background: &str) -> String {
+    let mut collected = String::new();
+    let mut current = None;
+    for (i, style) in styles.iter().enumerate() {
+        let c = match line.get(i) {
+            Some(val) => val.to_string(),
+            None if style.is_some() => " ".to_string(),
+            None => break,
+        };
+        if *style != current {
+            collected.push_str(ansi::RESET);
+            collected.push_str(background);
+            collected.push_str(style.unwrap_or(""));
+            current = *style;
+        }
+        collected.push_str(&c);
+    }
+    if current.is_some() {
+        collected.push_str(ansi::RESET);
+        collected.push_str(background);
+    }
+    collected
+}
+// Puts a marker in a row of cells, at the edge where the line is cut off. Cursors and selections are drawn over it.
+// A wide character it lands on half of is drawn as a space.
+fn clip_marker<'a>(cells: &mut Vec<String>, styles: &mut Vec<Option<&'a str>>, column: usize, marker: char, style: &'a str) {
+    if cells.len() <= column {
+        cells.resize(column + 1, " ".to_string());
+    }
+    if cells[column].is_empty() && column > 0 {
+        cells[column - 1] = " ".to_string();
+    }
+    if let Some(cell) = cells.get_mut(column + 1).filter(|x| x.is_empty()) {
+        *cell = " ".to_string();
+    }
+    cells[column] = marker.to_string();
+    if styles.len() <= column {
+        styles.resize(column + 1, None);
+    }
+    if styles[column].is_none() {
+        styles[column] = Some(style);
+    }
+}
+// Calculates the first line to show so that the current line stays in the middle of the screen when possible.
+pub fn centered_start(current_line: usize, total_length: usize, height: usize) -> usize {
+    let half_pos = height / 2;
+    if current_line < half_pos || total_length <= height {
+        0
+    } else if current_line >= total_length - half_pos {
+        total_length - height
+    } else {
+        current_line - half_pos
+    }
+}
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub struct Cursor {
     pub x_pos: usize,
     pub y_pos: usize,
@@ -39,167 +133,263 @@ impl Cursor {
         }
     }
 }
-pub struct TextBoxUndos {
-    pub cursor: Cursor,
-    pub contents: Vec<Vec<char>>,
-}
-pub struct TextBoxHistory {
-    pos: usize,
-    undos: Vec<TextBoxUndos>,
+// A substitute that asks before each replacement.
+pub struct Confirm {
+    // every match, as indexes into the text as it was before anything was replaced, along with what it's replaced with.
+    matches: Vec<(usize, usize, String)>,
+    // the match being asked about.
+    current: usize,
+    // how far the replacements so far have moved the matches after them.
+    shift: isize,
+    replaced: usize,
 }
-impl TextBoxHistory {
-    pub fn new() -> TextBoxHistory {
-        TextBoxHistory { pos: 0, undos: Vec::new() }
-    }
-    pub fn start(&mut self) {
-        self.pos = 0;
-    }
-    pub fn action(&mut self, tbco: &mut Vec<Vec<char>>, tbcu: &mut Cursor) {
-        self.pos += 1;
-        if self.pos > UNDOS {
-            self.undos.remove(0);
-            self.pos -= 1;
-        }
-        self.undos.push(TextBoxUndos {
-            cursor: tbcu.clone(),
-            contents: tbco.clone(),
-        });
-        if self.pos < self.undos.len() - 1 {
-            self.undos.drain((self.pos + 1)..);
-        }
-        
-    }
-    pub fn undo(&mut self, tbco: &mut Vec<Vec<char>>, tbcu: &mut Cursor) {
-        debug::debug(&format!("UNDOING! POS: {}", self.pos));
-        if self.pos > 0 {
-            debug::debug(&format!("WE DID IT!!!"));
-            self.pos -= 1;
-            let undo = &self.undos[self.pos];
-            *tbcu = undo.cursor.clone();
-            *tbco = undo.contents.clone();
-        }
-    }
-    pub fn redo(&mut self, tbco: &mut Vec<Vec<char>>, tbcu: &mut Cursor) {
-        if self.pos < self.undos.len() - 1 {
-            self.pos += 1;
-            let redo = &self.undos[self.pos];
-            *tbcu = redo.cursor.clone();
-            *tbco = redo.contents.clone();
-        }
-    }
+// Where the cursors are. Each pane keeps one of these for the buffer it shows, while another pane has the focus.
+#[derive(Clone, Debug, Default)]
+pub struct View {
+    pub cursor: Cursor,
+    pub cursors: Vec<Cursor>,
+    // how many columns the text is scrolled to the left.
+    pub scroll: usize,
 }
 pub struct TextBox {
+    // the main cursor, which the view follows.
     cursor: Cursor,
-    contents: Vec<Vec<char>>,
+    // every other cursor. Edits and motions happen at all of them at once.
+    cursors: Vec<Cursor>,
+    contents: Rope,
     path: String,
     history: TextBoxHistory,
+    // a hash of the file as it is on disk, used to check that a saved history still matches it.
+    disk_hash: u64,
+    // the undo tree browser, if it's open.
+    browser: Option<HistoryBrowser>,
+    // an operator waiting for the motion that tells it what to act on.
+    operator: Option<Operator>,
+    // the last rectangle that was yanked, so that pasting it can put it back as a rectangle.
+    block_yank: Option<String>,
+    // a block insert that's being typed: the first and last lines, the column on the screen, and whether short lines get
+    // padded out to it.
+    block_insert: Option<(usize, usize, usize, bool)>,
+    // where the cursor was when the search prompt opened, so the search can start from there as it's typed.
+    search_origin: Option<Cursor>,
+    // a substitute that's waiting to be told whether to replace the match under the cursor.
+    confirm: Option<Confirm>,
+    // the line endings and encoding the file was read with.
+    format: FileFormat,
+    // a message for the status line, and when it was given. It goes away after a while.
+    status_message: Option<(String, Instant)>,
+    // how wide the rows are that long lines are wrapped onto, if they're wrapped. It's the width of the focused pane
+    // the last time it was drawn, which is what moving up and down by row goes by.
+    wrap_width: Option<usize>,
+    // how many columns the text is scrolled to the left, so the cursor can be seen on long lines that aren't wrapped.
+    scroll: usize,
+    // how far apart the tab stops are, and whether spaces are put in rather than tabs. They're kept up to date with the
+    // config as keys come in, and as the text is drawn.
+    tab_width: usize,
+    expand_tab: bool,
+    // colors the text according to the grammar for the file's extension, if there is one.
+    highlighter: Highlighter,
+    // the text as a list of characters, for searching it. It's made when a search needs it, and kept until the text
+    // changes, so that typing a search or going through its matches doesn't copy the whole text every time.
+    search_text: Option<Vec<char>>,
 }
 impl TextBox {
-    pub fn new(lines: Vec<String>, path: String) -> TextBox {
-        let mut tb = TextBox {
-            cursor: Cursor::new(),
-            contents: lines.into_iter().map(|x| x.chars().collect()).collect(),
+    // Creates a textbox out of a file's contents. The hash is of the file as it was read from disk.
+    pub fn new(contents: Rope, path: String, disk_hash: u64, format: FileFormat, config: &Config) -> TextBox {
+        // picks up the history from the last time the file was open, if it was saved.
+        let (history, cursor) = TextBoxHistory::load(&path, disk_hash, config).unwrap_or_else(|| {
+            let history = TextBoxHistory::new(
+                config.undo_budget,
+                Duration::from_millis(config.undo_group_pause),
+            );
+            (history, Cursor::new())
+        });
+        let highlighter = Highlighter::new(Grammar::find(&path, &config.grammar_dir));
+        TextBox {
+            cursor,
+            cursors: Vec::new(),
+            contents,
             path,
-            history: TextBoxHistory::new(),
-        };
-        tb.history.action(&mut tb.contents, &mut tb.cursor);
-        tb.history.start();
-        tb
+            history,
+            disk_hash,
+            browser: None,
+            operator: None,
+            block_yank: None,
+            block_insert: None,
+            search_origin: None,
+            confirm: None,
+            format,
+            status_message: None,
+            wrap_width: None,
+            scroll: 0,
+            tab_width: config.tabstop.max(1),
+            expand_tab: config.expandtab,
+            highlighter,
+            search_text: None,
+        }
     }
+    // Writes the undo history to disk, if the file exists and persistent undo is turned on.
+    pub fn save_history(&mut self, config: &Config) {
+        if Path::new(&self.path).exists() {
+            let _ = self.history.save(&self.path, self.disk_hash, config, &self.cursor);
+        }
+    }
+    // Marks the end of an action, so everything edited since the last one is undone in one step.
     pub fn action(&mut self) {
-        self.history.action(&mut self.contents, &mut self.cursor);
+        self.history.action(&self.cursor);
     }
-    pub fn set_valid_pos(&mut self) {
-        let len = self.contents[self.cursor.y_pos].len();
-        if self.cursor.x_pos > len {
-            self.cursor.x_pos = len;
+    // Starts a compound command. Everything it edits is undone in one step once end_group is called.
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+    pub fn end_group(&mut self) {
+        self.history.end_group(&self.cursor);
+    }
+    // The number of characters in a line.
+    pub fn line_len(&self, y: usize) -> usize {
+        self.contents.line_len(y)
+    }
+    // Converts an (x, y) position into an index into the contents.
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        self.contents.line_to_char(y) + x
+    }
+    // Converts an (x, y) position into an index, treating positions past the end of a line as the end of the line.
+    pub fn clamped_index(&self, x: usize, y: usize) -> usize {
+        self.index(x.min(self.line_len(y)), y)
+    }
+    // Converts an index into the contents into an (x, y) position.
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        let y = self.contents.char_to_line(index);
+        (index - self.contents.line_to_char(y), y)
+    }
+    // Replaces the text between two indexes, recording the change in the history.
+    pub fn replace(&mut self, start: usize, end: usize, text: &str) {
+        let edit = Edit {
+            index: start,
+            removed: self.contents.slice(start, end),
+            inserted: text.to_string(),
+        };
+        if edit.removed.is_empty() && edit.inserted.is_empty() {
+            return;
         }
+        self.changed(self.contents.char_to_line(start));
+        edit.apply(&mut self.contents);
+        self.history.record(edit, &self.cursor);
+    }
+    // Forgets whatever was worked out from the text that comes after the start of a changed line.
+    pub fn changed(&mut self, line: usize) {
+        self.highlighter.invalidate(line);
+        self.search_text = None;
+    }
+    // Removes the text between two indexes.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        self.replace(start, end, "");
+    }
+    // Inserts text at the cursor, and moves the cursor to the end of the inserted text.
+    pub fn insert(&mut self, text: &str) {
+        let index = self.index(self.cursor.x_pos, self.cursor.y_pos);
+        self.replace(index, index, text);
+        let (x, y) = self.position(index + text.chars().count());
+        self.cursor.x_pos = x;
+        self.cursor.y_pos = y;
+    }
+    // Keeps the cursor within its line, and at the start of a grapheme rather than partway through one.
+    pub fn set_valid_pos(&mut self) {
+        self.cursor.x_pos = self.grapheme_start(self.cursor.x_pos, self.cursor.y_pos);
     }
     pub fn set_valid_pos_h(&mut self) {
         if let Some((x, y)) = self.cursor.highlight {
-            let len = self.contents[y].len();
-            if x > len {
-                self.cursor.highlight = Some((len, y));
+            self.cursor.highlight = Some((self.grapheme_start(x, y), y));
+        }
+    }
+    // The start of the grapheme a position is in, or the end of the line if it's past it.
+    pub fn grapheme_start(&self, x: usize, y: usize) -> usize {
+        let line = self.contents.line_chars(y);
+        if x >= line.len() {
+            return line.len();
+        }
+        render::boundaries(&line).into_iter().take_while(|b| *b <= x).last().unwrap_or(0)
+    }
+    // The start of the grapheme after the one a position is in, or the end of the line.
+    pub fn next_boundary(&self, x: usize, y: usize) -> usize {
+        let line = self.contents.line_chars(y);
+        render::boundaries(&line).into_iter().find(|b| *b > x).unwrap_or(line.len())
+    }
+    // Where a position ends up after moving a grapheme (or a word) to the left or right. It goes onto the next or
+    // previous line at the ends of lines.
+    pub fn moved(&self, x: usize, y: usize, forward: bool, word: bool) -> (usize, usize) {
+        let (mut x, mut y) = (x, y);
+        for _ in 0..self.word_length_toggle(!forward, x, y, word) {
+            if !forward && x > 0 {
+                x -= 1;
+            } else if !forward && y > 0 {
+                y -= 1;
+                x = self.line_len(y);
+            } else if forward && x < self.line_len(y) {
+                x += 1;
+            } else if forward && y + 1 < self.contents.len_lines() {
+                y += 1;
+                x = 0;
             }
         }
+        // a step that lands partway through a grapheme takes in the rest of it.
+        if forward && x != self.grapheme_start(x, y) {
+            x = self.next_boundary(x, y);
+        }
+        (self.grapheme_start(x, y), y)
     }
-    pub fn highlight(&mut self) -> Option<String> {
+    // Where the next word starts: past the rest of the word the position is in, and then past any whitespace, newlines
+    // included. An empty line counts as a word of its own.
+    pub fn next_word_start(&self, x: usize, y: usize) -> (usize, usize) {
+        let (mut x, mut y) = (x, y);
+        let mut line = self.contents.line_chars(y);
+        if x < line.len() && !line[x].is_whitespace() {
+            let word = kind(line[x]);
+            while x < line.len() && kind(line[x]) == word {
+                x += 1;
+            }
+        }
+        loop {
+            while x < line.len() && line[x].is_whitespace() {
+                x += 1;
+            }
+            if x < line.len() || y + 1 >= self.contents.len_lines() {
+                break;
+            }
+            y += 1;
+            x = 0;
+            line = self.contents.line_chars(y);
+            if line.is_empty() {
+                break;
+            }
+        }
+        // a word that ends partway through a grapheme takes in the rest of it.
+        if x != self.grapheme_start(x, y) {
+            x = self.next_boundary(x, y);
+        }
+        (x, y)
+    }
+    // The indexes of the start and end of the highlighted text.
+    pub fn highlight_range(&mut self) -> Option<(usize, usize)> {
         self.set_valid_pos();
         self.set_valid_pos_h();
         let (x2, y2) = self.cursor.highlight?;
-        let (x1, y1) = (self.cursor.x_pos, self.cursor.y_pos);
-        if y1 == y2 {
-            // if the highlight is on one line... return the characters between the two x positions.
-            let min = x1.min(x2);
-            let max = x1.max(x2);
-            Some(self.contents[y1][min..max].iter().collect::<String>())
-        } else if y1 < y2 {
-            // if the highlight goes downwards...
-            let mut s = String::new();
-            // return the characters after the cursor position...
-            s.push_str(&self.contents[y1][x1..].iter().collect::<String>());
-            for i in y1 + 1..y2 {
-                // all the lines in between...
-                s.push_str(&self.contents[i].iter().collect::<String>());
-            }
-            // and before the highlight position. 
-            s.push_str(&self.contents[y1][..x2].iter().collect::<String>());
-            Some(s)
-        } else { // y2 < y1
-            // if the highlight goes upwards...
-            let mut s = String::new();
-            // return the characters after the highlight position...
-            s.push_str(&self.contents[y2][x2..].iter().collect::<String>());
-            for i in y2 + 1..y1 {
-                // all the lines in between...
-                s.push_str(&self.contents[i].iter().collect::<String>());
-            }
-            // and before the cursor position.
-            s.push_str(&self.contents[y1][..x1].iter().collect::<String>());
-            Some(s)
-        }
+        let first = self.index(self.cursor.x_pos, self.cursor.y_pos);
+        let second = self.index(x2, y2);
+        Some((first.min(second), first.max(second)))
+    }
+    pub fn highlight(&mut self) -> Option<String> {
+        let (start, end) = self.highlight_range()?;
+        Some(self.contents.slice(start, end))
     }
     pub fn remove_highlight(&mut self) {
-        self.set_valid_pos();
-        self.set_valid_pos_h();
-        if let Some((x2, y2)) = self.cursor.highlight {
-            let (x1, y1) = (self.cursor.x_pos, self.cursor.y_pos);
-            if y1 == y2 {
-                // if the highlight is on one line... remove the characters between the two x positions.
-                let min = x1.min(x2);
-                let max = x1.max(x2);
-                self.contents[y1].drain(min..max);
-                self.cursor.x_pos = min;
-            } else if y1 < y2 {
-                self.cursor.x_pos = x1;
-                self.cursor.y_pos = y1;
-                // if the highlight goes downwards... remove the characters after the cursor position
-                self.contents[y1].drain(x1..);
-                // and before the highlight position. 
-                self.contents[y2].drain(..x2);
-                let mut removed = self.contents.remove(y2);
-                // Append them onto the previous line
-                self.contents[y1].append(&mut removed);
-                for _ in y1 + 1..y2 {
-                    // and remove all the lines in between.
-                    self.contents.remove(y1 + 1);
-                }
-            } else { // y2 < y1
-                self.cursor.x_pos = x2;
-                self.cursor.y_pos = y2;
-                // if the highlight goes upwards... remove the characters after the highlight position
-                self.contents[y2].drain(x2..);
-                // and before the cursor position. 
-                self.contents[y1].drain(..x1);
-                let mut removed = self.contents.remove(y1);
-                // Append them onto the first line
-                self.contents[y2].append(&mut removed);
-                for _ in y2 + 1..y1 {
-                    // and remove all the lines in between. 
-                    self.contents.remove(y2 + 1);
-                }
-            }
-            if y1 != y2 || x1 != x2 {
+        if let Some((start, end)) = self.highlight_range() {
+            // moves the cursor to whichever end of the highlight comes first, and removes the text in between.
+            self.remove(start, end);
+            let (x, y) = self.position(start);
+            self.cursor.x_pos = x;
+            self.cursor.y_pos = y;
+            if start != end {
                 self.action();
             }
         }
@@ -209,13 +399,13 @@ impl TextBox {
     // Calculates the length of the word at the cursor.
     // words are defined as a sequence of numbers, letters, punctuation/symbols, or whitespace characters. 
     pub fn word_length(&self, x: usize, y: usize, reverse: bool) -> usize {
+        let line = self.contents.line_chars(y);
         if !reverse {
             // goes forward
-            if x == self.contents[y].len() {
+            if x == line.len() {
                 return 1;
             }
             let mut offset = 1;
-            let line = &self.contents[y];
             let mut kind_res = kind(line[x]);
             while offset + x < line.len() 
                 && kind(line[offset + x]) >= 0 {
@@ -232,7 +422,6 @@ impl TextBox {
                 return 1;
             }
             let mut offset = 1;
-            let line = &self.contents[y];
             let mut kind_res = kind(line[x - 1]);
             while x - offset > 0 
                 && kind(line[x - offset - 1]) >= 0 {
@@ -242,479 +431,2113 @@ impl TextBox {
                     }
                     offset += 1;
             }
-            debug::debug(&format!("GOT REV {}", offset));
             offset
         }
     }
     pub fn word_length_toggle(&self, reverse: bool, x: usize, y: usize, ctrl: bool) -> usize {
         if ctrl { self.word_length(x, y, reverse) } else { 1 }
     }
-    pub fn word_length_toggle_c(&self, reverse: bool, ctrl: bool) -> usize {
-        self.word_length_toggle(reverse, self.cursor.x_pos, self.cursor.y_pos, ctrl)
-    }
-    pub fn word_length_toggle_h(&self, reverse: bool, ctrl: bool) -> usize {
-        let (x, y) = self.cursor.highlight.unwrap_or((self.cursor.x_pos, self.cursor.y_pos));
-        self.word_length_toggle(reverse, x, y, ctrl)
+    // Handles a key or action from the key map.
+    pub fn recv(&mut self, output: Output, state: &mut State) {
+        self.configure_tabs(&state.config);
+        match output {
+            Output::Key(k) => self.recv_key(k, state),
+            Output::Action(action) => self.recv_action(action, state),
+        }
     }
     // Handles the key press. 
-    pub fn recv_key(
-        &mut self,
-        k: KeyEvent,
-        d: &mut DrawProcess,
-        headers: &mut DrawProcess,
-        state: &mut State,
-    ) {
-        let KeyEvent { code, modifiers } = k;
-        match code {
-            // deletes the previous character if there is one. Merges two lines if needed.
-            KeyCode::Backspace => {
-                self.set_valid_pos();
-                if let Some(_) = self.cursor.highlight {
-                    self.remove_highlight();
-                } else {
-                    for _ in 0..self.word_length_toggle_c(true, modifiers.contains(KeyModifiers::CONTROL)) {
-                        // if we're at the beginning of a line...
-                        if self.cursor.x_pos == 0 {
-                            // if we're not on the first line...
-                            if self.cursor.y_pos != 0 {
-                                // move the cursor to the end of the previous line
-                                self.cursor.x_pos = self.contents[self.cursor.y_pos - 1].len();
-                                // add the current line onto the previous line
-                                let mut this_line = self.contents.remove(self.cursor.y_pos);
-                                self.contents[self.cursor.y_pos - 1].append(&mut this_line);
-                                // we're on the previous line now
-                                self.cursor.y_pos -= 1;
-                            }
-                        } else {
-                            self.contents[self.cursor.y_pos].remove(self.cursor.x_pos - 1);
-                            self.cursor.x_pos -= 1;
-                        }
+    pub fn recv_key(&mut self, k: KeyEvent, state: &mut State) {
+        // while the command line is open, it gets every key press.
+        if state.command_line.open {
+            let entered = state.command_line.recv_key(k, &mut state.message);
+            if self.search_origin.is_some() {
+                self.update_search(entered, state);
+            } else if let Some(line) = entered {
+                self.execute(&line, state);
+            }
+            return;
+        }
+        // while closing an unsaved buffer waits to be confirmed, a yes closes it and anything else doesn't.
+        if state.confirm_close {
+            state.confirm_close = false;
+            if k.code == KeyCode::Char('y') {
+                state.request = Some(Request::Command(Command::BufferDelete(true)));
+            }
+            return;
+        }
+        // while a substitute is asking about a match, it gets every key press.
+        if self.confirm.is_some() {
+            self.confirm_key(k, state);
+            return;
+        }
+        // while the results of a project-wide search are shown, they get every key press.
+        if let Some(grep) = state.grep.as_mut().filter(|x| x.open) {
+            match grep.recv_key(k) {
+                Some(GrepEvent::Open(path, line)) => state.request = Some(Request::Open(path, Some(line))),
+                Some(GrepEvent::Replace(replacement)) => state.request = Some(Request::Replace(replacement)),
+                None => {}
+            }
+            return;
+        }
+        // while the undo tree browser is open, it gets every key press.
+        if let Some(browser) = &mut self.browser {
+            if !browser.recv_key(k, &mut self.history, &mut self.contents, &mut self.cursor) {
+                self.browser = None;
+            }
+            self.changed(0);
+            return;
+        }
+        // otherwise, the key does whatever it does by default in the current mode.
+        if let Some(action) = default_action(k, state.mode) {
+            self.recv_action(action, state);
+        }
+    }
+    // Carries out an action.
+    pub fn recv_action(&mut self, action: Action, state: &mut State) {
+        // while the undo tree browser is open, the only things that can be done are closing it and quitting.
+        let grep_open = state.grep.as_ref().is_some_and(|x| x.open);
+        if (self.browser.is_none() && !grep_open) || matches!(action, Action::UndoTree | Action::Quit) {
+            self.run(action, state);
+        }
+        // once the selection is gone, so is the visual mode.
+        if state.mode.is_visual() && self.cursor.highlight.is_none() {
+            state.mode = Mode::Command;
+        }
+    }
+    // Carries out an action.
+    pub fn run(&mut self, action: Action, state: &mut State) {
+        // the history can't be gone through while a block insert is holding its edits back, so it's finished first.
+        if matches!(action, Action::Undo | Action::Redo | Action::PreviousBranch | Action::NextBranch | Action::UndoTree) {
+            self.finish_block_insert();
+        }
+        // an operator that's waiting acts on whatever the next motion moves over. Anything else drops it.
+        if let Some(operator) = self.operator.take() {
+            if action.is_motion() {
+                self.apply_operator(operator, action, state);
+                return;
+            } else if action == Action::Operator(operator) {
+                // giving the same operator twice acts on the current line.
+                let y = self.cursor.y_pos;
+                self.operate_lines(operator, y, y);
+                return;
+            }
+        }
+        if !self.cursors.is_empty() {
+            match action {
+                // each cursor's selection goes on a line of its own, so pasting with as many cursors puts each one back.
+                Action::Copy | Action::Cut => {
+                    let mut selections = Vec::new();
+                    self.each_cursor(|tb, _| selections.push(tb.highlight().unwrap_or_default()));
+                    selections.reverse();
+                    let _ = cli_clipboard::set_contents(selections.join("\n"));
+                    if action == Action::Cut {
+                        self.each_cursor(|tb, _| tb.remove_highlight());
                     }
-                    self.action();
+                    return;
                 }
-            }
-            // Deletes the next character if there is one. Merges two lines if needed.
-            KeyCode::Delete => {
-                self.set_valid_pos();
-                if let Some(_) = self.cursor.highlight {
-                    self.remove_highlight();
-                } else {
-                    for _ in 0..self.word_length_toggle_c(false, modifiers.contains(KeyModifiers::CONTROL)) {
-                        // if we're at the end of a line...
-                        if self.cursor.x_pos == self.contents[self.cursor.y_pos].len() {
-                            // if we're not on the last line...
-                            if self.cursor.y_pos != self.contents.len() - 1 {
-                                // add the current line onto the next line
-                                let mut next_line = self.contents.remove(self.cursor.y_pos + 1);
-                                self.contents[self.cursor.y_pos].append(&mut next_line);
-                            }
-                        } else {
-                            self.contents[self.cursor.y_pos].remove(self.cursor.x_pos);
-                        }
+                // pasting as many lines as there are cursors gives each cursor a line.
+                Action::Paste => {
+                    let text = cli_clipboard::get_contents().unwrap_or_default();
+                    let lines = text.split('\n').collect::<Vec<_>>();
+                    if lines.len() == self.cursors.len() + 1 {
+                        self.each_cursor(|tb, i| tb.insert_text(lines[i]));
+                        return;
                     }
-                    self.action();
                 }
+                // the history only knows where the main cursor was.
+                Action::Undo | Action::Redo | Action::PreviousBranch | Action::NextBranch | Action::Cancel => {
+                    self.cursors.clear();
+                }
+                _ => {}
             }
-            // splits the current line into two lines.
-            KeyCode::Enter => {
-                self.set_valid_pos();
-                self.remove_highlight();
-                let new_line = self.contents[self.cursor.y_pos].split_off(self.cursor.x_pos);
-                self.contents.insert(self.cursor.y_pos + 1, new_line);
-                self.cursor.x_pos = 0;
-                self.cursor.y_pos += 1;
-                self.action();
+            if action.applies_to_each_cursor() {
+                self.each_cursor(|tb, _| tb.run(action, state));
+                return;
             }
-            // moves the cursor leftwards, or to the end of the previous line.
-            KeyCode::Left => {
-                self.set_valid_pos();
-                self.set_valid_pos_h();
-                if modifiers.contains(KeyModifiers::SHIFT) {
-                    let amt = if let Some(_) = self.cursor.highlight {
-                        self.word_length_toggle_h(true, modifiers.contains(KeyModifiers::CONTROL))
-                    } else {
-                        self.word_length_toggle_c(true, modifiers.contains(KeyModifiers::CONTROL))
-                    };
-                    for _ in 0..amt {
-                        if let Some((x, y)) = self.cursor.highlight {
-                            if x > 0 {
-                                self.cursor.highlight = Some((x - 1, y));
-                            } else if y > 0 {
-                                self.cursor.highlight = Some((self.contents[y - 1].len(), y - 1));
-                            }
-                        } else {
-                            if self.cursor.x_pos > 0 {
-                                self.cursor.highlight = Some((self.cursor.x_pos - 1, self.cursor.y_pos));
-                            } else if self.cursor.y_pos > 0 {
-                                self.cursor.highlight = Some((self.contents[self.cursor.y_pos - 1].len(), self.cursor.y_pos - 1));
-                            }
-                        }
-                    }
-                } else {
-                    for _ in 0..self.word_length_toggle_c(true, modifiers.contains(KeyModifiers::CONTROL)) {
-                        self.cursor.highlight = None;
-                        if self.cursor.x_pos > 0 {
-                            self.cursor.x_pos -= 1;
-                        } else if self.cursor.y_pos > 0 {
-                            self.cursor.y_pos -= 1;
-                            self.cursor.x_pos = self.contents[self.cursor.y_pos].len();
-                        }
-                    }
+        }
+        match action {
+            // in the visual modes, motions move the cursor while the other end of the selection stays put.
+            motion if motion.is_motion() && state.mode.is_visual() => {
+                let anchor = self.cursor.highlight;
+                self.run_motion(motion);
+                self.cursor.highlight = anchor;
+            }
+            motion if motion.is_motion() => self.run_motion(motion),
+            Action::SelectLeft => self.select_left(false),
+            Action::SelectRight => self.select_right(false),
+            Action::SelectUp => self.select_up(),
+            Action::SelectDown => self.select_down(),
+            Action::SelectWordLeft => self.select_left(true),
+            Action::SelectWordRight => self.select_right(true),
+            Action::SelectBlockLeft | Action::SelectBlockRight | Action::SelectBlockUp | Action::SelectBlockDown => {
+                if state.mode != Mode::VisualBlock {
+                    self.switch_mode(Mode::VisualBlock, state);
                 }
+                let motion = match action {
+                    Action::SelectBlockLeft => Action::MoveLeft,
+                    Action::SelectBlockRight => Action::MoveRight,
+                    Action::SelectBlockUp => Action::MoveUp,
+                    _ => Action::MoveDown,
+                };
+                self.run(motion, state);
+            }
+            Action::DeleteBackward => self.delete_backward(false),
+            Action::DeleteForward => self.delete_forward(false),
+            Action::DeleteWordBackward => self.delete_backward(true),
+            Action::DeleteWordForward => self.delete_forward(true),
+            Action::DeleteLine => self.delete_line(),
+            Action::InsertNewline => self.insert_text("\n"),
+            Action::InsertTab => {
+                let indent = self.indent_text(self.cursor.x_pos, self.cursor.y_pos);
+                self.insert_text(&indent);
+            }
+            Action::InsertChar(c) => self.insert_text(c.encode_utf8(&mut [0; 4])),
+            // a selected rectangle is copied and cut column by column.
+            Action::Copy if state.mode == Mode::VisualBlock => self.run(Action::Operator(Operator::Yank), state),
+            Action::Cut if state.mode == Mode::VisualBlock => self.run(Action::Operator(Operator::Delete), state),
+            Action::Copy => self.copy(),
+            Action::Cut => self.cut(),
+            Action::Paste => self.paste(),
+            Action::PasteAfter => self.paste_after(),
+            // going through the history can change the text anywhere, so it's all highlighted and searched again.
+            Action::Undo => {
+                self.history.undo(&mut self.contents, &mut self.cursor);
+                self.changed(0);
+            }
+            Action::Redo => {
+                self.history.redo(&mut self.contents, &mut self.cursor);
+                self.changed(0);
+            }
+            Action::PreviousBranch => {
+                self.history.branch(false, &mut self.contents, &mut self.cursor);
+                self.changed(0);
+            }
+            Action::NextBranch => {
+                self.history.branch(true, &mut self.contents, &mut self.cursor);
+                self.changed(0);
+            }
+            // opens the undo tree browser, or closes it if it's open.
+            Action::UndoTree => {
+                self.browser = match self.browser {
+                    Some(_) => None,
+                    None => Some(HistoryBrowser::new()),
+                };
+            }
+            Action::CommandLine => state.command_line.open(':'),
+            Action::SearchForward | Action::SearchBackward => {
+                self.search_origin = Some(self.cursor);
+                state.command_line.open(if action == Action::SearchForward { '/' } else { '?' });
+            }
+            Action::SearchNext => self.search_next(false, state),
+            Action::SearchPrevious => self.search_next(true, state),
+            Action::Save => {
+                self.write(None, state);
+            }
+            Action::Quit => {
+                self.save_history(&state.config);
+                state.will_quit = true;
             }
-            // moves the cursor rightwards, or to the beginning of the next line.
-            KeyCode::Right => {
+            Action::SwitchMode(mode) => self.switch_mode(mode, state),
+            Action::Append => {
+                self.cursor.highlight = None;
                 self.set_valid_pos();
-                self.set_valid_pos_h();
-                if modifiers.contains(KeyModifiers::SHIFT) {
-                    let amt = if let Some(_) = self.cursor.highlight {
-                        self.word_length_toggle_h(false, modifiers.contains(KeyModifiers::CONTROL))
-                    } else {
-                        self.word_length_toggle_c(false, modifiers.contains(KeyModifiers::CONTROL))
-                    };
-                    for _ in 0..amt {
-                        if let Some((x, y)) = self.cursor.highlight {
-                            if x < self.contents[y].len() {
-                                self.cursor.highlight = Some((x + 1, y));
-                            } else if y < self.contents.len() - 1 {
-                                self.cursor.highlight = Some((0, y + 1));
-                            }
-                        } else {
-                            if self.cursor.x_pos < self.contents[self.cursor.y_pos].len() {
-                                self.cursor.highlight = Some((self.cursor.x_pos + 1, self.cursor.y_pos));
-                            } else if self.cursor.y_pos < self.contents.len() - 1 {
-                                self.cursor.highlight = Some((0, self.cursor.y_pos + 1));
-                            }
-                        }
-                    }
-                } else {
-                    for _ in 0..self.word_length_toggle_c(false, modifiers.contains(KeyModifiers::CONTROL)) {
-                        self.cursor.highlight = None;
-                        if self.cursor.x_pos < self.contents[self.cursor.y_pos].len() {
-                            self.cursor.x_pos += 1;
-                        } else if self.cursor.y_pos < self.contents.len() - 1 {
-                            self.cursor.y_pos += 1;
-                            self.cursor.x_pos = 0;
-                        }
-                    }
+                if self.cursor.x_pos < self.line_len(self.cursor.y_pos) {
+                    self.cursor.x_pos += 1;
                 }
+                state.mode = Mode::Insert;
             }
-            // moves the cursor upwards, or to the end of the last line (if on the last line)
-            KeyCode::Up => {
-                if modifiers.contains(KeyModifiers::SHIFT) {
-                    if let Some((x, y)) = self.cursor.highlight {
-                        if y > 0 {
-                            self.cursor.highlight = Some((x, y - 1));
-                        } else {
-                            self.cursor.highlight = Some((0, 0));
-                        }
-                    } else {
-                        if self.cursor.y_pos > 0 {
-                            self.cursor.highlight = Some((self.cursor.x_pos, self.cursor.y_pos - 1));
-                        } else {
-                            self.cursor.highlight = Some((0, 0));
-                        }
+            Action::BlockInsert | Action::BlockAppend => {
+                if let Some((first, last, left, right)) = self.block() {
+                    let append = action == Action::BlockAppend;
+                    let column = if append { right } else { left };
+                    let x = self.column_index(first, column);
+                    self.begin_group();
+                    // the first line is padded out to the column, so there's somewhere to type.
+                    let len = self.line_len(first);
+                    if len < x {
+                        let end = self.contents.line_to_char(first) + len;
+                        self.replace(end, end, &" ".repeat(x - len));
                     }
-                } else {
                     self.cursor.highlight = None;
-                    if self.cursor.y_pos > 0 {
-                        self.cursor.y_pos -= 1;
-                    } else {
-                        self.cursor.x_pos = 0;
-                    }
+                    self.cursor.x_pos = x;
+                    self.cursor.y_pos = first;
+                    self.block_insert = Some((first, last, column, append));
+                    state.mode = Mode::Insert;
                 }
             }
-            // moves the cursor downwards, or to the beginning of the first line (if on the first line).
-            KeyCode::Down => {
-                if modifiers.contains(KeyModifiers::SHIFT) {
-                    if let Some((x, y)) = self.cursor.highlight {
-                        if y < self.contents.len() - 1 {
-                            self.cursor.highlight = Some((x, y + 1));
-                        } else {
-                            self.cursor.highlight = Some((self.contents[y].len(), y));
-                        }
+            Action::Operator(operator) => {
+                // with text selected, there's no need to wait for a motion.
+                if let (Mode::VisualBlock, Some(block)) = (state.mode, self.block()) {
+                    self.operate_block(operator, block);
+                } else if let (Mode::VisualLine, Some((_, y))) = (state.mode, self.cursor.highlight) {
+                    let other = self.cursor.y_pos;
+                    self.operate_lines(operator, y.min(other), y.max(other));
+                } else if let Some((start, end)) = self.highlight_range() {
+                    // the visual mode's selection takes in the character under the cursor.
+                    let end = if state.mode == Mode::Visual {
+                        (end + 1).min(self.contents.len_chars())
                     } else {
-                        if self.cursor.y_pos < self.contents.len() - 1 {
-                            self.cursor.highlight = Some((self.cursor.x_pos, self.cursor.y_pos + 1));
-                        } else {
-                            self.cursor.highlight = Some((self.contents[self.cursor.y_pos].len(), self.cursor.y_pos));
-                        }
-                    }
+                        end
+                    };
+                    self.operate(operator, start, end);
                 } else {
-                    self.cursor.highlight = None;
-                    if self.cursor.y_pos < self.contents.len() - 1 {
-                        self.cursor.y_pos += 1;
-                    } else {
-                        self.cursor.x_pos = self.contents.get(self.cursor.y_pos).unwrap().len();
-                    }
+                    self.operator = Some(operator);
                 }
             }
-            // Inserts a tab character.
-            KeyCode::Tab => {
-                self.set_valid_pos();
-                self.remove_highlight();
-                self.contents[self.cursor.y_pos].insert(self.cursor.x_pos, '\t');
-                self.cursor.x_pos += 1;
-                self.action();
+            Action::Cancel => self.cursor.highlight = None,
+            Action::AddCursorAbove => self.add_cursor(false),
+            Action::AddCursorBelow => self.add_cursor(true),
+            Action::AddCursorAtNextMatch => self.add_cursor_at_next_match(),
+            Action::AddCursorsToLines => self.add_cursors_to_lines(state.mode),
+            // the panes are looked after outside of the textbox.
+            Action::SplitHorizontal => state.request = Some(Request::Command(Command::Split(None))),
+            Action::SplitVertical => state.request = Some(Request::Command(Command::VerticalSplit(None))),
+            Action::ClosePane => state.request = Some(Request::Command(Command::Close)),
+            Action::FocusLeft => state.request = Some(Request::Focus(Direction::Left)),
+            Action::FocusRight => state.request = Some(Request::Focus(Direction::Right)),
+            Action::FocusUp => state.request = Some(Request::Focus(Direction::Up)),
+            Action::FocusDown => state.request = Some(Request::Focus(Direction::Down)),
+            Action::GrowPane | Action::ShrinkPane | Action::WidenPane | Action::NarrowPane => {
+                let vertical = matches!(action, Action::WidenPane | Action::NarrowPane);
+                let amount = if matches!(action, Action::GrowPane | Action::WidenPane) { 1 } else { -1 };
+                state.request = Some(Request::Command(Command::Resize {
+                    vertical,
+                    amount,
+                    relative: true,
+                }));
             }
-            // Either executes a control sequence or adds a key.
-            KeyCode::Char(c) => {
-                if modifiers.contains(KeyModifiers::CONTROL) {
-                    self.ctrl_keys(c, modifiers, state);
-                } else {
-                    self.set_valid_pos();
-                    self.remove_highlight();
-                    self.contents[self.cursor.y_pos].insert(self.cursor.x_pos, c);
-                    self.cursor.x_pos += 1;
-                    self.action();
-                }
+            // and so are the tabs.
+            Action::NewTab => state.request = Some(Request::Command(Command::TabNew(None))),
+            Action::CloseTab => state.request = Some(Request::Command(Command::TabClose)),
+            Action::NextTab => state.request = Some(Request::Command(Command::TabNext)),
+            Action::PreviousTab => state.request = Some(Request::Command(Command::TabPrevious)),
+            Action::MoveTabLeft | Action::MoveTabRight => {
+                let amount = if action == Action::MoveTabRight { 1 } else { -1 };
+                state.request = Some(Request::Command(Command::TabMove { amount, relative: true }));
             }
-            // Escape currently does nothing, but might do something in the future. 
-            KeyCode::Esc => {}
-            // No other key presses currently do anything.
+            // every motion was handled above.
             _ => {}
         }
-        self.display(d, headers);
     }
-    // Executes a control sequence, and returns true if the program should end.
-    pub fn ctrl_keys(&mut self, c: char, m: KeyModifiers, state: &mut State) {
-        match c {
-            // ctrl+c will copy text in the future
-            'c' | 'C' => {
-                if let Some(val) = self.highlight() {
-                    let _ = cli_clipboard::set_contents(val);
-                }
+    // Does something at every cursor in turn, as one undo. Each cursor becomes the main cursor while it's handled, and
+    // the function is told which cursor it is, counting from the start of the text.
+    // Cursors are handled from the end of the text back, so that editing at one doesn't move the ones still to come.
+    pub fn each_cursor(&mut self, mut f: impl FnMut(&mut TextBox, usize)) {
+        self.begin_group();
+        let mut cursors = std::mem::take(&mut self.cursors).into_iter().map(|x| (x, false)).collect::<Vec<_>>();
+        cursors.push((self.cursor, true));
+        cursors.sort_by_key(|(x, _)| std::cmp::Reverse(self.clamped_index(x.x_pos, x.y_pos)));
+        let count = cursors.len();
+        // the cursors handled so far, as indexes into the contents. They all come after the one being handled.
+        let mut done: Vec<(usize, Option<usize>, bool)> = Vec::new();
+        for (i, (cursor, main)) in cursors.into_iter().enumerate() {
+            self.cursor = cursor;
+            let before = self.contents.len_chars();
+            f(self, count - 1 - i);
+            let after = self.contents.len_chars();
+            let shift = |x: usize| (x + after).saturating_sub(before);
+            for (position, highlight, _) in &mut done {
+                *position = shift(*position);
+                *highlight = highlight.map(shift);
             }
-            // If ctrl+q is pressed, the program should end.
-            'q' | 'Q' => {
-                state.will_quit = true;
+            let highlight = self.cursor.highlight.map(|(x, y)| self.clamped_index(x, y));
+            done.push((self.clamped_index(self.cursor.x_pos, self.cursor.y_pos), highlight, main));
+        }
+        let len = self.contents.len_chars();
+        for (position, highlight, main) in done {
+            let (x_pos, y_pos) = self.position(position.min(len));
+            let cursor = Cursor {
+                x_pos,
+                y_pos,
+                highlight: highlight.map(|x| self.position(x.min(len))),
+            };
+            if main {
+                self.cursor = cursor;
+            } else {
+                self.cursors.push(cursor);
             }
-            // ctrl+v will paste text in the future
-            'v' | 'V' => {
-                if let Ok(str) = cli_clipboard::get_contents() {
-                    self.remove_highlight();
-                    for c in str.chars() {
-                        self.contents[self.cursor.y_pos].insert(self.cursor.x_pos, c);
-                        self.cursor.x_pos += 1;
+        }
+        self.merge_cursors();
+        self.end_group();
+    }
+    // Merges cursors that are in the same place, or whose selections overlap.
+    pub fn merge_cursors(&mut self) {
+        let mut cursors = std::mem::take(&mut self.cursors).into_iter().map(|x| (x, false)).collect::<Vec<_>>();
+        cursors.push((self.cursor, true));
+        let mut ranges = cursors
+            .into_iter()
+            .map(|(cursor, main)| {
+                let first = self.clamped_index(cursor.x_pos, cursor.y_pos);
+                let second = cursor.highlight.map(|(x, y)| self.clamped_index(x, y)).unwrap_or(first);
+                (first.min(second), first.max(second), cursor, main)
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|(start, ..)| *start);
+        let mut merged: Vec<(usize, usize, Cursor, bool)> = Vec::new();
+        for (start, end, cursor, main) in ranges {
+            match merged.last_mut() {
+                Some(last) if start == last.0 || start < last.1 => {
+                    // the merged cursor selects everything either of them did.
+                    if end > last.1 {
+                        last.1 = end;
+                        let (x_pos, y_pos) = self.position(last.0);
+                        last.2 = Cursor {
+                            x_pos,
+                            y_pos,
+                            highlight: Some(self.position(end)),
+                        };
                     }
-                    self.action();
+                    last.3 |= main;
                 }
+                _ => merged.push((start, end, cursor, main)),
             }
-            'z' | 'Z' => {
-                if m.contains(KeyModifiers::SHIFT) {
-                    debug::debug("CONTROL SHIFT ZEEEEE\n");
-                    self.history.redo(&mut self.contents, &mut self.cursor);
-                } else {
-                    debug::debug("CONTROL ZEEEEE\n");
-                    self.history.undo(&mut self.contents, &mut self.cursor);
-                }
+        }
+        for (_, _, cursor, main) in merged {
+            if main {
+                self.cursor = cursor;
+            } else {
+                self.cursors.push(cursor);
             }
-            'y' | 'Y' => {
-                if m.contains(KeyModifiers::SHIFT) {
-                    debug::debug("CONTROL SHIFT WHYYY\n");
-                    self.history.redo(&mut self.contents, &mut self.cursor);
-                } else {
-                    debug::debug("CONTROL WHY\n");
-                    self.history.redo(&mut self.contents, &mut self.cursor);
-                }
+        }
+    }
+    // Adds a cursor on the line above the topmost cursor (or below the bottommost one), in the main cursor's column.
+    // The new cursor becomes the main one.
+    pub fn add_cursor(&mut self, below: bool) {
+        let all = self.cursors.iter().chain(std::iter::once(&self.cursor)).map(|x| x.y_pos);
+        let y = if below {
+            Some(all.max().unwrap_or(0) + 1).filter(|x| *x < self.contents.len_lines())
+        } else {
+            all.min().unwrap_or(0).checked_sub(1)
+        };
+        if let Some(y_pos) = y {
+            self.cursors.push(self.cursor);
+            self.cursor = Cursor {
+                x_pos: self.cursor.x_pos,
+                y_pos,
+                highlight: None,
+            };
+        }
+    }
+    // Selects the word under the cursor if nothing is selected. Otherwise, adds a cursor that selects the next place
+    // the selected text shows up, going back to the start of the text after reaching the end.
+    pub fn add_cursor_at_next_match(&mut self) {
+        let (start, end) = match self.highlight_range() {
+            Some((start, end)) if start != end => (start, end),
+            _ => {
+                self.select_word();
+                return;
             }
-            // ctrl+x will cut text in the future
-            'x' | 'X' => {
-                self.ctrl_keys('c', m, state);
-                self.remove_highlight();
-            }
-            // ctrl+s saves the file
-            's' | 'S' => {
-                if let Err(_) = std::fs::write(
-                    &self.path,
-                    self.contents
-                        .iter() // iterates through the lines
-                        .map(|x| x.iter().collect::<String>()) // collects each line into a string
-                        .collect::<Vec<String>>() // collects each line into a vector of lines
-                        .join("\n"),
-                ) {
-                    // joins the lines with a newline character
-                    // attempts to write the resulting data. If the write fails, prints a message.
-                    println!("Failed to save!");
-                }
+        };
+        let needle = self.contents.slice(start, end);
+        let len = self.contents.len_chars();
+        // looks after the selection first, then before it.
+        let after = self.contents.slice(end, len);
+        let found = match after.find(&needle) {
+            Some(val) => Some(end + after[..val].chars().count()),
+            None => {
+                let before = self.contents.slice(0, end);
+                before.find(&needle).map(|val| before[..val].chars().count())
             }
+        };
+        let found = match found {
+            Some(val) => val,
+            None => return,
+        };
+        // every match already has a cursor once the search comes back around to one.
+        let taken = self.cursors.iter().chain(std::iter::once(&self.cursor)).any(|x| {
+            let first = self.clamped_index(x.x_pos, x.y_pos);
+            let second = x.highlight.map(|(x, y)| self.clamped_index(x, y)).unwrap_or(first);
+            first.min(second) == found
+        });
+        if !taken {
+            self.cursors.push(self.cursor);
+            let (x_pos, y_pos) = self.position(found);
+            self.cursor = Cursor {
+                x_pos,
+                y_pos,
+                highlight: Some(self.position(found + needle.chars().count())),
+            };
+        }
+    }
+    // Selects the word under the cursor.
+    pub fn select_word(&mut self) {
+        self.set_valid_pos();
+        let line = self.contents.line_chars(self.cursor.y_pos);
+        let x = self.cursor.x_pos;
+        if x >= line.len() || line[x].is_whitespace() {
+            return;
+        }
+        let start = x - line[..x].iter().rev().take_while(|c| kind(**c) == kind(line[x])).count();
+        let end = x + line[x..].iter().take_while(|c| kind(**c) == kind(line[x])).count();
+        self.cursor.x_pos = start;
+        self.cursor.highlight = Some((end, self.cursor.y_pos));
+    }
+    // Replaces the selection with a cursor on each of its lines: at the left edge of a rectangle, or the end of the line.
+    pub fn add_cursors_to_lines(&mut self, mode: Mode) {
+        let (first, last, column) = match (mode, self.block(), self.cursor.highlight) {
+            (Mode::VisualBlock, Some((first, last, left, _)), _) => (first, last, Some(left)),
+            (_, _, Some((_, y))) => (y.min(self.cursor.y_pos), y.max(self.cursor.y_pos), None),
+            _ => return,
+        };
+        self.cursors = (first + 1..=last)
+            .map(|y| Cursor {
+                x_pos: column.map_or_else(|| self.line_len(y), |x| self.column_index(y, x)),
+                y_pos: y,
+                highlight: None,
+            })
+            .collect();
+        self.cursor = Cursor {
+            x_pos: column.map_or_else(|| self.line_len(first), |x| self.column_index(first, x)),
+            y_pos: first,
+            highlight: None,
+        };
+    }
+    // Carries out an action that only moves the cursor.
+    pub fn run_motion(&mut self, motion: Action) {
+        match motion {
+            Action::MoveLeft => self.move_left(false),
+            Action::MoveRight => self.move_right(false),
+            Action::MoveUp => self.move_up(),
+            Action::MoveDown => self.move_down(),
+            Action::MoveWordLeft => self.move_left(true),
+            Action::MoveWordRight => self.move_right(true),
+            Action::MoveNextWord => self.move_next_word(),
+            Action::MoveLineStart => self.move_line_start(),
+            Action::MoveLineEnd => self.move_line_end(),
             _ => {}
         }
     }
-    // Calculates the position where the display starts printing.
-    pub fn calculate_start(&mut self, height: usize) -> usize {
-        let current_line = self.cursor.y_pos;
-        let half_pos = height / 2;
-        let total_length = self.contents.len();
-        if current_line < half_pos || total_length <= height {
-            0
-        } else if current_line >= total_length - half_pos {
-            total_length - height
-        } else {
-            current_line - half_pos
-        }
-    }
-    pub fn display(&mut self, d: &mut DrawProcess, headers: &mut DrawProcess) {
-        let start = self.calculate_start(d.height());
-
-        // the length of the left side of the twolinkedlist. This is used for the cursor.
-        let mut min_x;
-        let min_y;
-        let mut max_x;
-        let max_y;
-        let highlight; 
-        if let Some((x1, y1)) = self.cursor.highlight {
-            highlight = ansi::BACKGROUND_CYAN;
-            let (x2, y2) = (self.cursor.x_pos, self.cursor.y_pos);
-            match y1.cmp(&y2) {
-                std::cmp::Ordering::Less => {
-                    min_x = x1;
-                    min_y = y1;
-                    max_x = x2;
-                    max_y = y2;
-                },
-                std::cmp::Ordering::Equal => {
-                    min_x = x1.min(x2);
-                    min_y = y1;
-                    max_x = x1.max(x2);
-                    max_y = y1;
-                },
-                std::cmp::Ordering::Greater => {
-                    min_x = x2;
-                    min_y = y2;
-                    max_x = x1;
-                    max_y = y1;
-                },
+    // Changes the mode. The visual modes start a selection at the cursor, and leaving them drops it.
+    pub fn switch_mode(&mut self, mode: Mode, state: &mut State) {
+        if state.mode == Mode::Insert && mode != Mode::Insert {
+            self.finish_block_insert();
+        }
+        if mode.is_visual() && !state.mode.is_visual() {
+            self.set_valid_pos();
+            self.cursor.highlight = Some((self.cursor.x_pos, self.cursor.y_pos));
+        } else if !mode.is_visual() && state.mode.is_visual() {
+            self.cursor.highlight = None;
+        }
+        state.mode = mode;
+    }
+    // Copies whatever was typed on the first line of a block insert onto the rest of its lines, as one undo.
+    pub fn finish_block_insert(&mut self) {
+        if let Some((first, last, column, pad)) = self.block_insert.take() {
+            let line = self.contents.line_chars(first);
+            let start = self.column_index(first, column);
+            if self.cursor.y_pos == first && self.cursor.x_pos > start {
+                let text = line[start..self.cursor.x_pos.min(line.len())].iter().collect::<String>();
+                for y in first + 1..=last {
+                    let len = self.line_len(y);
+                    let x = self.column_index(y, column);
+                    // lines that don't reach the column are left alone, unless they're being appended to.
+                    if len < x && !pad {
+                        continue;
+                    }
+                    let index = self.contents.line_to_char(y) + len.min(x);
+                    self.replace(index, index, &format!("{}{}", " ".repeat(x.saturating_sub(len)), text));
+                }
             }
+            self.action();
+            self.end_group();
+        }
+    }
+    // The selected rectangle: its first and last lines, and the columns it spans on the screen (the right one isn't
+    // included). Tabs and wide characters make the same columns different places on different lines.
+    pub fn block(&self) -> Option<(usize, usize, usize, usize)> {
+        let (x, y) = self.cursor.highlight?;
+        let (x2, y2) = (self.cursor.x_pos, self.cursor.y_pos);
+        let ((left, right), (left2, right2)) = (self.cells(x, y), self.cells(x2, y2));
+        Some((y.min(y2), y.max(y2), left.min(left2), right.max(right2)))
+    }
+    // The columns the grapheme at a position takes up: its first, and the one after its last. Past the end of the line,
+    // each position takes up a column.
+    fn cells(&self, x: usize, y: usize) -> (usize, usize) {
+        let line = self.contents.line_chars(y);
+        let columns = render::columns(&line, self.tab_width, None);
+        if x >= line.len() {
+            let column = columns[line.len()] + x - line.len();
+            return (column, column + 1);
+        }
+        let next = render::boundaries(&line).into_iter().find(|b| *b > x).expect("Safe unwrap");
+        (columns[x], columns[next])
+    }
+    // Where a column of a rectangle is on a line: the start of the grapheme that covers it, or past the end of the line
+    // by as many columns as the column is.
+    fn column_index(&self, y: usize, column: usize) -> usize {
+        render::index_at(&self.contents.line_chars(y), column, self.tab_width, None)
+    }
+    // A line's part of a rectangle, from where its left column is to just past the grapheme that covers its last one.
+    // A grapheme that's only partly inside the rectangle is taken in whole.
+    fn block_span(&self, y: usize, left: usize, right: usize) -> (usize, usize) {
+        let last = self.column_index(y, right.saturating_sub(1));
+        let len = self.line_len(y);
+        let end = if last < len {
+            let line = self.contents.line_chars(y);
+            render::boundaries(&line).into_iter().find(|b| *b > last).expect("Safe unwrap")
         } else {
-            highlight = ansi::SELECTED;
-            min_x = self.cursor.x_pos;
-            min_y = self.cursor.y_pos;
-            max_x = self.cursor.x_pos;
-            max_y = self.cursor.y_pos;
+            last + 1
+        };
+        (self.column_index(y, left), end)
+    }
+    // Has an operator act on each line's part of a rectangle. Yanked lines are joined with newlines.
+    pub fn operate_block(&mut self, operator: Operator, (first, last, left, right): (usize, usize, usize, usize)) {
+        if matches!(operator, Operator::Indent | Operator::Outdent) {
+            self.operate_lines(operator, first, last);
+            return;
         }
-        // at least one character is highlighted, so max_x must be at least min_x + 1.
-        if max_x == min_x && min_y == max_y {
-            max_x += 1;
+        let mut pieces = Vec::new();
+        for y in first..=last {
+            let line_start = self.contents.line_to_char(y);
+            let len = self.line_len(y);
+            let (start, end) = self.block_span(y, left, right);
+            let (start, end) = (line_start + start.min(len), line_start + end.min(len));
+            let text = self.contents.slice(start, end);
+            match operator {
+                Operator::Delete => self.remove(start, end),
+                Operator::Yank => {}
+                _ => self.replace(start, end, &change_case(operator, &text)),
+            }
+            pieces.push(text);
         }
-        // removes all extra spaces and inserts these formatting codes at the beginning/end:
-        let mut main_strategy = ansi_truncate::AnsiTruncate::new(ansi::RESET, ansi::RESET);
-        let mut current_line_strategy =
-            ansi_truncate::AnsiTruncate::new(ansi::SELECTED_LINE, ansi::RESET);
-        // enumerates through the contents.
-        for (mut i, line) in self.contents[start..(start + d.height()).min(self.contents.len())].iter().enumerate() {
-            i += start;
-            // if we're on the only highlighted line... 
-            if i == min_y && i == max_y {
-                // the current string to be printed
-                max_x = max_x.min(line.len());
-                min_x = min_x.min(line.len());
-                
-                let mut current = if line.len() == 0 {" ".to_string()} else {line[min_x..max_x].iter().collect::<String>()};
-                if current.is_empty() {
-                    current = " ".to_string();
+        if matches!(operator, Operator::Delete | Operator::Yank) {
+            let text = pieces.join("\n");
+            let _ = cli_clipboard::set_contents(text.clone());
+            self.block_yank = Some(text);
+        }
+        self.action();
+        self.cursor.highlight = None;
+        self.cursor.x_pos = self.column_index(first, left);
+        self.cursor.y_pos = first;
+        self.set_valid_pos();
+    }
+    // Pastes a rectangle at a column, one of its lines on each line from the cursor down.
+    // Lines that don't reach the column are padded out with spaces, and lines are added at the end if needed.
+    pub fn paste_block(&mut self, text: &str, column: usize) {
+        self.cursor.highlight = None;
+        let y = self.cursor.y_pos;
+        for (i, piece) in text.split('\n').enumerate() {
+            if y + i >= self.contents.len_lines() {
+                let end = self.contents.len_chars();
+                self.replace(end, end, "\n");
+            }
+            let len = self.line_len(y + i);
+            let index = self.contents.line_to_char(y + i) + len.min(column);
+            self.replace(index, index, &format!("{}{}", " ".repeat(column.saturating_sub(len)), piece));
+        }
+        self.action();
+        self.cursor.x_pos = column;
+    }
+    // Runs a motion, then has the operator act on the text between where the cursor was and where it ended up.
+    pub fn apply_operator(&mut self, operator: Operator, motion: Action, state: &mut State) {
+        self.set_valid_pos();
+        self.cursor.highlight = None;
+        let (x, y) = (self.cursor.x_pos, self.cursor.y_pos);
+        // operators move up and down by whole lines, even when they're wrapped.
+        let wrap_width = self.wrap_width.take();
+        self.run(motion, state);
+        self.wrap_width = wrap_width;
+        self.set_valid_pos();
+        // the next word is only looked for on the same line, so the newline at the end of it isn't taken.
+        if motion == Action::MoveNextWord && self.cursor.y_pos > y {
+            self.cursor.x_pos = self.line_len(y);
+            self.cursor.y_pos = y;
+        }
+        if matches!(motion, Action::MoveUp | Action::MoveDown) {
+            // moving up or down acts on whole lines.
+            let other = self.cursor.y_pos;
+            self.operate_lines(operator, y.min(other), y.max(other));
+        } else {
+            let first = self.index(x, y);
+            let second = self.index(self.cursor.x_pos, self.cursor.y_pos);
+            self.operate(operator, first.min(second), first.max(second));
+        }
+    }
+    // Has an operator act on the text between two indexes. The cursor is left at the start of the text.
+    pub fn operate(&mut self, operator: Operator, start: usize, end: usize) {
+        match operator {
+            Operator::Delete | Operator::Yank => {
+                let _ = cli_clipboard::set_contents(self.contents.slice(start, end));
+                if operator == Operator::Delete {
+                    self.remove(start, end);
+                    self.action();
                 }
-                // formats the string with the current character highlighted - the left portion, formatting for the current character,
-                // the current character, default formatting, and then the right portion.
-                let collected = if max_x == line.len() {
-                    format!(
-                        "{}{}{}\u{001b}[48;5;235m{}",
-                        line[..min_x].iter().collect::<String>(),
-                        highlight,
-                        current,
-                        ""
-                    )
-                } else {
-                    format!(
-                        "{}{}{}\u{001b}[48;5;235m{}",
-                        line[..min_x].iter().collect::<String>(),
-                        highlight,
-                        current,
-                        line[max_x..].iter().collect::<String>()
-                    )
-                };
-                // adds it to the section
-                let _ = d.add_to_section(collected, &mut current_line_strategy, Alignment::Plus);
-            } else if i == min_y {
-                let collected = if min_x >= line.len() {
-                    format!("{}{}{}", 
-                        line.iter().collect::<String>(), 
-                        highlight, 
-                        " "
-                    )
-                } else {
-                    format!("{}{}{}", 
-                        line[..min_x].iter().collect::<String>(), 
-                        highlight, 
-                        line[min_x..].iter().collect::<String>()
-                    )
-                };
-                // adds it to the selection
-                let _ = d.add_to_section(collected, &mut main_strategy, Alignment::Plus);
-            } else if i > min_y && i < max_y {
-                let collected = format!("{}{}", highlight, line.iter().collect::<String>());
-                let _ = d.add_to_section(collected, &mut main_strategy, Alignment::Plus);
-
-            } else if i == max_y {
-                let collected = if max_x >= line.len() {
-                    format!("{}{} ", 
-                        highlight, 
-                        line.iter().collect::<String>(),
-                    )
-                } else {
-                    format!("{}{}{}{}", 
-                        highlight,
-                        line[..max_x].iter().collect::<String>(),
-                        ansi::RESET,
-                        line[max_x..].iter().collect::<String>()
-                    )
-                };
-                let _ = d.add_to_section(collected, &mut main_strategy, Alignment::Plus);
-            } else {
-                // otherwise, just prints the whole line
-                let collected = line.iter().collect::<String>();
-                let _ = d.add_to_section(collected, &mut main_strategy, Alignment::Plus);
+            }
+            // indenting always works on whole lines.
+            Operator::Indent | Operator::Outdent => {
+                let (_, first) = self.position(start);
+                let (_, last) = self.position(end);
+                self.operate_lines(operator, first, last);
+                return;
+            }
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                let text = change_case(operator, &self.contents.slice(start, end));
+                self.replace(start, end, &text);
+                self.action();
             }
         }
-        // creates and prints the headers
-        self.print_headers(headers, start);
-
-        // prints the main drawprocess out to the terminal.
-        d.print(&mut CrosstermHandler, &mut stdout())
-            .expect("Error queueing display instructions");
-
-        // flushes the queued instructions out onto the screen.
-        CrosstermHandler::finish(&mut stdout()).expect("Error flushing display queue");
-
-        // clears displays of text
-        d.clear(DividerStrategy::Beginning);
-        headers.clear(DividerStrategy::Beginning);
+        let (x, y) = self.position(start);
+        self.cursor.x_pos = x;
+        self.cursor.y_pos = y;
+        self.cursor.highlight = None;
     }
-    pub fn print_headers(&mut self, headers: &mut DrawProcess, start: usize) {
-        // removes all extra spaces and inserts these formatting codes at the beginning/end:
-        let mut header_strategy = ansi_truncate::AnsiTruncate::new(ansi::GREEN, ansi::RESET);
-
-        // removes all extra spaces and inserts these formatting codes at the beginning/end:
-        let mut eof_strategy = ansi_truncate::AnsiTruncate::new(ansi::CYAN, ansi::RESET);
-
-        // the line count of the program.
-        let total_length = self.contents.len();
-
-        // for all lines in the file and on the display...
-        for i in start..start + (headers.height().min(total_length)) {
-            // print the number with the header_strategy format scheme
-            let _ =
-                headers.add_to_section(format!("{:-4} ", i), &mut header_strategy, Alignment::Plus);
+    // Has an operator act on whole lines.
+    // What's yanked always ends in a newline, so pasting it puts it on lines of its own.
+    pub fn operate_lines(&mut self, operator: Operator, first: usize, last: usize) {
+        match operator {
+            Operator::Indent | Operator::Outdent => {
+                self.indent_lines(first, last, operator == Operator::Indent);
+                return;
+            }
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                let start = self.contents.line_to_char(first);
+                let end = self.contents.line_to_char(last) + self.line_len(last);
+                self.operate(operator, start, end);
+                return;
+            }
+            Operator::Delete | Operator::Yank => {}
         }
-        // for all lines of exposed space in the display...
-        for _ in total_length..headers.height() {
-            // prints the character ~ with the eof_strategy format scheme
-            let _ = headers.add_to_section(format!("   ~ "), &mut eof_strategy, Alignment::Plus);
+        let start = self.contents.line_to_char(first);
+        let end = if last + 1 < self.contents.len_lines() {
+            self.contents.line_to_char(last + 1)
+        } else {
+            self.contents.len_chars()
+        };
+        let mut text = self.contents.slice(start, end);
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let _ = cli_clipboard::set_contents(text);
+        self.cursor.highlight = None;
+        self.cursor.x_pos = 0;
+        if operator == Operator::Delete {
+            self.delete_lines(first, last);
+        } else {
+            self.cursor.y_pos = first;
         }
+    }
+    // Picks up the tab settings from the config.
+    pub fn configure_tabs(&mut self, config: &Config) {
+        self.tab_width = config.tabstop.max(1);
+        self.expand_tab = config.expandtab;
+    }
+    // What the tab key puts in at a position: a tab, or enough spaces to reach the next tab stop.
+    pub fn indent_text(&self, x: usize, y: usize) -> String {
+        if self.expand_tab {
+            let column = render::column(&self.contents.line_chars(y), x, self.tab_width, None);
+            " ".repeat(render::grapheme_width("\t", column, self.tab_width))
+        } else {
+            "\t".to_string()
+        }
+    }
+    // Adds a level of indentation to the start of each line that isn't empty, or takes one level of indentation off of each line.
+    // The cursor is left at the start of the text on the first line.
+    pub fn indent_lines(&mut self, first: usize, last: usize, indent: bool) {
+        self.cursor.highlight = None;
+        for y in first..=last {
+            let start = self.contents.line_to_char(y);
+            let line = self.contents.line_chars(y);
+            if indent && !line.is_empty() {
+                let text = self.indent_text(0, y);
+                self.replace(start, start, &text);
+            } else if !indent {
+                // a level of indentation is a tab, or up to a tab's width of spaces.
+                let amount = if line.first() == Some(&'\t') {
+                    1
+                } else {
+                    line.iter().take(self.tab_width).take_while(|x| **x == ' ').count()
+                };
+                self.remove(start, start + amount);
+            }
+        }
+        self.action();
+        let line = self.contents.line_chars(first);
+        self.cursor.y_pos = first;
+        self.cursor.x_pos = line.iter().take_while(|x| x.is_whitespace()).count();
+    }
+    // deletes the previous grapheme (or word) if there is one. Merges two lines if needed.
+    pub fn delete_backward(&mut self, word: bool) {
+        self.set_valid_pos();
+        if self.cursor.highlight.is_some() {
+            self.remove_highlight();
+        } else {
+            // at the start of a line, this removes the newline, adding the line onto the previous one.
+            let (x, y) = self.moved(self.cursor.x_pos, self.cursor.y_pos, false, word);
+            self.remove(self.index(x, y), self.index(self.cursor.x_pos, self.cursor.y_pos));
+            self.cursor.x_pos = x;
+            self.cursor.y_pos = y;
+            self.action();
+        }
+    }
+    // Deletes the next grapheme (or word) if there is one. Merges two lines if needed.
+    pub fn delete_forward(&mut self, word: bool) {
+        self.set_valid_pos();
+        if self.cursor.highlight.is_some() {
+            self.remove_highlight();
+        } else {
+            // at the end of a line, this removes the newline, adding the next line onto the current one.
+            let (x, y) = self.moved(self.cursor.x_pos, self.cursor.y_pos, true, word);
+            self.remove(self.index(self.cursor.x_pos, self.cursor.y_pos), self.index(x, y));
+            self.action();
+        }
+    }
+    // Deletes the line the cursor is on, along with its newline.
+    pub fn delete_line(&mut self) {
+        self.cursor.highlight = None;
+        let y = self.cursor.y_pos;
+        self.delete_lines(y, y);
+    }
+    // Deletes a range of lines, along with their newlines, and moves the cursor to the line after them.
+    pub fn delete_lines(&mut self, first: usize, last: usize) {
+        let start = self.contents.line_to_char(first);
+        if last + 1 < self.contents.len_lines() {
+            self.remove(start, self.contents.line_to_char(last + 1));
+            self.cursor.y_pos = first;
+        } else if first > 0 {
+            // the last line has no newline of its own, so the one before it goes instead.
+            self.remove(start - 1, self.contents.len_chars());
+            self.cursor.y_pos = first - 1;
+        } else {
+            self.remove(start, self.contents.len_chars());
+            self.cursor.y_pos = 0;
+        }
+        self.set_valid_pos();
+        self.action();
+    }
+    // Replaces the highlighted text (if there is any) with some text.
+    pub fn insert_text(&mut self, text: &str) {
+        self.set_valid_pos();
+        self.remove_highlight();
+        self.insert(text);
+        self.action();
+    }
+    // moves the cursor leftwards (by a grapheme or a word), or to the end of the previous line.
+    pub fn move_left(&mut self, word: bool) {
+        self.set_valid_pos();
+        self.set_valid_pos_h();
+        self.cursor.highlight = None;
+        (self.cursor.x_pos, self.cursor.y_pos) = self.moved(self.cursor.x_pos, self.cursor.y_pos, false, word);
+    }
+    // moves the cursor to the start of the next word.
+    pub fn move_next_word(&mut self) {
+        self.set_valid_pos();
+        self.set_valid_pos_h();
+        self.cursor.highlight = None;
+        (self.cursor.x_pos, self.cursor.y_pos) = self.next_word_start(self.cursor.x_pos, self.cursor.y_pos);
+    }
+    // moves the cursor to the start of its line.
+    pub fn move_line_start(&mut self) {
+        self.cursor.highlight = None;
+        self.cursor.x_pos = 0;
+    }
+    // moves the cursor to the end of its line.
+    pub fn move_line_end(&mut self) {
+        self.cursor.highlight = None;
+        self.cursor.x_pos = self.line_len(self.cursor.y_pos);
+    }
+    // moves the highlighted end of the selection leftwards.
+    pub fn select_left(&mut self, word: bool) {
+        self.set_valid_pos();
+        self.set_valid_pos_h();
+        let (x, y) = self.cursor.highlight.unwrap_or((self.cursor.x_pos, self.cursor.y_pos));
+        self.cursor.highlight = Some(self.moved(x, y, false, word));
+    }
+    // moves the cursor rightwards (by a grapheme or a word), or to the beginning of the next line.
+    pub fn move_right(&mut self, word: bool) {
+        self.set_valid_pos();
+        self.set_valid_pos_h();
+        self.cursor.highlight = None;
+        (self.cursor.x_pos, self.cursor.y_pos) = self.moved(self.cursor.x_pos, self.cursor.y_pos, true, word);
+    }
+    // moves the highlighted end of the selection rightwards.
+    pub fn select_right(&mut self, word: bool) {
+        self.set_valid_pos();
+        self.set_valid_pos_h();
+        let (x, y) = self.cursor.highlight.unwrap_or((self.cursor.x_pos, self.cursor.y_pos));
+        self.cursor.highlight = Some(self.moved(x, y, true, word));
+    }
+    // moves the cursor upwards, or to the start of the first line (if on the first line)
+    pub fn move_up(&mut self) {
+        self.cursor.highlight = None;
+        if let Some((x, y)) = self.row_step(self.cursor.x_pos, self.cursor.y_pos, false) {
+            self.cursor.x_pos = x;
+            self.cursor.y_pos = y;
+        } else if self.cursor.y_pos > 0 {
+            self.cursor.y_pos -= 1;
+        } else {
+            self.cursor.x_pos = 0;
+        }
+    }
+    // moves the highlighted end of the selection upwards.
+    pub fn select_up(&mut self) {
+        let (x, y) = self.cursor.highlight.unwrap_or((self.cursor.x_pos, self.cursor.y_pos));
+        if let Some(val) = self.row_step(x, y, false) {
+            self.cursor.highlight = Some(val);
+        } else if y > 0 {
+            self.cursor.highlight = Some((x, y - 1));
+        } else {
+            self.cursor.highlight = Some((0, 0));
+        }
+    }
+    // moves the cursor downwards, or to the end of the last line (if on the last line).
+    pub fn move_down(&mut self) {
+        self.cursor.highlight = None;
+        if let Some((x, y)) = self.row_step(self.cursor.x_pos, self.cursor.y_pos, true) {
+            self.cursor.x_pos = x;
+            self.cursor.y_pos = y;
+        } else if self.cursor.y_pos < self.contents.len_lines() - 1 {
+            self.cursor.y_pos += 1;
+        } else {
+            self.cursor.x_pos = self.line_len(self.cursor.y_pos);
+        }
+    }
+    // Where a position ends up when it's moved a row up or down, while long lines are wrapped. It stays in the same
+    // column of the row, even past the end of a short line, like it does when lines aren't wrapped. Returns None if
+    // lines aren't wrapped, or there's no row to move to.
+    pub fn row_step(&self, x: usize, y: usize, down: bool) -> Option<(usize, usize)> {
+        let width = self.wrap_width?;
+        let (tab_width, wrap) = (self.tab_width, self.wrap_width);
+        let line = self.contents.line_chars(y);
+        // rows are made of columns on the screen, which tabs and wide characters take up more than one of.
+        let column = render::column(&line, x, tab_width, wrap);
+        let len = render::width(&line, tab_width, wrap);
+        let (row, offset) = (column.min(len) / width, column % width);
+        if down && row < len / width {
+            let next = render::index_at(&line, (column.min(len) + width).min(len), tab_width, wrap);
+            // a tab can reach over into the next row. The cursor goes on to the grapheme after it then.
+            if render::column(&line, next, tab_width, wrap) / width <= row {
+                Some((self.next_boundary(next, y), y))
+            } else {
+                Some((next, y))
+            }
+        } else if down && y + 1 < self.contents.len_lines() {
+            Some((render::index_at(&self.contents.line_chars(y + 1), offset, tab_width, wrap), y + 1))
+        } else if !down && row > 0 {
+            Some((render::index_at(&line, column.min(len) - width, tab_width, wrap), y))
+        } else if !down && y > 0 {
+            let above = self.contents.line_chars(y - 1);
+            let column = render::width(&above, tab_width, wrap) / width * width + offset;
+            Some((render::index_at(&above, column, tab_width, wrap), y - 1))
+        } else {
+            None
+        }
+    }
+    // How many rows a line takes up. There's always room after the end of a line for the cursor.
+    pub fn rows(&self, y: usize) -> usize {
+        match self.wrap_width {
+            Some(width) => render::width(&self.contents.line_chars(y), self.tab_width, self.wrap_width) / width + 1,
+            None => 1,
+        }
+    }
+    // The column of the screen the cursor is in, counting from the start of its line.
+    pub fn cursor_column(&self) -> usize {
+        let line = self.contents.line_chars(self.cursor.y_pos);
+        render::column(&line, self.cursor.x_pos.min(line.len()), self.tab_width, self.wrap_width)
+    }
+    // moves the highlighted end of the selection downwards.
+    pub fn select_down(&mut self) {
+        let (x, y) = self.cursor.highlight.unwrap_or((self.cursor.x_pos, self.cursor.y_pos));
+        if let Some(val) = self.row_step(x, y, true) {
+            self.cursor.highlight = Some(val);
+        } else if y < self.contents.len_lines() - 1 {
+            self.cursor.highlight = Some((x, y + 1));
+        } else {
+            self.cursor.highlight = Some((self.line_len(y), y));
+        }
+    }
+    // copies the highlighted text to the clipboard.
+    pub fn copy(&mut self) {
+        if let Some(val) = self.highlight() {
+            let _ = cli_clipboard::set_contents(val);
+        }
+    }
+    // copies the highlighted text to the clipboard, then removes it.
+    pub fn cut(&mut self) {
+        self.copy();
+        self.remove_highlight();
+    }
+    // replaces the highlighted text with the contents of the clipboard.
+    pub fn paste(&mut self) {
+        if let Ok(str) = cli_clipboard::get_contents() {
+            if self.block_yank.as_ref() == Some(&str) {
+                self.set_valid_pos();
+                self.paste_block(&str, self.cursor.x_pos);
+                return;
+            }
+            // replacing the highlighted text and pasting are undone together.
+            self.begin_group();
+            self.remove_highlight();
+            self.insert(&str);
+            self.action();
+            self.end_group();
+        }
+    }
+    // Pastes after the cursor instead of at it. Text that ends in a newline goes on lines of its own, below the current one.
+    pub fn paste_after(&mut self) {
+        if let Ok(str) = cli_clipboard::get_contents() {
+            self.set_valid_pos();
+            self.cursor.highlight = None;
+            let y = self.cursor.y_pos;
+            if self.block_yank.as_ref() == Some(&str) {
+                let column = (self.cursor.x_pos + 1).min(self.line_len(y));
+                self.paste_block(&str, column);
+                return;
+            } else if let Some(lines) = str.strip_suffix('\n') {
+                if y + 1 < self.contents.len_lines() {
+                    let index = self.contents.line_to_char(y + 1);
+                    self.replace(index, index, &str);
+                } else {
+                    // the last line has no newline to paste after, so one goes before the pasted lines instead.
+                    let index = self.contents.len_chars();
+                    self.replace(index, index, &format!("\n{}", lines));
+                }
+                self.cursor.x_pos = 0;
+                self.cursor.y_pos = y + 1;
+            } else {
+                if self.cursor.x_pos < self.line_len(y) {
+                    self.cursor.x_pos += 1;
+                }
+                self.insert(&str);
+            }
+            self.action();
+        }
+    }
+    // Keeps the search up to date as it's typed, with the cursor on the first match from where the search started.
+    // Entering the search keeps it for finding the next and previous matches. Closing the prompt puts the cursor back.
+    pub fn update_search(&mut self, entered: Option<String>, state: &mut State) {
+        let origin = match self.search_origin {
+            Some(val) => val,
+            None => return,
+        };
+        self.cursor = origin;
+        let backward = state.command_line.prompt == '?';
+        if let Some(pattern) = entered {
+            self.search_origin = None;
+            // entering nothing searches for the last pattern again.
+            if !pattern.is_empty() {
+                state.search = Some(Search { pattern, backward });
+            } else if let Some(search) = &mut state.search {
+                search.backward = backward;
+            }
+            self.search_next(false, state);
+        } else if !state.command_line.open {
+            self.search_origin = None;
+        } else {
+            let pattern = state.command_line.input.clone();
+            if let Some((found, ..)) = self.find_next(&pattern, backward, &state.config) {
+                let (x, y) = self.position(found);
+                self.cursor.x_pos = x;
+                self.cursor.y_pos = y;
+                self.cursor.highlight = None;
+            }
+        }
+    }
+    // Moves to the next match of the last search, and says which match it is.
+    pub fn search_next(&mut self, reverse: bool, state: &mut State) {
+        let search = match &state.search {
+            Some(val) => val.clone(),
+            None => {
+                state.message = Some(Message::Error("No previous search".to_string()));
+                return;
+            }
+        };
+        state.highlight_search = true;
+        let pattern = search.pattern.chars().collect::<Vec<_>>();
+        match self.find_next(&pattern, search.backward != reverse, &state.config) {
+            Some((found, number, count, wrapped)) => {
+                let (x, y) = self.position(found);
+                self.cursor.x_pos = x;
+                self.cursor.y_pos = y;
+                self.cursor.highlight = None;
+                let wrapped = if wrapped { " (wrapped around)" } else { "" };
+                state.message = Some(Message::Info(format!("{} of {} matches{}", number, count, wrapped)));
+            }
+            None => state.message = Some(Message::Error(format!("Pattern not found: {}", search.pattern))),
+        }
+    }
+    // Finds the first match after the cursor (or before it, going backward), going around the end of the text if there
+    // isn't one. Returns where it is, which match it is counting from one, how many there are, and whether it went around.
+    pub fn find_next(&mut self, pattern: &[char], backward: bool, config: &Config) -> Option<(usize, usize, usize, bool)> {
+        let index = self.clamped_index(self.cursor.x_pos, self.cursor.y_pos);
+        let contents = &self.contents;
+        let text = self.search_text.get_or_insert_with(|| contents.to_string().chars().collect());
+        let matches = find_all(text, pattern, config);
+        let (i, wrapped) = if backward {
+            match matches.iter().rposition(|(x, _)| *x < index) {
+                Some(val) => (val, false),
+                None => (matches.len().checked_sub(1)?, true),
+            }
+        } else {
+            match matches.iter().position(|(x, _)| *x > index) {
+                Some(val) => (val, false),
+                None => (0, true),
+            }
+        };
+        let (found, _) = *matches.get(i)?;
+        Some((found, i + 1, matches.len(), wrapped))
+    }
+    // Carries out a line typed into the command line.
+    pub fn execute(&mut self, line: &str, state: &mut State) {
+        if line.trim().is_empty() {
+            return;
+        }
+        match Command::parse(line) {
+            Ok(command) => self.run_command(command, state),
+            Err(e) => state.message = Some(Message::Error(e)),
+        }
+    }
+    pub fn run_command(&mut self, command: Command, state: &mut State) {
+        match command {
+            Command::Write(path) => {
+                self.write(path, state);
+            }
+            Command::WriteQuit(path) => {
+                if self.write(path, state) {
+                    state.request = Some(Request::Command(Command::Quit(false)));
+                }
+            }
+            Command::Goto(line) => self.goto(line),
+            Command::Set(args) => {
+                // the settings are changed on a copy, which is only kept if all of them (and the theme) work out.
+                let mut config = state.config.clone();
+                let mut shown = Vec::new();
+                for arg in args.split_whitespace() {
+                    match config.set(arg) {
+                        Ok(val) => shown.push(val),
+                        Err(e) => {
+                            state.message = Some(Message::Error(e));
+                            return;
+                        }
+                    }
+                }
+                // the theme file, or how many colors it's drawn with, may have changed.
+                match Theme::load(&config) {
+                    Ok(theme) => state.theme = theme,
+                    Err(e) => {
+                        state.message = Some(Message::Error(e));
+                        return;
+                    }
+                }
+                self.history.configure(&config);
+                state.config = config;
+                state.message = Some(Message::Info(shown.join("  ")));
+            }
+            Command::NoHighlight => state.highlight_search = false,
+            Command::Substitute(substitute) => self.substitute(substitute, state),
+            Command::Earlier(amount) => {
+                self.finish_block_insert();
+                match amount {
+                    Amount::Steps(steps) => {
+                        // a huge number of steps stops once there's nothing left to undo.
+                        for _ in 0..steps {
+                            if !self.history.undo(&mut self.contents, &mut self.cursor) {
+                                break;
+                            }
+                        }
+                    }
+                    Amount::Time(time) => self.history.earlier(time, &mut self.contents, &mut self.cursor),
+                }
+                self.changed(0);
+            }
+            Command::Later(amount) => {
+                self.finish_block_insert();
+                match amount {
+                    Amount::Steps(steps) => {
+                        // a huge number of steps stops once there's nothing left to redo.
+                        for _ in 0..steps {
+                            if !self.history.redo(&mut self.contents, &mut self.cursor) {
+                                break;
+                            }
+                        }
+                    }
+                    Amount::Time(time) => self.history.later(time, &mut self.contents, &mut self.cursor),
+                }
+                self.changed(0);
+            }
+            // everything else has to do with more than one file, so it's left to the buffers.
+            command => state.request = Some(Request::Command(command)),
+        }
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn text(&self) -> String {
+        self.contents.to_string()
+    }
+    pub fn view(&self) -> View {
+        View {
+            cursor: self.cursor,
+            cursors: self.cursors.clone(),
+            scroll: self.scroll,
+        }
+    }
+    // Puts the cursors back where a view had them, or as close as the text allows if it's changed since.
+    pub fn set_view(&mut self, view: &View) {
+        let last = self.contents.len_lines() - 1;
+        self.cursor = view.cursor;
+        self.cursor.y_pos = self.cursor.y_pos.min(last);
+        self.cursor.highlight = self.cursor.highlight.map(|(x, y)| (x, y.min(last)));
+        self.cursors = view.cursors.iter().filter(|x| x.y_pos <= last).copied().collect();
+        self.scroll = view.scroll;
+        self.set_valid_pos();
+        self.set_valid_pos_h();
+    }
+    // Whether there are changes that haven't been saved.
+    pub fn modified(&self) -> bool {
+        self.history.modified()
+    }
+    // The message on the status line, if it hasn't gone away yet.
+    pub fn status_message(&self, timeout: Duration) -> Option<&str> {
+        self.status_message
+            .as_ref()
+            .filter(|(_, given)| given.elapsed() < timeout)
+            .map(|(message, _)| message.as_str())
+    }
+    // How long until the message on the status line goes away, if there is one.
+    pub fn message_time_left(&self, timeout: Duration) -> Option<Duration> {
+        self.status_message(timeout)?;
+        self.status_message.as_ref().map(|(_, given)| timeout.saturating_sub(given.elapsed()))
+    }
+    // The column the cursor is in, counting each character as the user sees it (a grapheme) as one.
+    pub fn grapheme_column(&self) -> usize {
+        let line = self.contents.line_chars(self.cursor.y_pos);
+        let before = line[..self.cursor.x_pos.min(line.len())].iter().collect::<String>();
+        before.graphemes(true).count()
+    }
+    // Adds the status line under the text, filled in from the format in the config.
+    pub fn display_status(&self, area: &mut Area, state: &State, focused: bool) {
+        // other panes are left in command mode when the focus moves away from them.
+        let mode = if focused { state.mode } else { Mode::Command };
+        let timeout = Duration::from_millis(state.config.message_timeout);
+        let fields = [
+            ("path", self.path.clone()),
+            ("modified", if self.modified() { " [+]".to_string() } else { String::new() }),
+            ("mode", mode.name().to_string()),
+            ("line", (self.cursor.y_pos + 1).to_string()),
+            ("column", (self.grapheme_column() + 1).to_string()),
+            ("lines", self.contents.len_lines().to_string()),
+            ("line_ending", self.format.line_ending().to_string()),
+            ("encoding", self.format.encoding().to_string()),
+            ("syntax", self.highlighter.name().unwrap_or("text").to_string()),
+            ("message", self.status_message(timeout).unwrap_or_default().to_string()),
+        ];
+        let mut line = state.config.status_line.clone();
+        for (name, value) in fields {
+            line = line.replace(&format!("{{{}}}", name), &value);
+        }
+        let (left, right) = line.split_once("{=}").unwrap_or((&line, ""));
+        area.show_status(left, right, focused, &state.theme);
+    }
+    // Puts the cursor at the start of a line, or the last line if there aren't that many. Lines are counted from one,
+    // the way they're numbered on the screen, and zero goes to the first line too.
+    pub fn goto(&mut self, line: usize) {
+        self.cursor.highlight = None;
+        self.cursor.y_pos = line.saturating_sub(1).min(self.contents.len_lines() - 1);
+        self.cursor.x_pos = 0;
+    }
+    // Replaces the matches of a regular expression on some lines as one undo. Each line is given with the text it had
+    // when it was searched, and one that has changed since then is left alone. Returns how many matches were replaced.
+    pub fn replace_matches(&mut self, regex: &Regex, replacement: &str, lines: &[(usize, &str)]) -> usize {
+        let mut count = 0;
+        self.begin_group();
+        for (y, text) in lines.iter().rev() {
+            if *y >= self.contents.len_lines() || self.contents.line(*y) != *text {
+                continue;
+            }
+            let start = self.contents.line_to_char(*y);
+            let matches = replacements(regex, text, Some(replacement));
+            for (first, last, replaced) in matches.iter().rev() {
+                self.replace(start + first, start + last, replaced);
+            }
+            count += matches.len();
+        }
+        self.end_group();
+        self.set_valid_pos();
+        count
+    }
+    // The text a substitute works on: the selection, or the whole text if nothing is selected.
+    pub fn selected_range(&mut self, mode: Mode) -> Option<(usize, usize)> {
+        if let (Mode::VisualLine, Some((_, y))) = (mode, self.cursor.highlight) {
+            let (first, last) = (y.min(self.cursor.y_pos), y.max(self.cursor.y_pos));
+            return Some((self.contents.line_to_char(first), self.contents.line_to_char(last) + self.line_len(last)));
+        }
+        let (start, end) = self.highlight_range()?;
+        // the visual mode's selection takes in the character under the cursor.
+        if mode == Mode::Visual {
+            return Some((start, (end + 1).min(self.contents.len_chars())));
+        }
+        Some((start, end))
+    }
+    // Finds the matches of a substitute's pattern, then replaces them all at once as one undo, or starts asking about
+    // each of them in turn.
+    pub fn substitute(&mut self, substitute: Substitute, state: &mut State) {
+        let (start, end) = match self.selected_range(state.mode) {
+            Some(val) if !substitute.whole => val,
+            _ => (0, self.contents.len_chars()),
+        };
+        let regex = match build_regex(&substitute.pattern, substitute.ignore_case || state.config.ignore_case) {
+            Ok(val) => val,
+            Err(e) => {
+                state.message = Some(Message::Error(e));
+                return;
+            }
+        };
+        let text = self.contents.slice(start, end);
+        let matches = replacements(&regex, &text, Some(&substitute.replacement))
+            .into_iter()
+            .map(|(first, last, replaced)| (start + first, start + last, replaced))
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            state.message = Some(Message::Error(format!("Pattern not found: {}", substitute.pattern)));
+            return;
+        }
+        let count = matches.len();
+        if substitute.count_only {
+            state.message = Some(Message::Info(format!("{} matches", count)));
+            return;
+        }
+        self.switch_mode(Mode::Command, state);
+        self.cursor.highlight = None;
+        self.begin_group();
+        if substitute.confirm {
+            self.confirm = Some(Confirm {
+                matches,
+                current: 0,
+                shift: 0,
+                replaced: 0,
+            });
+            self.show_confirm();
+            return;
+        }
+        // replacing from the end back means the earlier matches don't move.
+        for (first, last, replaced) in matches.iter().rev() {
+            self.replace(*first, *last, replaced);
+        }
+        let (x, y) = self.position(matches[0].0);
+        self.cursor.x_pos = x;
+        self.cursor.y_pos = y;
+        self.end_group();
+        state.message = Some(Message::Info(format!("{} substitutions", count)));
+    }
+    // Handles a key while a substitute is asking about a match: y replaces it, n skips it, a replaces it and every one
+    // after it, and q or escape stops.
+    pub fn confirm_key(&mut self, k: KeyEvent, state: &mut State) {
+        let confirm = match &self.confirm {
+            Some(val) => val,
+            None => return,
+        };
+        let (replace, rest) = match k.code {
+            KeyCode::Char('y') => (true, false),
+            KeyCode::Char('n') => (false, false),
+            KeyCode::Char('a') => (true, true),
+            KeyCode::Char('q') | KeyCode::Esc => (false, true),
+            _ => return,
+        };
+        let count = if rest && replace { confirm.matches.len() - confirm.current } else { 1 };
+        let mut confirm = self.confirm.take().expect("Safe unwrap");
+        // the match is only selected to show it, so the history doesn't keep the selection.
+        self.cursor.highlight = None;
+        if replace {
+            for (first, last, replaced) in &confirm.matches[confirm.current..confirm.current + count] {
+                let first = (*first as isize + confirm.shift) as usize;
+                let last = (*last as isize + confirm.shift) as usize;
+                self.replace(first, last, replaced);
+                confirm.shift += replaced.chars().count() as isize - (last - first) as isize;
+                confirm.replaced += 1;
+            }
+        }
+        confirm.current += count;
+        if rest || confirm.current == confirm.matches.len() {
+            self.end_group();
+            state.message = Some(Message::Info(format!("{} substitutions", confirm.replaced)));
+        } else {
+            self.confirm = Some(confirm);
+            self.show_confirm();
+        }
+    }
+    // Selects the match a substitute is asking about.
+    pub fn show_confirm(&mut self) {
+        let (first, last) = match &self.confirm {
+            Some(confirm) => {
+                let (first, last, _) = &confirm.matches[confirm.current];
+                ((*first as isize + confirm.shift) as usize, (*last as isize + confirm.shift) as usize)
+            }
+            None => return,
+        };
+        let (x, y) = self.position(first);
+        self.cursor.x_pos = x;
+        self.cursor.y_pos = y;
+        self.cursor.highlight = Some(self.position(last));
+    }
+    // Writes the contents to a file - the textbox's own file if no path is given. Returns whether it worked.
+    // Writing to another file leaves the textbox's own file as it was.
+    pub fn write(&mut self, path: Option<String>, state: &mut State) -> bool {
+        // what's saved has to be a state in the history, so a block insert that's under way is finished first.
+        self.finish_block_insert();
+        let path = path.unwrap_or_else(|| self.path.clone());
+        // the file keeps the line endings and encoding it was read with.
+        let contents = self.format.encode(&self.contents.to_string());
+        if let Err(e) = std::fs::write(&path, &contents) {
+            state.message = Some(Message::Error(format!("Failed to save {}: {}", path, e)));
+            return false;
+        }
+        let message = if path == self.path {
+            self.disk_hash = content_hash(contents.as_bytes());
+            self.history.mark_saved();
+            self.save_history(&state.config);
+            format!("Saved {} lines", self.contents.len_lines())
+        } else {
+            format!("Wrote {} lines to {}", self.contents.len_lines(), path)
+        };
+        self.status_message = Some((message, Instant::now()));
+        true
+    }
+    // Calculates the position where the display starts printing: a line, and how many of its rows are skipped when
+    // it's wrapped. The cursor's row is kept in the middle of the screen when possible.
+    pub fn calculate_start(&mut self, height: usize) -> (usize, usize) {
+        if self.wrap_width.is_none() {
+            return (centered_start(self.cursor.y_pos, self.contents.len_lines(), height), 0);
+        }
+        let y = self.cursor.y_pos;
+        let row = self.cursor_column() / self.wrap_width.unwrap_or(1);
+        // the rows after the cursor's, up to a screenful.
+        let mut below = self.rows(y) - row - 1;
+        let mut line = y + 1;
+        while below < height && line < self.contents.len_lines() {
+            below += self.rows(line);
+            line += 1;
+        }
+        // there are as many rows above the cursor's as there are below it, unless the end of the text is near.
+        let mut above = (height / 2).max(height.saturating_sub(below + 1));
+        let (mut line, mut skip) = (y, row);
+        while above > 0 {
+            if skip > 0 {
+                let taken = skip.min(above);
+                skip -= taken;
+                above -= taken;
+            } else if line > 0 {
+                line -= 1;
+                skip = self.rows(line) - 1;
+                above -= 1;
+            } else {
+                break;
+            }
+        }
+        (line, skip)
+    }
+    // Draws the text into a pane. Only the focused pane shows the cursors, and whatever is drawn over the text.
+    pub fn display(&mut self, d: &mut DrawProcess, headers: &mut DrawProcess, state: &State, focused: bool) {
+        // the results of a project-wide search, and the undo tree browser, are drawn over the text while they're open.
+        if let Some(grep) = state.grep.as_ref().filter(|x| x.open && focused) {
+            grep.display(d, headers, &state.theme);
+        } else if let Some(browser) = self.browser.as_ref().filter(|_| focused) {
+            browser.display(&self.history, d, headers, &state.theme);
+        } else {
+            self.display_text(d, headers, state, focused);
+        }
+    }
+    // Adds whatever goes on the bottom line of the screen.
+    pub fn display_bottom(&mut self, screen: &mut Screen, state: &State) {
+        // the bottom line shows the command being typed, a message, or the mode and any operator waiting for a motion.
+        if state.command_line.open {
+            screen.show_command_line(&state.command_line, &self.substitute_preview(state), &state.theme);
+        } else if state.confirm_close {
+            let question = format!("\"{}\" has unsaved changes. Close it anyway? (y/n)", self.path);
+            screen.show_message(&Message::Info(question), &state.theme);
+        } else if let Some(question) = state.grep.as_ref().filter(|x| x.open).and_then(|x| x.question()) {
+            screen.show_message(&Message::Info(question), &state.theme);
+        } else if let Some(confirm) = &self.confirm {
+            let (.., replaced) = &confirm.matches[confirm.current];
+            let question = format!(
+                "replace with {} (y/n/a/q)? {} of {}",
+                replaced,
+                confirm.current + 1,
+                confirm.matches.len()
+            );
+            screen.show_message(&Message::Info(question), &state.theme);
+        } else if let Some(message) = &state.message {
+            screen.show_message(message, &state.theme);
+        } else {
+            let pending = self.operator.map(|x| x.name().to_string()).unwrap_or_default();
+            screen.show_mode(state.mode, &pending, &state.theme);
+        }
+    }
+    // How many matches the substitute being typed would replace, shown after it as it's typed.
+    pub fn substitute_preview(&mut self, state: &State) -> String {
+        if state.command_line.prompt != ':' {
+            return String::new();
+        }
+        let substitute = match Command::parse(&state.command_line.input.iter().collect::<String>()) {
+            Ok(Command::Substitute(val)) => val,
+            _ => return String::new(),
+        };
+        let (start, end) = match self.selected_range(state.mode) {
+            Some(val) if !substitute.whole => val,
+            _ => (0, self.contents.len_chars()),
+        };
+        match build_regex(&substitute.pattern, substitute.ignore_case || state.config.ignore_case) {
+            Ok(regex) => format!("  ({} matches)", regex.find_iter(&self.contents.slice(start, end)).count()),
+            Err(_) => String::new(),
+        }
+    }
+    // Adds the visible lines of text and their line numbers to the display.
+    pub fn display_text(&mut self, d: &mut DrawProcess, headers: &mut DrawProcess, state: &State, focused: bool) {
+        let mode = state.mode;
+        let width = d.width().max(1);
+        self.configure_tabs(&state.config);
+        let wrap = state.config.wrap;
+        let wrap_width = Some(width).filter(|_| wrap);
+        // moving up and down goes by the rows of the focused pane.
+        if focused {
+            self.wrap_width = wrap_width;
+        }
+        // the rows of this pane decide where it starts.
+        let saved = std::mem::replace(&mut self.wrap_width, wrap_width);
+        let (start, mut skip) = self.calculate_start(d.height());
+        self.wrap_width = saved;
+        self.scroll_to_cursor(width, state);
+        // the syntax is drawn first, then the matches over it, and then the cursors over both.
+        let mut spans = if state.config.syntax {
+            self.highlighter.spans(&self.contents, start, start + d.height(), &state.theme)
+        } else {
+            Vec::new()
+        };
+        spans.extend(self.match_spans(start, d.height(), state));
+        if focused {
+            spans.extend(self.cursor_spans(mode, &state.theme));
+        }
+        let block = if mode == Mode::VisualBlock && focused { self.block() } else { None };
+
+        // removes all extra spaces and inserts these formatting codes at the beginning/end:
+        let theme = &state.theme;
+        let mut main_strategy = ansi_truncate::AnsiTruncate::new(&theme.text, ansi::RESET);
+        let mut current_line_strategy = ansi_truncate::AnsiTruncate::new(&theme.current_line, ansi::RESET);
+        // the line each row shows, if it's the first row of it.
+        let mut rows = Vec::new();
+        // enumerates through the contents.
+        for i in start..self.contents.len_lines() {
+            if rows.len() >= d.height() {
+                break;
+            }
+            let mut line = self.contents.line_chars(i);
+            let line_start = self.contents.line_to_char(i);
+            // one more style than there are characters, for the cursor at the end of the line.
+            let mut styles = vec![None; line.len() + 1];
+            for &(first, last, style) in &spans {
+                for x in first.max(line_start)..last.min(line_start + line.len() + 1) {
+                    styles[x - line_start] = Some(style);
+                }
+            }
+            if let Some((_, _, left, right)) = block.filter(|(first, last, ..)| (*first..=*last).contains(&i)) {
+                let (left, right) = self.block_span(i, left, right);
+                // lines that don't reach the right edge of the rectangle are padded out, so it's drawn whole.
+                if line.len() < right {
+                    line.resize(right, ' ');
+                    styles.resize(right + 1, None);
+                }
+                for style in &mut styles[left..right] {
+                    *style = Some(theme.selection.as_str());
+                }
+            }
+            // tabs are spread out over the cells up to the next tab stop, and wide characters over two.
+            let (line, styles) = render::expand(&line, &styles, self.tab_width, wrap_width);
+            // a wrapped line is cut into rows as wide as the pane. Otherwise, it's cut off at the edge.
+            let pieces = if wrap { line.len() / width + 1 } else { 1 };
+            for piece in std::mem::take(&mut skip)..pieces {
+                if rows.len() >= d.height() {
+                    break;
+                }
+                let first = if wrap { piece * width } else { self.scroll };
+                let last = first + width;
+                let mut chars = render::cut(&line, first, last);
+                let mut styles = styles[first.min(styles.len())..last.min(styles.len())].to_vec();
+                // markers show where a line that isn't wrapped carries on past the edges.
+                if !wrap {
+                    if first > 0 && !line.is_empty() {
+                        clip_marker(&mut chars, &mut styles, 0, '<', &theme.clip_marker);
+                    }
+                    if line.len() > last {
+                        clip_marker(&mut chars, &mut styles, width - 1, '>', &theme.clip_marker);
+                    }
+                }
+                if i == self.cursor.y_pos && focused {
+                    let collected = styled_line(&chars, &styles, &theme.current_line);
+                    let _ = d.add_to_section(collected, &mut current_line_strategy, Alignment::Plus);
+                } else {
+                    let collected = styled_line(&chars, &styles, &theme.text);
+                    let _ = d.add_to_section(collected, &mut main_strategy, Alignment::Plus);
+                }
+                rows.push(Some(i).filter(|_| piece == 0));
+            }
+        }
+        // creates and prints the headers
+        self.print_headers(headers, &rows, theme);
+    }
+    // The stretches of the visible text that match the search being typed, or the last one entered.
+    pub fn match_spans<'a>(&self, start: usize, height: usize, state: &'a State) -> Vec<(usize, usize, &'a str)> {
+        let pattern = if self.search_origin.is_some() && state.command_line.open {
+            state.command_line.input.clone()
+        } else {
+            match &state.search {
+                Some(search) if state.highlight_search => search.pattern.chars().collect(),
+                _ => return Vec::new(),
+            }
+        };
+        // one character on either side is taken along, to tell whether the matches at the edges are whole words.
+        let end_line = (start + height).min(self.contents.len_lines()) - 1;
+        let first = self.contents.line_to_char(start).saturating_sub(1);
+        let last = (self.contents.line_to_char(end_line) + self.line_len(end_line) + 1).min(self.contents.len_chars());
+        let text = self.contents.slice(first, last).chars().collect::<Vec<_>>();
+        find_all(&text, &pattern, &state.config)
+            .into_iter()
+            .map(|(start, end)| (first + start, first + end, state.theme.search_match.as_str()))
+            .collect()
+    }
+    // The stretches of text that the cursors cover, as indexes into the contents along with how to draw them.
+    // A cursor without a selection covers the character under it.
+    pub fn cursor_spans<'a>(&self, mode: Mode, theme: &'a Theme) -> Vec<(usize, usize, &'a str)> {
+        let mut spans = Vec::new();
+        for cursor in self.cursors.iter().chain(std::iter::once(&self.cursor)) {
+            // a cursor partway through a grapheme is drawn on the whole of it.
+            let first = self.index(self.grapheme_start(cursor.x_pos, cursor.y_pos), cursor.y_pos);
+            let (x, y) = match cursor.highlight {
+                // the rectangle of the visual block mode is drawn separately.
+                Some(_) if mode == Mode::VisualBlock => continue,
+                Some(val) => val,
+                None => {
+                    spans.push((first, first + 1, theme.cursor.as_str()));
+                    continue;
+                }
+            };
+            let second = self.clamped_index(x, y);
+            let (start, end) = (first.min(second), first.max(second));
+            // the visual mode's selection takes in the character under the cursor, and the line-wise one takes in whole lines.
+            let span = match mode {
+                Mode::VisualLine => {
+                    let last = y.max(cursor.y_pos);
+                    let start = self.contents.line_to_char(y.min(cursor.y_pos));
+                    (start, self.contents.line_to_char(last) + self.line_len(last) + 1, theme.selection.as_str())
+                }
+                Mode::Visual => (start, end + 1, theme.selection.as_str()),
+                _ if start == end => (start, end + 1, theme.cursor.as_str()),
+                _ => (start, end, theme.selection.as_str()),
+            };
+            spans.push(span);
+        }
+        spans
+    }
+    // Scrolls the text sideways, if it has to, so the cursor is in view and a margin away from the edges of the pane.
+    // Wrapped lines are never scrolled.
+    pub fn scroll_to_cursor(&mut self, width: usize, state: &State) {
+        if state.config.wrap {
+            self.scroll = 0;
+            return;
+        }
+        let x = self.cursor_column();
+        let margin = state.config.side_margin.min(width.saturating_sub(1) / 2);
+        if x < self.scroll + margin {
+            self.scroll = x.saturating_sub(margin);
+        } else if x + margin >= self.scroll + width {
+            self.scroll = x + margin + 1 - width;
+        }
+    }
+    // Adds the line numbers next to the rows of text. Rows that carry on a wrapped line get a marker instead.
+    pub fn print_headers(&mut self, headers: &mut DrawProcess, rows: &[Option<usize>], theme: &Theme) {
+        // removes all extra spaces and inserts these formatting codes at the beginning/end:
+        let mut header_strategy = ansi_truncate::AnsiTruncate::new(&theme.gutter, ansi::RESET);
+        let mut wrap_strategy = ansi_truncate::AnsiTruncate::new(&theme.wrap_marker, ansi::RESET);
+
+        // removes all extra spaces and inserts these formatting codes at the beginning/end:
+        let mut eof_strategy = ansi_truncate::AnsiTruncate::new(&theme.filler, ansi::RESET);
+
+        // for all rows on the display...
+        for row in rows {
+            match row {
+                // print the number with the header_strategy format scheme
+                Some(i) => {
+                    let _ = headers.add_to_section(format!("{:-4} ", i + 1), &mut header_strategy, Alignment::Plus);
+                }
+                None => {
+                    let _ = headers.add_to_section("   ↪ ".to_string(), &mut wrap_strategy, Alignment::Plus);
+                }
+            }
+        }
+        // for all lines of exposed space in the display...
+        for _ in rows.len()..headers.height() {
+            // prints the character ~ with the eof_strategy format scheme
+            let _ = headers.add_to_section("   ~ ".to_string(), &mut eof_strategy, Alignment::Plus);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        command_line::CommandLine,
+        theme::{ColorDepth, ThemeFile},
+    };
+
+    fn textbox(text: &str) -> TextBox {
+        TextBox::new(Rope::from(text), "test.txt".to_string(), 0, FileFormat::default(), &Config::default())
+    }
+
+    pub fn state() -> State {
+        let config = Config::default();
+        State {
+            mode: Mode::Command,
+            will_quit: false,
+            theme: Theme::new(&ThemeFile::default(), ColorDepth::TrueColor),
+            config,
+            command_line: CommandLine::new(),
+            message: None,
+            search: None,
+            highlight_search: false,
+            grep: None,
+            request: None,
+            confirm_close: false,
+        }
+    }
+
+    fn at(textbox: &mut TextBox, x: usize, y: usize) {
+        textbox.cursor = Cursor { x_pos: x, y_pos: y, highlight: None };
+    }
+
+    #[test]
+    fn next_word() {
+        let mut textbox = textbox("one  two.three\n\n  four");
+        let mut stops = Vec::new();
+        for _ in 0..5 {
+            textbox.run_motion(Action::MoveNextWord);
+            stops.push((textbox.cursor.x_pos, textbox.cursor.y_pos));
+        }
+        // the empty line is a stop of its own, and the last one is the end of the text.
+        assert_eq!(stops, vec![(5, 0), (8, 0), (9, 0), (0, 1), (2, 2)]);
+        textbox.run_motion(Action::MoveNextWord);
+        assert_eq!((textbox.cursor.x_pos, textbox.cursor.y_pos), (6, 2));
+    }
+
+    #[test]
+    fn delete_word() {
+        let mut state = state();
+        let mut textbox = textbox("one two three\nfour");
+        textbox.apply_operator(Operator::Delete, Action::MoveNextWord, &mut state);
+        assert_eq!(textbox.contents.to_string(), "two three\nfour");
+        // the last word on a line leaves the newline alone.
+        at(&mut textbox, 4, 0);
+        textbox.apply_operator(Operator::Delete, Action::MoveNextWord, &mut state);
+        assert_eq!(textbox.contents.to_string(), "two \nfour");
+        // and so does the last word in the text.
+        at(&mut textbox, 0, 1);
+        textbox.apply_operator(Operator::Delete, Action::MoveNextWord, &mut state);
+        assert_eq!(textbox.contents.to_string(), "two \n");
+    }
+
+    #[test]
+    fn search_sees_edits() {
+        let mut state = state();
+        let mut textbox = textbox("one two one");
+        let pattern = "one".chars().collect::<Vec<_>>();
+        assert_eq!(textbox.find_next(&pattern, false, &state.config), Some((8, 2, 2, false)));
+        textbox.replace(8, 11, "six");
+        textbox.action();
+        assert_eq!(textbox.find_next(&pattern, false, &state.config), Some((0, 1, 1, true)));
+        textbox.run(Action::Undo, &mut state);
+        assert_eq!(textbox.find_next(&pattern, false, &state.config), Some((8, 2, 2, false)));
+    }
+
+    #[test]
+    fn replace_listed_matches() {
+        let mut state = state();
+        let mut textbox = textbox("foo \nbar\nfoo bar\nfoo bar");
+        let regex = build_regex(r"o\s+b", false).expect("Safe unwrap");
+        // the last line has changed since it was searched, so it's left alone.
+        let count = textbox.replace_matches(&regex, "O-B", &[(2, "foo bar"), (3, "foo  bar")]);
+        assert_eq!(count, 1);
+        assert_eq!(textbox.contents.to_string(), "foo \nbar\nfoO-Bar\nfoo bar");
+        textbox.run(Action::Undo, &mut state);
+        assert_eq!(textbox.contents.to_string(), "foo \nbar\nfoo bar\nfoo bar");
+    }
+
+    #[test]
+    fn goto_counts_from_one() {
+        let mut state = state();
+        let mut textbox = textbox("one\ntwo\nthree");
+        let mut lines = Vec::new();
+        for line in ["2", "1", "0", "3", "99"] {
+            textbox.execute(line, &mut state);
+            lines.push(textbox.cursor.y_pos);
+        }
+        assert_eq!(lines, vec![1, 0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn graphemes_move_and_delete_together() {
+        let mut state = state();
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let mut textbox = textbox(&format!("e\u{301}{}x", family));
+        let mut stops = Vec::new();
+        for _ in 0..3 {
+            textbox.run(Action::MoveRight, &mut state);
+            stops.push(textbox.cursor.x_pos);
+        }
+        assert_eq!(stops, vec![2, 7, 8]);
+        for _ in 0..3 {
+            textbox.run(Action::MoveLeft, &mut state);
+            stops.push(textbox.cursor.x_pos);
+        }
+        assert_eq!(stops[3..], [7, 2, 0]);
+        // deleting takes the whole grapheme, either way.
+        textbox.run(Action::DeleteForward, &mut state);
+        assert_eq!(textbox.contents.to_string(), format!("{}x", family));
+        at(&mut textbox, 5, 0);
+        textbox.run(Action::DeleteBackward, &mut state);
+        assert_eq!(textbox.contents.to_string(), "x");
+        assert_eq!(textbox.cursor.x_pos, 0);
+    }
+
+    #[test]
+    fn edits_are_highlighted_again() {
+        let state = state();
+        let comment = state.theme.scope("comment").expect("Safe unwrap");
+        let mut textbox = TextBox::new(Rope::from("a\nb"), "test.rs".to_string(), 0, FileFormat::default(), &state.config);
+        assert_eq!(textbox.highlighter.spans(&textbox.contents, 0, 2, &state.theme), vec![]);
+        textbox.replace(0, 0, "/*");
+        assert_eq!(textbox.highlighter.spans(&textbox.contents, 1, 2, &state.theme), vec![(4, 5, comment)]);
+    }
+
+    #[test]
+    fn undo_finishes_a_block_insert() {
+        let mut state = state();
+        let mut textbox = textbox("abc\ndef\nghi");
+        at(&mut textbox, 2, 2);
+        textbox.run(Action::Append, &mut state);
+        for c in "X ".chars() {
+            textbox.run(Action::InsertChar(c), &mut state);
+        }
+        textbox.run(Action::SwitchMode(Mode::Command), &mut state);
+        at(&mut textbox, 0, 0);
+        textbox.run(Action::SwitchMode(Mode::VisualBlock), &mut state);
+        textbox.run(Action::MoveDown, &mut state);
+        textbox.run(Action::BlockInsert, &mut state);
+        textbox.run(Action::InsertChar('Q'), &mut state);
+        // undoing in the middle of the block insert finishes it first, and then undoes all of it.
+        textbox.run(Action::Undo, &mut state);
+        assert_eq!(textbox.contents.to_string(), "abc\ndef\nghiX ");
+        textbox.run(Action::Undo, &mut state);
+        assert_eq!(textbox.contents.to_string(), "abc\ndef\nghi");
+        textbox.run(Action::Redo, &mut state);
+        textbox.run(Action::Redo, &mut state);
+        assert_eq!(textbox.contents.to_string(), "Qabc\nQdef\nghiX ");
+    }
+
+    #[test]
+    fn saving_finishes_a_block_insert() {
+        let mut state = state();
+        let path = std::env::temp_dir().join(format!("textedit-block-{}.txt", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let mut textbox = TextBox::new(Rope::from("abc\ndef"), path.clone(), 0, FileFormat::default(), &state.config);
+        textbox.run(Action::SwitchMode(Mode::VisualBlock), &mut state);
+        textbox.run(Action::MoveDown, &mut state);
+        textbox.run(Action::BlockInsert, &mut state);
+        textbox.run(Action::InsertChar('Q'), &mut state);
+        textbox.run(Action::Save, &mut state);
+        assert_eq!(std::fs::read_to_string(&path).expect("Safe unwrap"), "Qabc\nQdef");
+        assert!(!textbox.history.modified());
+        // the saved state is the one with the whole block insert in it.
+        textbox.run(Action::Undo, &mut state);
+        assert!(textbox.history.modified());
+        textbox.run(Action::Redo, &mut state);
+        assert!(!textbox.history.modified());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn steps_stop_at_the_ends_of_the_history() {
+        let mut state = state();
+        let mut textbox = textbox("");
+        textbox.insert_text("ab");
+        // these would take forever if every step were gone through.
+        textbox.execute(&format!("earlier {}", usize::MAX), &mut state);
+        assert_eq!(textbox.contents.to_string(), "");
+        textbox.execute(&format!("later {}", usize::MAX), &mut state);
+        assert_eq!(textbox.contents.to_string(), "ab");
+    }
+
+    #[test]
+    fn bad_settings_change_nothing() {
+        let mut state = state();
+        for line in ["set tabstop=8 nosuchoption", "set tabstop=8 colors=bogus"] {
+            textbox("").execute(line, &mut state);
+            assert!(matches!(state.message, Some(Message::Error(_))), "{}", line);
+            assert_eq!(state.config.tabstop, Config::default().tabstop);
+            assert_eq!(state.config.colors, Config::default().colors);
+        }
+        textbox("").execute("set tabstop=8 colors=16", &mut state);
+        assert_eq!((state.config.tabstop, state.config.colors.as_str()), (8, "16"));
+    }
+
+    // Where every cursor is, the main one included, from the start of the text.
+    fn positions(textbox: &TextBox) -> Vec<(usize, usize)> {
+        let mut positions = textbox.cursors.iter().chain([&textbox.cursor]).map(|x| (x.x_pos, x.y_pos)).collect::<Vec<_>>();
+        positions.sort_by_key(|(x, y)| (*y, *x));
+        positions
+    }
+
+    #[test]
+    fn cursors_edit_from_the_end_back() {
+        let mut state = state();
+        let mut textbox = textbox("one two\nthree");
+        textbox.cursors = vec![Cursor { x_pos: 3, y_pos: 0, highlight: None }];
+        at(&mut textbox, 7, 0);
+        textbox.run(Action::AddCursorBelow, &mut state);
+        // the edits at the later cursors don't move the earlier ones, and the earlier ones move the later ones along.
+        for c in "--".chars() {
+            textbox.run(Action::InsertChar(c), &mut state);
+        }
+        assert_eq!(textbox.contents.to_string(), "one-- two--\nthree--");
+        assert_eq!(positions(&textbox), vec![(5, 0), (11, 0), (7, 1)]);
+        textbox.run(Action::DeleteBackward, &mut state);
+        assert_eq!(textbox.contents.to_string(), "one- two-\nthree-");
+        assert_eq!(positions(&textbox), vec![(4, 0), (9, 0), (6, 1)]);
+        // each edit is one undo, however many cursors made it.
+        let mut undone = Vec::new();
+        for _ in 0..3 {
+            textbox.run(Action::Undo, &mut state);
+            undone.push(textbox.contents.to_string());
+        }
+        assert_eq!(undone, vec!["one-- two--\nthree--", "one- two-\nthree-", "one two\nthree"]);
+    }
+
+    #[test]
+    fn cursors_that_meet_are_merged() {
+        let mut state = state();
+        let mut textbox = textbox("abc");
+        textbox.cursors = vec![Cursor { x_pos: 1, y_pos: 0, highlight: None }, Cursor { x_pos: 1, y_pos: 0, highlight: None }];
+        at(&mut textbox, 2, 0);
+        textbox.merge_cursors();
+        assert_eq!(positions(&textbox), vec![(1, 0), (2, 0)]);
+        // deleting back to the start of the text puts both cursors in the same place.
+        textbox.run(Action::DeleteBackward, &mut state);
+        textbox.run(Action::DeleteBackward, &mut state);
+        assert_eq!(textbox.contents.to_string(), "c");
+        assert_eq!(positions(&textbox), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn overlapping_selections_are_merged() {
+        let mut textbox = textbox("one two");
+        textbox.cursors = vec![Cursor { x_pos: 0, y_pos: 0, highlight: Some((3, 0)) }];
+        textbox.cursor = Cursor { x_pos: 5, y_pos: 0, highlight: Some((2, 0)) };
+        textbox.merge_cursors();
+        // the merged selection covers both.
+        assert!(textbox.cursors.is_empty());
+        assert_eq!(textbox.highlight_range(), Some((0, 5)));
+    }
+
+    #[test]
+    fn cursors_at_next_matches() {
+        let mut state = state();
+        let mut textbox = textbox("foo bar\nfoo foo");
+        at(&mut textbox, 1, 1);
+        // the first time selects the word, and then each match after it gets a cursor, going around to the start.
+        let mut selections = Vec::new();
+        for _ in 0..4 {
+            textbox.run(Action::AddCursorAtNextMatch, &mut state);
+            selections.push(textbox.highlight_range());
+        }
+        assert_eq!(selections, vec![Some((8, 11)), Some((12, 15)), Some((0, 3)), Some((0, 3))]);
+        assert_eq!(textbox.cursors.len(), 2);
+        textbox.run(Action::InsertChar('x'), &mut state);
+        assert_eq!(textbox.contents.to_string(), "x bar\nx x");
+        textbox.run(Action::Undo, &mut state);
+        assert_eq!(textbox.contents.to_string(), "foo bar\nfoo foo");
+    }
+
+    // Selects a rectangle from one corner to the other, in the visual block mode.
+    fn select_block(textbox: &mut TextBox, state: &mut State, (x, y): (usize, usize), (x2, y2): (usize, usize)) {
+        state.mode = Mode::VisualBlock;
+        textbox.cursor = Cursor { x_pos: x2, y_pos: y2, highlight: Some((x, y)) };
+    }
+
+    #[test]
+    fn block_insert_and_append() {
+        let mut state = state();
+        let mut results = Vec::new();
+        for (action, x, x2) in [(Action::BlockInsert, 1, 2), (Action::BlockAppend, 1, 2), (Action::BlockInsert, 3, 3)] {
+            let mut textbox = textbox("abcd\nab\nabcd");
+            select_block(&mut textbox, &mut state, (x, 0), (x2, 2));
+            textbox.run(action, &mut state);
+            textbox.run(Action::InsertChar('X'), &mut state);
+            textbox.run(Action::SwitchMode(Mode::Command), &mut state);
+            results.push(textbox.contents.to_string());
+            textbox.run(Action::Undo, &mut state);
+            assert_eq!(textbox.contents.to_string(), "abcd\nab\nabcd");
+        }
+        // a short line is padded out to be appended to, and left alone when it doesn't reach where text is inserted.
+        assert_eq!(results, vec!["aXbcd\naXb\naXbcd", "abcXd\nab X\nabcXd", "abcXd\nab\nabcXd"]);
+    }
+
+    #[test]
+    fn blocks_go_by_columns() {
+        let mut state = state();
+        let mut textbox = textbox("a\tb\n日本語\nabcdefgh");
+        // the tab on the first line takes up the columns from one to three, so the rectangle goes from one to three on
+        // every line, and the wide character that's partly inside it is taken in whole.
+        select_block(&mut textbox, &mut state, (3, 2), (1, 0));
+        assert_eq!(textbox.block(), Some((0, 2, 1, 4)));
+        textbox.run(Action::Operator(Operator::Yank), &mut state);
+        assert_eq!(textbox.block_yank.as_deref(), Some("\t\n日本\nbcd"));
+        select_block(&mut textbox, &mut state, (4, 2), (2, 0));
+        textbox.run(Action::Operator(Operator::Delete), &mut state);
+        assert_eq!(textbox.contents.to_string(), "a\t\n日本\nabcdfgh");
+        // the end of a line takes up a column too, and a line that ends before the right edge is padded out to it.
+        select_block(&mut textbox, &mut state, (4, 2), (2, 1));
+        textbox.run(Action::BlockAppend, &mut state);
+        textbox.run(Action::InsertChar('X'), &mut state);
+        textbox.run(Action::SwitchMode(Mode::Command), &mut state);
+        assert_eq!(textbox.contents.to_string(), "a\t\n日本 X\nabcdfXgh");
+    }
+
+    #[test]
+    fn operators() {
+        let mut textbox = textbox("Hello World\nfoo");
+        textbox.operate(Operator::ToggleCase, 0, 5);
+        assert_eq!(textbox.contents.to_string(), "hELLO World\nfoo");
+        textbox.operate(Operator::Uppercase, 6, 15);
+        assert_eq!(textbox.contents.to_string(), "hELLO WORLD\nFOO");
+        textbox.operate(Operator::Lowercase, 0, 11);
+        assert_eq!(textbox.contents.to_string(), "hello world\nFOO");
+        // the cursor is left at the start of what was acted on.
+        textbox.operate(Operator::Delete, 5, 13);
+        assert_eq!(textbox.contents.to_string(), "helloOO");
+        assert_eq!((textbox.cursor.x_pos, textbox.cursor.y_pos), (5, 0));
+    }
+
+    #[test]
+    fn line_operators() {
+        let mut state = state();
+        let mut textbox = textbox("a\n  b\nc");
+        textbox.operate_lines(Operator::Uppercase, 1, 2);
+        assert_eq!(textbox.contents.to_string(), "a\n  B\nC");
+        textbox.operate_lines(Operator::Outdent, 0, 1);
+        assert_eq!(textbox.contents.to_string(), "a\nB\nC");
+        textbox.operate_lines(Operator::Delete, 0, 1);
+        assert_eq!(textbox.contents.to_string(), "C");
+        textbox.run(Action::Undo, &mut state);
+        assert_eq!(textbox.contents.to_string(), "a\nB\nC");
+        // giving an operator twice acts on the cursor's line.
+        at(&mut textbox, 0, 2);
+        textbox.run(Action::Operator(Operator::Delete), &mut state);
+        textbox.run(Action::Operator(Operator::Delete), &mut state);
+        assert_eq!(textbox.contents.to_string(), "a\nB");
+        // and moving up or down acts on the lines in between.
+        at(&mut textbox, 0, 1);
+        textbox.apply_operator(Operator::Indent, Action::MoveUp, &mut state);
+        assert_eq!(textbox.contents.to_string(), "\ta\n\tB");
+        // indenting takes in the whole of every line it touches, wherever it starts and ends.
+        textbox.operate(Operator::Outdent, 2, 4);
+        assert_eq!(textbox.contents.to_string(), "a\nB");
+    }
+
+    #[test]
+    fn visual_operators() {
+        let mut state = state();
+        let mut textbox = textbox("one two\nthree");
+        // the visual mode takes in the character under the cursor, whichever way the selection goes.
+        state.mode = Mode::Visual;
+        textbox.cursor = Cursor { x_pos: 4, y_pos: 0, highlight: Some((6, 0)) };
+        textbox.run(Action::Operator(Operator::Uppercase), &mut state);
+        assert_eq!(textbox.contents.to_string(), "one TWO\nthree");
+        // the visual line mode takes in whole lines.
+        state.mode = Mode::VisualLine;
+        textbox.cursor = Cursor { x_pos: 2, y_pos: 1, highlight: Some((5, 0)) };
+        textbox.run(Action::Operator(Operator::ToggleCase), &mut state);
+        assert_eq!(textbox.contents.to_string(), "ONE two\nTHREE");
+        // without a selection, the operator waits for a motion.
+        state.mode = Mode::Command;
+        at(&mut textbox, 4, 0);
+        textbox.run(Action::Operator(Operator::Delete), &mut state);
+        textbox.run(Action::MoveLineEnd, &mut state);
+        assert_eq!(textbox.contents.to_string(), "ONE \nTHREE");
+    }
+
+    #[test]
+    fn moving_across_wrapped_rows() {
+        let mut textbox = textbox("abcdefghij\nxy\n日本語");
+        textbox.wrap_width = Some(4);
+        at(&mut textbox, 1, 0);
+        let mut stops = Vec::new();
+        for _ in 0..4 {
+            textbox.move_down();
+            stops.push((textbox.cursor.x_pos, textbox.cursor.y_pos));
+        }
+        // the column in the row is kept, even past the end of a short line.
+        assert_eq!(stops, vec![(5, 0), (9, 0), (1, 1), (0, 2)]);
+        // a wide character that doesn't fit at the end of a row starts the next one.
+        assert_eq!(textbox.rows(2), 2);
+        textbox.move_down();
+        assert_eq!((textbox.cursor.x_pos, textbox.cursor.y_pos), (2, 2));
+        for _ in 0..3 {
+            textbox.move_up();
+            stops.push((textbox.cursor.x_pos, textbox.cursor.y_pos));
+        }
+        assert_eq!(stops[4..], [(0, 2), (0, 1), (8, 0)]);
+    }
+
+    #[test]
+    fn wrapped_lines_keep_the_cursor_in_view() {
+        let mut textbox = textbox(&["abcdefghij"; 10].join("\n"));
+        textbox.wrap_width = Some(4);
+        // each line takes up three rows, and the cursor's row goes in the middle.
+        at(&mut textbox, 9, 5);
+        assert_eq!(textbox.calculate_start(5), (5, 0));
+        at(&mut textbox, 0, 5);
+        assert_eq!(textbox.calculate_start(5), (4, 1));
+        // near the end of the text, the screen is filled up from above.
+        at(&mut textbox, 9, 9);
+        assert_eq!(textbox.calculate_start(5), (8, 1));
+        // the rows of a line longer than the screen are skipped until the cursor's is in view.
+        let mut textbox = TextBox::new(Rope::from("x".repeat(50).as_str()), String::new(), 0, FileFormat::default(), &Config::default());
+        textbox.wrap_width = Some(10);
+        at(&mut textbox, 45, 0);
+        assert_eq!(textbox.calculate_start(3), (0, 3));
+    }
+
+    #[test]
+    fn scrolling_sideways() {
+        let mut state = state();
+        state.config.side_margin = 2;
+        let mut textbox = textbox(&"x".repeat(30));
+        let mut scrolls = Vec::new();
+        for x in [25, 20, 18, 0, 30] {
+            at(&mut textbox, x, 0);
+            textbox.scroll_to_cursor(10, &state);
+            scrolls.push(textbox.scroll);
+        }
+        // the cursor stays a margin away from the edges, and the line only scrolls as far as it has to.
+        assert_eq!(scrolls, vec![18, 18, 16, 0, 23]);
+        state.config.wrap = true;
+        textbox.scroll_to_cursor(10, &state);
+        assert_eq!(textbox.scroll, 0);
+    }
 
-        // prints the header drawprocess out to the terminal.
-        headers
-            .print(&mut CrosstermHandler, &mut stdout())
-            .expect("Error queueing display instructions");
+    #[test]
+    fn clip_markers() {
+        let cells = |text: &str| text.chars().map(|x| if x == '_' { String::new() } else { x.to_string() }).collect::<Vec<_>>();
+        let (mut chars, mut styles) = (cells("abc"), vec![Some("cursor")]);
+        clip_marker(&mut chars, &mut styles, 0, '<', "clip");
+        clip_marker(&mut chars, &mut styles, 2, '>', "clip");
+        // the cursor is drawn over the marker.
+        assert_eq!((chars, styles), (cells("<b>"), vec![Some("cursor"), None, Some("clip")]));
+        // a wide character that's cut in half becomes a space.
+        let (mut chars, mut styles) = (cells("日_本_"), Vec::new());
+        clip_marker(&mut chars, &mut styles, 0, '<', "clip");
+        clip_marker(&mut chars, &mut styles, 3, '>', "clip");
+        assert_eq!(chars, cells("<  >"));
+        // and a short row is padded out to the edge.
+        let (mut chars, mut styles) = (cells("a"), Vec::new());
+        clip_marker(&mut chars, &mut styles, 3, '>', "clip");
+        assert_eq!(chars, cells("a  >"));
     }
 }
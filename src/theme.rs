@@ -0,0 +1,358 @@
+use std::{collections::HashMap, env, fs};
+
+use serde::Deserialize;
+
+use crate::{ansi, config::Config};
+
+// The sixteen basic colors, as most terminals show them. Colors that the terminal can't show are swapped for the closest
+// of these, or of the 256 colors.
+const BASIC: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+// How many colors the terminal can show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorDepth {
+    Basic,
+    Indexed,
+    TrueColor,
+}
+impl ColorDepth {
+    // Works out how many colors the terminal can show, from what it says about itself.
+    pub fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Indexed
+        } else {
+            ColorDepth::Basic
+        }
+    }
+    // Reads the colors setting: "16", "256", "truecolor", or "auto" to ask the terminal.
+    pub fn parse(name: &str) -> Result<ColorDepth, String> {
+        match name {
+            "auto" => Ok(ColorDepth::detect()),
+            "16" => Ok(ColorDepth::Basic),
+            "256" => Ok(ColorDepth::Indexed),
+            "truecolor" => Ok(ColorDepth::TrueColor),
+            _ => Err(format!("Unknown color depth: {}", name)),
+        }
+    }
+}
+// A color, written in a theme file as the name of one of the sixteen basic colors (like "red" or "bright_red"), a number
+// from the 256 colors, or "#rrggbb".
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "ColorFile")]
+pub enum Color {
+    Basic(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorFile {
+    Indexed(u8),
+    Text(String),
+}
+impl TryFrom<ColorFile> for Color {
+    type Error = String;
+    fn try_from(value: ColorFile) -> Result<Color, String> {
+        let text = match value {
+            ColorFile::Indexed(val) => return Ok(Color::Indexed(val)),
+            ColorFile::Text(val) => val,
+        };
+        if let Some(hex) = text.strip_prefix('#').filter(|x| x.len() == 6) {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("Invalid color: {}", text));
+            return Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        let (bright, name) = match text.strip_prefix("bright_") {
+            Some(val) => (8, val),
+            None => (0, text.as_str()),
+        };
+        let i = NAMES.iter().position(|x| *x == name).ok_or_else(|| format!("Invalid color: {}", text))?;
+        Ok(Color::Basic(i as u8 + bright))
+    }
+}
+impl Color {
+    // The red, green and blue of the color.
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Basic(i) => BASIC[i as usize % 16],
+            Color::Indexed(i) if i < 16 => BASIC[i as usize],
+            // a 6x6x6 cube of colors, and then a ramp of grays.
+            Color::Indexed(i) if i < 232 => {
+                let level = |x: u8| if x == 0 { 0 } else { 55 + x * 40 };
+                let i = i - 16;
+                (level(i / 36), level(i / 6 % 6), level(i % 6))
+            }
+            Color::Indexed(i) => {
+                let gray = 8 + (i - 232) * 10;
+                (gray, gray, gray)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+    // The closest color to this one that the terminal can show.
+    pub fn downgrade(self, depth: ColorDepth) -> Color {
+        let closest = |colors: std::ops::Range<u16>| {
+            let (r, g, b) = self.rgb();
+            colors
+                .min_by_key(|x| {
+                    let (r2, g2, b2) = Color::Indexed(*x as u8).rgb();
+                    [(r, r2), (g, g2), (b, b2)].iter().map(|(x, y)| (*x as i32 - *y as i32).pow(2)).sum::<i32>()
+                })
+                .expect("Safe unwrap") as u8
+        };
+        match (self, depth) {
+            (Color::Rgb(..), ColorDepth::Indexed) => Color::Indexed(closest(16..256)),
+            (Color::Indexed(i), ColorDepth::Basic) if i < 16 => Color::Basic(i),
+            (Color::Indexed(_) | Color::Rgb(..), ColorDepth::Basic) => Color::Basic(closest(0..16)),
+            _ => self,
+        }
+    }
+    // The escape code parameters that set the color, as the text color or the background.
+    fn parameters(self, background: bool) -> String {
+        let (basic, extended) = if background { (40, 48) } else { (30, 38) };
+        match self {
+            Color::Basic(i) if i < 8 => (basic + i).to_string(),
+            Color::Basic(i) => (basic + 60 + i - 8).to_string(),
+            Color::Indexed(i) => format!("{};5;{}", extended, i),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", extended, r, g, b),
+        }
+    }
+}
+// How something is drawn: its colors, and whether it's bold, dim, italic or underlined.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+impl Style {
+    fn fg(color: Color) -> Style {
+        Style { fg: Some(color), ..Style::default() }
+    }
+    fn bg(color: Color) -> Style {
+        Style { bg: Some(color), ..Style::default() }
+    }
+    // The escape code that turns the style on, with the colors brought down to what the terminal can show. A style that
+    // doesn't change anything is left empty.
+    pub fn escape(&self, depth: ColorDepth) -> String {
+        let mut parameters = Vec::new();
+        for (on, parameter) in [(self.bold, "1"), (self.dim, "2"), (self.italic, "3"), (self.underline, "4")] {
+            if on {
+                parameters.push(parameter.to_string());
+            }
+        }
+        parameters.extend(self.fg.map(|x| x.downgrade(depth).parameters(false)));
+        parameters.extend(self.bg.map(|x| x.downgrade(depth).parameters(true)));
+        if parameters.is_empty() {
+            String::new()
+        } else {
+            format!("\u{1b}[{}m", parameters.join(";"))
+        }
+    }
+}
+// A theme as it's written in its file. Anything missing from the file is drawn the default way.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ThemeFile {
+    // the text, and the line the cursor is on.
+    pub text: Style,
+    pub current_line: Style,
+    // a cursor without a selection, selections, and the matches of a search.
+    pub cursor: Style,
+    pub selection: Style,
+    pub search_match: Style,
+    // the line numbers, the marker for rows that carry on a wrapped line, and the ~ after the end of the text.
+    pub gutter: Style,
+    pub wrap_marker: Style,
+    pub filler: Style,
+    // the < and > where a line is cut off.
+    pub clip_marker: Style,
+    // the status lines of the focused pane and the others.
+    pub status: Style,
+    pub status_inactive: Style,
+    // the tab bar, and the current tab's label in it.
+    pub tab_bar: Style,
+    pub tab_current: Style,
+    // the lines between panes.
+    pub divider: Style,
+    // the bottom line: the mode, errors, and the help at the top of lists.
+    pub mode: Style,
+    pub error: Style,
+    pub help: Style,
+    // how each scope of a grammar is drawn.
+    pub syntax: HashMap<String, Style>,
+}
+impl Default for ThemeFile {
+    fn default() -> Self {
+        let bold = Style { bold: true, ..Style::default() };
+        let dim = Style { dim: true, ..Style::default() };
+        let syntax = [
+            ("comment", dim.clone()),
+            ("string", Style::fg(Color::Basic(2))),
+            ("code", Style::fg(Color::Basic(2))),
+            ("keyword", Style::fg(Color::Basic(5))),
+            ("number", Style::fg(Color::Basic(3))),
+            ("constant", Style::fg(Color::Basic(3))),
+            ("label", Style::fg(Color::Basic(3))),
+            ("attribute", Style::fg(Color::Basic(3))),
+            ("type", Style::fg(Color::Basic(6))),
+            ("variable", Style::fg(Color::Basic(6))),
+            ("function", Style::fg(Color::Basic(4))),
+            ("key", Style::fg(Color::Basic(4))),
+            ("heading", bold.clone()),
+            ("strong", bold.clone()),
+            ("emphasis", Style { italic: true, ..Style::default() }),
+            ("link", Style { underline: true, ..Style::default() }),
+        ];
+        ThemeFile {
+            text: Style::default(),
+            current_line: Style::bg(Color::Indexed(235)),
+            cursor: Style::bg(Color::Indexed(239)),
+            selection: Style::bg(Color::Basic(6)),
+            search_match: Style { fg: Some(Color::Basic(0)), bg: Some(Color::Indexed(179)), ..Style::default() },
+            gutter: Style::fg(Color::Basic(2)),
+            wrap_marker: dim.clone(),
+            filler: Style::fg(Color::Basic(6)),
+            clip_marker: Style::fg(Color::Basic(6)),
+            status: Style::bg(Color::Indexed(239)),
+            status_inactive: Style::bg(Color::Indexed(235)),
+            tab_bar: Style::bg(Color::Indexed(235)),
+            tab_current: Style { bold: true, ..Style::bg(Color::Indexed(239)) },
+            divider: dim,
+            mode: bold,
+            error: Style::fg(Color::Basic(1)),
+            help: Style::fg(Color::Basic(6)),
+            syntax: syntax.into_iter().map(|(x, y)| (x.to_string(), y)).collect(),
+        }
+    }
+}
+// The escape codes for everything on the screen, worked out from a theme file for the terminal's colors.
+pub struct Theme {
+    pub text: String,
+    pub current_line: String,
+    pub cursor: String,
+    pub selection: String,
+    pub search_match: String,
+    pub gutter: String,
+    pub wrap_marker: String,
+    pub filler: String,
+    pub clip_marker: String,
+    pub status: String,
+    pub status_inactive: String,
+    pub tab_bar: String,
+    pub tab_current: String,
+    pub divider: String,
+    pub mode: String,
+    pub error: String,
+    pub help: String,
+    pub syntax: HashMap<String, String>,
+}
+impl Theme {
+    pub fn new(file: &ThemeFile, depth: ColorDepth) -> Theme {
+        // the text's style is what everything else goes back to, so it starts by clearing whatever came before it. The
+        // current line is drawn over it.
+        let text = format!("{}{}", ansi::RESET, file.text.escape(depth));
+        Theme {
+            current_line: format!("{}{}", text, file.current_line.escape(depth)),
+            text,
+            cursor: file.cursor.escape(depth),
+            selection: file.selection.escape(depth),
+            search_match: file.search_match.escape(depth),
+            gutter: file.gutter.escape(depth),
+            wrap_marker: file.wrap_marker.escape(depth),
+            filler: file.filler.escape(depth),
+            clip_marker: file.clip_marker.escape(depth),
+            status: file.status.escape(depth),
+            status_inactive: file.status_inactive.escape(depth),
+            tab_bar: file.tab_bar.escape(depth),
+            tab_current: file.tab_current.escape(depth),
+            divider: file.divider.escape(depth),
+            mode: file.mode.escape(depth),
+            error: file.error.escape(depth),
+            help: file.help.escape(depth),
+            syntax: file.syntax.iter().map(|(x, y)| (x.clone(), y.escape(depth))).collect(),
+        }
+    }
+    // Reads the theme file named in the config, falling back on the default theme if there isn't one.
+    pub fn load(config: &Config) -> Result<Theme, String> {
+        let depth = ColorDepth::parse(&config.colors)?;
+        let file = match fs::read_to_string(&config.theme) {
+            Ok(val) => serde_json::from_str(&val).map_err(|e| format!("Invalid theme file: {}", e))?,
+            Err(_) => ThemeFile::default(),
+        };
+        Ok(Theme::new(&file, depth))
+    }
+    // How a scope of a grammar is drawn, if the theme styles it.
+    pub fn scope(&self, scope: &str) -> Option<&str> {
+        self.syntax.get(scope).map(|x| x.as_str()).filter(|x| !x.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(json: &str) -> Result<Color, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(color(r#""red""#).expect("Safe unwrap"), Color::Basic(1));
+        assert_eq!(color(r#""bright_white""#).expect("Safe unwrap"), Color::Basic(15));
+        assert_eq!(color(r##""#ff8000""##).expect("Safe unwrap"), Color::Rgb(255, 128, 0));
+        assert_eq!(color("42").expect("Safe unwrap"), Color::Indexed(42));
+        for bad in [r#""purple""#, r#""bright_""#, r##""#12345""##, r##""#gg0000""##, "256", "-1"] {
+            assert!(color(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn downgrading() {
+        let red = Color::Rgb(255, 0, 0);
+        assert_eq!(red.downgrade(ColorDepth::TrueColor), red);
+        assert_eq!(red.downgrade(ColorDepth::Indexed), Color::Indexed(196));
+        assert_eq!(red.downgrade(ColorDepth::Basic), Color::Basic(9));
+        // a gray goes to the ramp of grays rather than the cube, and then to the basic gray.
+        assert_eq!(Color::Rgb(128, 128, 128).downgrade(ColorDepth::Indexed), Color::Indexed(244));
+        assert_eq!(Color::Indexed(244).downgrade(ColorDepth::Basic), Color::Basic(8));
+        // the first sixteen of the 256 colors are the basic ones, and basic colors are shown everywhere.
+        assert_eq!(Color::Indexed(3).downgrade(ColorDepth::Basic), Color::Basic(3));
+        assert_eq!(Color::Basic(12).downgrade(ColorDepth::Indexed), Color::Basic(12));
+    }
+
+    #[test]
+    fn escapes() {
+        let style = Style { fg: Some(Color::Rgb(255, 0, 0)), bg: Some(Color::Basic(12)), bold: true, ..Style::default() };
+        assert_eq!(style.escape(ColorDepth::TrueColor), "\u{1b}[1;38;2;255;0;0;104m");
+        assert_eq!(style.escape(ColorDepth::Indexed), "\u{1b}[1;38;5;196;104m");
+        assert_eq!(style.escape(ColorDepth::Basic), "\u{1b}[1;91;104m");
+        assert_eq!(Style::bg(Color::Indexed(239)).escape(ColorDepth::Indexed), "\u{1b}[48;5;239m");
+        assert_eq!(Style::default().escape(ColorDepth::TrueColor), "");
+    }
+}